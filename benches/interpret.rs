@@ -0,0 +1,54 @@
+//! Benchmarks for the interpreter.
+//!
+//! Run with `cargo bench`. Each benchmark runs a multiplier to completion for a few input sizes,
+//! using only the public API of the crate.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use minsky::arith;
+use minsky::m3_parser;
+use minsky::magnificent::{interpret, Machine};
+use minsky::marvellous;
+
+const SIZES: [i32; 3] = [10, 30, 100];
+
+fn bench_mult(c: &mut Criterion) {
+    c.bench_function("mult 100 100", |b| {
+        b.iter(|| arith::mult(black_box(100), black_box(100)))
+    });
+}
+
+fn bench_6_rule_mult(c: &mut Criterion) {
+    let program = m3_parser::read_program("examples/6-rule-mult.m3");
+    let mut group = c.benchmark_group("6-rule mult");
+    for n in SIZES {
+        let machine = Machine::new(0, vec![0, n, n, 0]);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &machine, |b, machine| {
+            b.iter(|| interpret(machine.clone(), &program, u64::MAX).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_marvellous_mult(c: &mut Criterion) {
+    let orig = arith::mult_program();
+    let (program, state_map) = marvellous::transpile_with_state_map(&orig);
+    let mut group = c.benchmark_group("marvellous mult");
+    for n in SIZES {
+        let orig_machine = Machine::new(0, vec![0, n, 0, n - 1]);
+        let machine =
+            marvellous::lift_machine(&orig_machine, &state_map, orig.num_tapes(), state_map.len());
+        group.bench_with_input(BenchmarkId::from_parameter(n), &machine, |b, machine| {
+            b.iter(|| interpret(machine.clone(), &program, u64::MAX).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_mult,
+    bench_6_rule_mult,
+    bench_marvellous_mult
+);
+criterion_main!(benches);
@@ -2,24 +2,81 @@
 //!
 //! This module demonstrates some basic Magnificent Minsky Machines that perform arithmetic.
 
-use crate::magnificent::{interpret, Machine, Program, Rule};
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+
+use crate::magnificent::{interpret, Machine, Program};
+use crate::program;
+
+/// Construct the adder program.
+///
+/// Inputs are placed in tapes 0 and 1, and the sum is left in tape 0.
+pub fn adder_program() -> Program {
+    program! {
+        tapes: 2;
+        0 => 0 [1, -1];
+    }
+}
 
 /// Construct a Magnificent Minsky Machine that adds two non-negative integers and run it on the
 /// inputs.
 pub fn adder(x: i32, y: i32) -> i32 {
+    adder_steps(x, y).0
+}
+
+/// Add two non-negative integers like [`adder`], also returning the number of steps taken.
+///
+/// The adder takes exactly `y` steps.
+pub fn adder_steps(x: i32, y: i32) -> (i32, u64) {
     assert!(x >= 0 && y >= 0);
-    let rule = Rule::new(0, 0, vec![1, -1]);
-    let program = Program::new(2, vec![rule]);
+    let program = adder_program();
     let machine = Machine::new(0, vec![x, y]);
 
     // machine transitions:
     //   - rule will fire y times, moving tape 0 to x+y and tape 1 to 0
     let end_machine = interpret(machine, &program, 2 * y as u64);
     assert!(end_machine.is_ok());
-    let (_, end_machine) = end_machine.unwrap();
+    let (steps, end_machine, _) = end_machine.unwrap();
+    (end_machine.tape_pos(0), steps)
+}
+
+/// Construct the truncated subtraction program.
+///
+/// The minuend is placed in tape 0 and the subtrahend in tape 1. Both tapes are decremented
+/// together until one of them reaches zero, leaving `max(x - y, 0)` in tape 0.
+pub fn monus_program() -> Program {
+    program! {
+        tapes: 2;
+        0 => 0 [-1, -1];
+    }
+}
+
+/// Construct a Magnificent Minsky Machine that computes the truncated difference `max(x - y, 0)`
+/// of two non-negative integers and run it on the inputs.
+pub fn monus(x: i32, y: i32) -> i32 {
+    assert!(x >= 0 && y >= 0);
+    let program = monus_program();
+    let machine = Machine::new(0, vec![x, y]);
+
+    // machine transitions:
+    //   - rule will fire min(x, y) times, moving tape 0 to max(x-y, 0)
+    let end_machine = interpret(machine, &program, 2 * y as u64);
+    assert!(end_machine.is_ok());
+    let (_, end_machine, _) = end_machine.unwrap();
     end_machine.tape_pos(0)
 }
 
+/// Construct the basic multiplier program, see [`mult`] for the tape layout.
+pub fn mult_program() -> Program {
+    program! {
+        tapes: 4;
+        0 => 0 [1, -1, 1, 0];
+        0 => 1 [0, 0, 0, 0];
+        1 => 1 [0, 1, -1, 0];
+        1 => 0 [0, 0, 0, -1];
+    }
+}
+
 /// Basic multiplier machine
 ///
 /// Initial machine state:
@@ -41,22 +98,94 @@ pub fn adder(x: i32, y: i32) -> i32 {
 /// 1: x*y x   0   0   --> HALT
 ///
 pub fn mult(x: i32, y: i32) -> i32 {
-    let rule0 = Rule::new(0, 0, vec![1, -1, 1, 0]);
-    let rule1 = Rule::new(0, 1, vec![0, 0, 0, 0]);
-    let rule2 = Rule::new(1, 1, vec![0, 1, -1, 0]);
-    let rule3 = Rule::new(1, 0, vec![0, 0, 0, -1]);
-    let program = Program::new(4, vec![rule0, rule1, rule2, rule3]);
-    let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+    mult_steps(x, y).0
+}
+
+/// Multiply like [`mult`], also returning the number of steps taken.
+///
+/// Each of the `y` rounds takes `2x + 2` steps, except the last which halts before resetting
+/// the state, so the multiplier takes `y(2x + 2) - 1` steps.
+pub fn mult_steps(x: i32, y: i32) -> (i32, u64) {
+    let program = mult_program();
+    let machine = Machine::try_new(0, vec![0, x, 0, y - 1]).expect("mult requires y >= 1");
 
     let end_machine = interpret(machine, &program, (2 * (x + 1) * y) as u64);
     assert!(end_machine.is_ok());
-    let (_, end_machine) = end_machine.unwrap();
+    let (steps, end_machine, _) = end_machine.unwrap();
+    (end_machine.tape_pos(0), steps)
+}
+
+/// Construct the exponentiation program, see [`power`] for the tape layout.
+pub fn power_program() -> Program {
+    program! {
+        tapes: 5;
+        // outer loop, one multiplication per unit of exp
+        0 => 1 [0, 0, -1, 0, 0];
+        // consume a unit of acc, or move the product back to acc once acc is empty
+        1 => 2 [-1, 0, 0, 0, 0];
+        1 => 4 [0, 0, 0, 0, 0];
+        // add base to the product, saving base in tmp
+        2 => 2 [0, -1, 0, 1, 1];
+        2 => 3 [0, 0, 0, 0, 0];
+        // restore base from tmp
+        3 => 3 [0, 1, 0, 0, -1];
+        3 => 1 [0, 0, 0, 0, 0];
+        // move the product to acc
+        4 => 4 [1, 0, 0, -1, 0];
+        4 => 0 [0, 0, 0, 0, 0];
+    }
+}
+
+/// Number of steps [`power_program`] takes to compute `base^exp`
+fn power_steps(base: u64, exp: u32) -> u64 {
+    (0..exp).map(|k| 3 + base.pow(k) * (3 * base + 3)).sum()
+}
+
+/// Exponentiation machine, computing `base^exp` by repeated multiplication
+///
+/// Tapes: acc, base, exp, prod, tmp
+///
+/// Initial machine state:
+///
+/// 0: 1   base exp 0   0
+///
+/// Each time the machine is in state 0 it consumes one unit of exp and multiplies acc by base,
+/// leaving the result in acc:
+///
+/// 0: a   b    e   0   0   --> rule0 fires st' = 1
+/// 1: a   b    e-1 0   0   --> rule1 consumes a unit of acc st' = 2
+/// 2: a-1 b    e-1 0   0   --> rule3 moves base to prod and tmp
+/// 2: ...                  --> ...
+/// 2: a-1 0    e-1 b   b   --> rule3 doesn't apply, rule4 fires st' = 3
+/// 3: a-1 0    e-1 b   b   --> rule5 moves tmp back to base
+/// 3: ...                  --> ...
+/// 3: a-1 b    e-1 b   0   --> rule5 doesn't apply, rule6 fires st' = 1
+/// 1: ...                  --> ...
+/// 1: 0   b    e-1 a*b 0   --> rule1 doesn't apply, rule2 fires st' = 4
+/// 4: 0   b    e-1 a*b 0   --> rule7 moves prod to acc
+/// 4: ...                  --> ...
+/// 4: a*b b    e-1 0   0   --> rule7 doesn't apply, rule8 fires st' = 0
+/// .. ...
+/// 0: b^e b    0   0   0   --> HALT
+///
+pub fn power(base: i32, exp: i32) -> i32 {
+    assert!(base >= 0 && exp >= 0);
+    let program = power_program();
+    let machine = Machine::new(0, vec![1, base, exp, 0, 0]);
+
+    let end_machine = interpret(machine, &program, power_steps(base as u64, exp as u32) + 1);
+    assert!(end_machine.is_ok());
+    let (_, end_machine, _) = end_machine.unwrap();
     end_machine.tape_pos(0)
 }
 
 #[cfg(test)]
 mod test {
-    use super::{adder, mult};
+    use super::{
+        adder, adder_program, adder_steps, monus, mult, mult_program, mult_steps, power,
+        power_program, power_steps,
+    };
+    use crate::magnificent::{interpret, Machine};
 
     #[test]
     fn add_x_y() {
@@ -67,6 +196,17 @@ mod test {
         }
     }
 
+    #[test]
+    fn monus_x_y() {
+        for x in 1..10 {
+            for y in 1..10 {
+                assert_eq!(monus(x, y), std::cmp::max(x - y, 0));
+            }
+        }
+        assert_eq!(monus(3, 5), 0);
+        assert_eq!(monus(5, 0), 5);
+    }
+
     #[test]
     fn mult_x_y() {
         for x in 1..10 {
@@ -76,6 +216,47 @@ mod test {
         }
     }
 
+    #[test]
+    fn step_counts() {
+        assert_eq!(adder_steps(4, 9), (13, 9));
+        assert_eq!(adder_steps(4, 0), (4, 0));
+        for x in 0..10 {
+            for y in 1..10 {
+                assert_eq!(mult_steps(x, y), (x * y, (y * (2 * x + 2) - 1) as u64));
+            }
+        }
+        assert_eq!(mult_steps(7, 13), (91, 207));
+    }
+
+    #[test]
+    fn program_builders() {
+        let (x, y) = (1234, 5678);
+        let machine = Machine::new(0, vec![x, y]);
+        let (steps, end_machine, _) = interpret(machine, &adder_program(), u64::MAX).unwrap();
+        assert_eq!(steps, y as u64);
+        assert_eq!(end_machine.tape_pos(0), x + y);
+
+        let (x, y) = (37, 41);
+        let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+        let (_, end_machine, _) = interpret(machine, &mult_program(), u64::MAX).unwrap();
+        assert_eq!(end_machine.tape_pos(0), x * y);
+    }
+
+    #[test]
+    fn power_x_y() {
+        for x in 0..6 {
+            for y in 0..6 {
+                assert_eq!(power(x, y), x.pow(y as u32));
+            }
+        }
+        assert_eq!(power(2, 5), 32);
+        assert_eq!(power(3, 4), 81);
+
+        let machine = Machine::new(0, vec![1, 2, 5, 0, 0]);
+        let (steps, _, _) = interpret(machine, &power_program(), u64::MAX).unwrap();
+        assert_eq!(steps, power_steps(2, 5));
+    }
+
     #[test]
     fn big_mult() {
         assert_eq!(mult(100, 100), 10_000); // 20200 steps
@@ -0,0 +1,273 @@
+//! # minsky-repl
+//!
+//! Step through an m3 program interactively.
+//!
+//! ```text
+//! minsky-repl [--state STATE] PROGRAM.m3 [TAPE...]
+//! ```
+//!
+//! The program and the initial machine are given like for `minsky`. Commands are then read one
+//! per line from stdin:
+//!
+//! ```text
+//! step      fire the first applicable rule
+//! run N     take up to N steps, stopping early if the machine halts
+//! rules     list the rules that apply in the current configuration
+//! tapes     print the current configuration
+//! reset     go back to the initial machine
+//! help      list the commands
+//! quit      leave the REPL, as does the end of input
+//! ```
+
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+
+use minsky::m3_parser;
+use minsky::magnificent::{Machine, Program, StepResult};
+
+const USAGE: &str = "usage: minsky-repl [--state STATE] PROGRAM.m3 [TAPE...]
+
+options:
+    --state STATE    initial machine state (default the program's start state, or 0)";
+
+const HELP: &str = "commands:
+    step      fire the first applicable rule
+    run N     take up to N steps, stopping early if the machine halts
+    rules     list the rules that apply in the current configuration
+    tapes     print the current configuration
+    reset     go back to the initial machine
+    help      list the commands
+    quit      leave the REPL";
+
+/// A program together with the machine being stepped through it
+struct Session {
+    program: Program,
+    initial: Machine,
+    machine: Machine,
+    steps: u64,
+    halted: bool,
+}
+
+/// What happened when the REPL tried to take a step
+enum Outcome {
+    /// the rule at the given index fired
+    Fired(usize),
+    /// no rule applies, the machine halted
+    Halted,
+    /// the machine had already halted
+    AlreadyHalted,
+    /// the step failed, the machine is unchanged
+    Error(String),
+}
+
+impl Session {
+    fn new(program: Program, initial: Machine) -> Self {
+        Session {
+            program,
+            machine: initial.clone(),
+            initial,
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    /// Format the current configuration like a `minsky --trace` line.
+    fn configuration(&self) -> String {
+        let m = &self.machine;
+        if self.program.tape_names().is_empty() {
+            format!("{}: {} {:?}", self.steps, m.machine_state(), m.tapes())
+        } else {
+            let tapes: Vec<String> = m
+                .tapes()
+                .iter()
+                .enumerate()
+                .map(|(t, p)| format!("{}={}", self.program.tape_label(t), p))
+                .collect();
+            format!("{}: {} {}", self.steps, m.machine_state(), tapes.join(" "))
+        }
+    }
+
+    /// Take one step.
+    fn step(&mut self) -> Outcome {
+        if self.halted {
+            return Outcome::AlreadyHalted;
+        }
+        match self.machine.step(&self.program) {
+            StepResult::Fired(idx) => {
+                self.steps += 1;
+                Outcome::Fired(idx)
+            }
+            StepResult::Halted => {
+                self.halted = true;
+                Outcome::Halted
+            }
+            StepResult::BadClause(rule) => {
+                Outcome::Error(format!("rule {} has a bad clause", rule))
+            }
+            StepResult::Overflow(rule, tape) => {
+                Outcome::Error(format!("rule {} would overflow tape {}", rule, tape))
+            }
+        }
+    }
+
+    /// Describe the outcome of a step.
+    fn report(&self, outcome: &Outcome, out: &mut impl Write) -> io::Result<()> {
+        match outcome {
+            Outcome::Fired(idx) => {
+                let rule = self.program.iter().nth(*idx).expect("fired rule exists");
+                writeln!(out, "rule {}: {}", idx, rule)
+            }
+            Outcome::Halted => {
+                write!(out, "halted after {} steps", self.steps)?;
+                if !self.program.accept_states().is_empty() {
+                    let accepted = self.program.is_accepting(self.machine.machine_state());
+                    write!(out, ", accepted: {}", accepted)?;
+                }
+                writeln!(out)
+            }
+            Outcome::AlreadyHalted => {
+                writeln!(out, "the machine has halted, use `reset` to start over")
+            }
+            Outcome::Error(e) => writeln!(out, "error: {}", e),
+        }
+    }
+
+    /// Execute one command line. Returns `false` when the session should end.
+    fn execute(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["step"] => {
+                let outcome = self.step();
+                self.report(&outcome, out)?;
+                if let Outcome::Fired(_) = outcome {
+                    writeln!(out, "{}", self.configuration())?;
+                }
+            }
+            ["run", n] => match n.parse::<u64>() {
+                Ok(n) => {
+                    for _ in 0..n {
+                        let outcome = self.step();
+                        if let Outcome::Fired(_) = outcome {
+                            continue;
+                        }
+                        self.report(&outcome, out)?;
+                        break;
+                    }
+                    writeln!(out, "{}", self.configuration())?;
+                }
+                Err(_) => writeln!(out, "error: invalid number of steps `{}`", n)?,
+            },
+            ["rules"] => {
+                let rules = self.machine.applicable_rules(&self.program);
+                if rules.is_empty() {
+                    writeln!(out, "no rule applies")?;
+                }
+                for (idx, rule) in rules {
+                    writeln!(out, "rule {}: {}", idx, rule)?;
+                }
+            }
+            ["tapes"] => writeln!(out, "{}", self.configuration())?,
+            ["reset"] => {
+                self.machine = self.initial.clone();
+                self.steps = 0;
+                self.halted = false;
+                writeln!(out, "{}", self.configuration())?;
+            }
+            ["help"] => writeln!(out, "{}", HELP)?,
+            ["quit"] | ["exit"] => return Ok(false),
+            _ => writeln!(out, "error: unknown command `{}`, try `help`", line.trim())?,
+        }
+        Ok(true)
+    }
+}
+
+/// Parse the command line into the program file, the initial state, and the tape positions.
+fn parse_args<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<(String, Option<usize>, Vec<i32>), String> {
+    let mut state = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--state" => {
+                let value = args.next().ok_or("missing value for state")?;
+                let parsed = value
+                    .parse()
+                    .map_err(|_| format!("invalid state `{}`", value))?;
+                state = Some(parsed);
+            }
+            "-h" | "--help" => return Err(String::new()),
+            a if a.starts_with("--") => return Err(format!("unknown option `{}`", a)),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.is_empty() {
+        return Err("missing program file".to_string());
+    }
+    let program = positional.remove(0);
+    let tapes = positional
+        .into_iter()
+        .map(|t| {
+            t.parse()
+                .map_err(|_| format!("invalid tape position `{}`", t))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((program, state, tapes))
+}
+
+fn run(file: String, state: Option<usize>, mut tapes: Vec<i32>) -> Result<(), String> {
+    let program = m3_parser::try_read_program(&file).map_err(|e| e.to_string())?;
+    if tapes.len() > program.num_tapes() {
+        return Err(format!(
+            "{} tape positions given but the program has {} tapes",
+            tapes.len(),
+            program.num_tapes()
+        ));
+    }
+    tapes.resize(program.num_tapes(), 0);
+    let state = match state {
+        Some(state) => state.into(),
+        None => program.start_state().unwrap_or_default(),
+    };
+    let machine = Machine::try_new(state, tapes)?;
+
+    let mut session = Session::new(program, machine);
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut out = io::stdout();
+    let io_error = |e: io::Error| e.to_string();
+    writeln!(out, "{}", session.configuration()).map_err(io_error)?;
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            write!(out, "> ").map_err(io_error)?;
+            out.flush().map_err(io_error)?;
+        }
+        let line = match lines.next() {
+            Some(line) => line.map_err(io_error)?,
+            None => return Ok(()),
+        };
+        if !session.execute(&line, &mut out).map_err(io_error)? {
+            return Ok(());
+        }
+    }
+}
+
+fn main() {
+    let (file, state, tapes) = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("error: {}", e);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(file, state, tapes) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
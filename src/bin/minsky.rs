@@ -0,0 +1,173 @@
+//! # minsky
+//!
+//! Run an m3 program from the command line.
+//!
+//! ```text
+//! minsky [--trace] [--sample N] [--dot] [--state STATE] [--fuel FUEL] PROGRAM.m3 [TAPE...]
+//! ```
+//!
+//! The initial tape positions are given after the program file, missing positions default to 0.
+//! A program file of `-` reads the program from stdin.
+//! On success the number of steps taken, the final machine state, and the final tape positions
+//! are printed, followed by whether the machine halted in an accept state if the program
+//! declares any.
+
+use std::env;
+use std::io;
+use std::process;
+
+use minsky::m3_parser;
+use minsky::magnificent::{self, HaltReason, Machine};
+
+const USAGE: &str = "usage: minsky [--trace] [--sample N] [--dot] [--state STATE] [--fuel FUEL] \
+                     PROGRAM.m3 [TAPE...]
+
+options:
+    --trace          print every configuration of the machine while it runs
+    --sample N       like --trace, but only print every Nth configuration and the last one
+    --dot            print the state graph of the program in graphviz dot format and exit
+    --state STATE    initial machine state (default the program's start state, or 0)
+    --fuel FUEL      maximum number of steps to run (default 1000000)
+
+Use `-` as the program file to read the program from stdin.";
+
+/// Command line options
+struct Options {
+    trace: bool,
+    sample: u64,
+    dot: bool,
+    state: Option<usize>,
+    fuel: u64,
+    program: String,
+    tapes: Vec<i32>,
+}
+
+/// Parse a numeric command line value, naming `what` it is in the error.
+fn parse_value<T: std::str::FromStr>(what: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", what))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} `{}`", what, value))
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut trace = false;
+    let mut sample = 1;
+    let mut dot = false;
+    let mut state = None;
+    let mut fuel = 1_000_000;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--sample" => {
+                sample = parse_value("sample interval", args.next())?;
+                trace = true;
+            }
+            "--dot" => dot = true,
+            "--state" => state = Some(parse_value("state", args.next())?),
+            "--fuel" => fuel = parse_value("fuel", args.next())?,
+            "-h" | "--help" => return Err(String::new()),
+            a if a.starts_with("--") => return Err(format!("unknown option `{}`", a)),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.is_empty() {
+        return Err("missing program file".to_string());
+    }
+    let program = positional.remove(0);
+    let tapes = positional
+        .into_iter()
+        .map(|t| parse_value("tape position", Some(t)))
+        .collect::<Result<_, _>>()?;
+    Ok(Options {
+        trace,
+        sample,
+        dot,
+        state,
+        fuel,
+        program,
+        tapes,
+    })
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let program = if options.program == "-" {
+        m3_parser::parse_reader(io::stdin().lock())
+    } else {
+        m3_parser::try_read_program(&options.program)
+    }
+    .map_err(|e| e.to_string())?;
+    if options.dot {
+        print!("{}", magnificent::to_dot(&program));
+        return Ok(());
+    }
+
+    let mut tapes = options.tapes;
+    if tapes.len() > program.num_tapes() {
+        return Err(format!(
+            "{} tape positions given but the program has {} tapes",
+            tapes.len(),
+            program.num_tapes()
+        ));
+    }
+    tapes.resize(program.num_tapes(), 0);
+    let state = match options.state {
+        Some(state) => state.into(),
+        None => program.start_state().unwrap_or_default(),
+    };
+    let machine = Machine::try_new(state, tapes)?;
+
+    let trace = options.trace;
+    let result = magnificent::interpret_with_sampled_trace(
+        machine,
+        &program,
+        options.fuel,
+        options.sample,
+        |m, step| {
+            if !trace {
+                return;
+            }
+            if program.tape_names().is_empty() {
+                println!("{}: {} {:?}", step, m.machine_state(), m.tapes());
+            } else {
+                let tapes: Vec<String> = m
+                    .tapes()
+                    .iter()
+                    .enumerate()
+                    .map(|(t, p)| format!("{}={}", program.tape_label(t), p))
+                    .collect();
+                println!("{}: {} {}", step, m.machine_state(), tapes.join(" "));
+            }
+        },
+    );
+    match result {
+        Ok((steps, machine, reason)) => {
+            println!("halted after {} steps", steps);
+            println!("state: {}", machine.machine_state());
+            println!("tapes: {:?}", machine.tapes());
+            if !program.accept_states().is_empty() {
+                println!("accepted: {}", reason == HaltReason::Accepted);
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("error: {}", e);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -1,12 +1,26 @@
 //! # Minsky
 //!
 //! See documentation in [`magnificent`] and [`marvellous`].
+//!
+//! The crate builds without the standard library when the default `std` feature is disabled. The
+//! interpreter, the transpiler, and the arithmetic programs then only need `alloc`; the parser in
+//! [`m3_parser`] and the command line tool need `std`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
 pub mod arith;
+#[cfg(feature = "std")]
+pub mod m3_ast;
+#[cfg(feature = "std")]
 pub mod m3_parser;
 pub mod magnificent;
 pub mod marvellous;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate lalrpop_util;
 
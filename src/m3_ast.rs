@@ -0,0 +1,375 @@
+//! # m3 Abstract Syntax
+//!
+//! This module defines the raw program representation produced by the m3 parser in
+//! [`m3_parser`]. A raw program is validated before it is converted into a
+//! [`magnificent::Program`].
+//!
+//! States in an m3 file are either all numeric or all named. Named states are resolved to dense
+//! integers while parsing, in the order in which the names first appear in the program, so the
+//! raw rules always carry integer states. A next state may also be written relative to the
+//! rule's current state, as in `@+1`, which is resolved to an integer state as well. Parsed
+//! rules also record where they appear in the source, so diagnostics can point back to it.
+//!
+//! The adjustments of a rule are either written out for every tape, as in `[0, -1, 1]`, or only
+//! for the tapes the rule moves, as in `{1: -1, 2: 1}`. Sparse adjustments are expanded to one
+//! adjustment per tape once the number of tapes is known.
+//!
+//! A program may also include the rules of other m3 files with an `include "path.m3"` directive.
+//! The raw program only records where each include appears, the rules are spliced in by the
+//! parser when the program is read.
+//!
+//! [`m3_parser`]: crate::m3_parser
+//! [`magnificent::Program`]: crate::magnificent::Program
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Parse an integer literal as accepted by the m3 lexer.
+///
+/// The literal has an optional sign followed by decimal digits or by `0x` and hexadecimal
+/// digits. Digits may be separated by underscores. Returns an error if the value does not fit in
+/// an `i32`.
+pub fn parse_integer(literal: &str) -> Result<i32, String> {
+    let (negative, unsigned) = match literal.as_bytes().first() {
+        Some(b'-') => (true, &literal[1..]),
+        Some(b'+') => (false, &literal[1..]),
+        _ => (false, literal),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") | Some("0X") => (16, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    // parse the magnitude with a wider type so that `-0x8000_0000` is accepted
+    let magnitude = i64::from_str_radix(&digits, radix)
+        .map_err(|e| format!("invalid integer literal `{}`: {}", literal, e))?;
+    let value = if negative { -magnitude } else { magnitude };
+    i32::try_from(value).map_err(|_| format!("integer literal `{}` is out of range", literal))
+}
+
+/// A state as written in the source, either a number or an identifier
+#[derive(Debug, Eq, PartialEq)]
+pub enum StateLabel {
+    Num(i32),
+    Name(String),
+    /// An offset from the current state of the rule, e.g. `@+1`, only valid as a next state
+    Relative(i32),
+}
+
+impl fmt::Display for StateLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateLabel::Num(n) => write!(f, "{}", n),
+            StateLabel::Name(name) => write!(f, "{}", name),
+            StateLabel::Relative(offset) => write!(f, "@{:+}", offset),
+        }
+    }
+}
+
+/// Tape adjustments as written in the source
+#[derive(Debug, Eq, PartialEq)]
+pub enum Adjustments {
+    /// one adjustment per tape, e.g. `[0, -1, 1]`
+    Dense(Vec<i32>),
+    /// the adjustments of the listed tapes, all other tapes are not moved, e.g. `{1: -1, 2: 1}`
+    Sparse(Vec<(i32, i32)>),
+}
+
+impl Adjustments {
+    /// Expand the adjustments to one adjustment per tape.
+    ///
+    /// Sparse adjustments must name tapes in `0..num_tapes`, each at most once. Dense adjustments
+    /// are returned as they are, their width is checked when the program is validated.
+    pub fn expand(self, num_tapes: usize) -> Result<Vec<i32>, String> {
+        let entries = match self {
+            Adjustments::Dense(v) => return Ok(v),
+            Adjustments::Sparse(entries) => entries,
+        };
+        let mut dense = vec![0; num_tapes];
+        let mut given = vec![false; num_tapes];
+        for (tape, amt) in entries {
+            let idx = usize::try_from(tape)
+                .ok()
+                .filter(|t| *t < num_tapes)
+                .ok_or_else(|| {
+                    format!(
+                        "tape {} is out of range for a program with {} tapes",
+                        tape, num_tapes
+                    )
+                })?;
+            if given[idx] {
+                return Err(format!("tape {} is adjusted more than once", tape));
+            }
+            given[idx] = true;
+            dense[idx] = amt;
+        }
+        Ok(dense)
+    }
+}
+
+/// A rule as written in the source, before state names are resolved
+#[derive(Debug, Eq, PartialEq)]
+pub struct LabeledRule {
+    pub cur_state: StateLabel,
+    pub adjustments: Adjustments,
+    pub next_state: StateLabel,
+    pub probe: Option<String>,
+    pub line: Option<usize>,
+    pub span: Option<(usize, usize)>,
+}
+
+/// A rule or an include directive, in the order they appear in the source
+#[derive(Debug, Eq, PartialEq)]
+pub enum Item {
+    Rule(LabeledRule),
+    /// The included path and the byte offsets of the directive in the source, if parsed
+    Include(String, Option<(usize, usize)>),
+}
+
+/// An include directive
+#[derive(Debug, Eq, PartialEq)]
+pub struct Include {
+    /// Path of the included file, as written in the source
+    pub path: String,
+    /// Number of rules of the including program that precede the directive
+    pub position: usize,
+    /// Byte offsets of the start and end of the directive in the source, if it was parsed
+    pub span: Option<(usize, usize)>,
+}
+
+/// A parsed rule with resolved integer states
+#[derive(Debug, Eq, PartialEq)]
+pub struct RawRule {
+    pub cur_state: i32,
+    pub next_state: i32,
+    pub adjustments: Vec<i32>,
+    /// Whether the adjustments were written in sparse form, e.g. `{1: -1}`
+    pub sparse: bool,
+    /// Offset of the next state from the current state if it was written relative, e.g. `@+1`
+    pub next_offset: Option<i32>,
+    /// Message of a probe rule, e.g. `probe "reached inner loop"`
+    pub probe: Option<String>,
+    /// Line of the source the rule starts on, counting from 1, if the rule was parsed
+    pub line: Option<usize>,
+    /// Byte offsets of the start and end of the rule in the source, if the rule was parsed
+    pub span: Option<(usize, usize)>,
+}
+
+/// Raw rules are displayed in m3 syntax, e.g. `0 [1, -1] 1`.
+impl fmt::Display for RawRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let adjustments: Vec<String> = self.adjustments.iter().map(|a| a.to_string()).collect();
+        write!(
+            f,
+            "{} [{}] {}",
+            self.cur_state,
+            adjustments.join(", "),
+            self.next_state
+        )?;
+        if let Some(message) = &self.probe {
+            write!(f, " probe \"{}\"", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed program
+#[derive(Debug, Eq, PartialEq)]
+pub struct RawProgram {
+    /// Number of tapes declared in the program header
+    pub num_tapes: usize,
+    /// Optional upper bound on each tape declared in a `bounds:` header, `None` for unbounded
+    pub bounds: Option<Vec<Option<i32>>>,
+    /// Optional tape names declared in a `names:` header
+    pub tape_names: Option<Vec<String>>,
+    /// Optional start state declared in a `start:` header
+    pub start_state: Option<i32>,
+    /// Optional accept states declared in an `accept:` header, in source order
+    pub accept_states: Option<Vec<i32>>,
+    /// Rules in the order they appear in the source
+    pub rules: Vec<RawRule>,
+    /// Include directives in the order they appear in the source
+    pub includes: Vec<Include>,
+    /// State names indexed by their assigned integer state, empty if the program uses numeric
+    /// states
+    pub state_names: Vec<String>,
+}
+
+impl RawProgram {
+    /// Build a raw program from the rules and include directives of a source file, see
+    /// `resolve`.
+    ///
+    /// If the number of tapes is not given it is taken from the width of the first rule with
+    /// dense adjustments, which must then exist.
+    pub fn from_items(num_tapes: Option<usize>, items: Vec<Item>) -> Result<Self, String> {
+        let num_tapes = match num_tapes {
+            Some(n) => n,
+            None => items
+                .iter()
+                .find_map(|item| match item {
+                    Item::Rule(LabeledRule {
+                        adjustments: Adjustments::Dense(v),
+                        ..
+                    }) => Some(v.len()),
+                    _ => None,
+                })
+                .ok_or("cannot infer the number of tapes of a program without dense rules")?,
+        };
+        let mut labeled_rules = Vec::new();
+        let mut includes = Vec::new();
+        for item in items {
+            match item {
+                Item::Rule(r) => labeled_rules.push(r),
+                Item::Include(path, span) => includes.push(Include {
+                    path,
+                    position: labeled_rules.len(),
+                    span,
+                }),
+            }
+        }
+        let mut program = RawProgram::resolve(num_tapes, labeled_rules)?;
+        program.includes = includes;
+        Ok(program)
+    }
+
+    /// Set the start state from its label in a `start:` header, see `resolve_header_state`.
+    pub fn resolve_start(&mut self, label: StateLabel) -> Result<(), String> {
+        self.start_state = Some(self.resolve_header_state(label, "start state")?);
+        Ok(())
+    }
+
+    /// Set the accept states from their labels in an `accept:` header, see
+    /// `resolve_header_state`.
+    pub fn resolve_accept(&mut self, labels: Vec<StateLabel>) -> Result<(), String> {
+        let states = labels
+            .into_iter()
+            .map(|label| self.resolve_header_state(label, "accept state"))
+            .collect::<Result<Vec<i32>, String>>()?;
+        self.accept_states = Some(states);
+        Ok(())
+    }
+
+    /// Resolve the label of a state given in a header, described as `what` in errors.
+    ///
+    /// A named state must be one of the names used by the rules, and the state must be numeric
+    /// if the rules use numeric states.
+    fn resolve_header_state(&self, label: StateLabel, what: &str) -> Result<i32, String> {
+        match label {
+            StateLabel::Num(n) if self.state_names.is_empty() => Ok(n),
+            StateLabel::Name(name) if !self.state_names.is_empty() => self
+                .state_names
+                .iter()
+                .position(|s| *s == name)
+                .map(|s| s as i32)
+                .ok_or_else(|| format!("{} `{}` does not appear in the rules", what, name)),
+            StateLabel::Relative(_) => Err(format!("{} `{}` cannot be relative", what, label)),
+            label => Err(format!(
+                "cannot mix named and numeric states (found {} `{}`)",
+                what, label
+            )),
+        }
+    }
+
+    /// Build a raw program from labeled rules, resolving state names to integers.
+    ///
+    /// Names are assigned integers `0, 1, ...` in order of first appearance. It is an error to
+    /// mix named and numeric states in one program. Sparse adjustments are expanded to
+    /// `num_tapes` adjustments. A relative next state is added to the
+    /// numeric current state of its rule, and must not come out negative. Relative states
+    /// cannot be used with named states.
+    pub fn resolve(num_tapes: usize, labeled_rules: Vec<LabeledRule>) -> Result<Self, String> {
+        let mut state_names: Vec<String> = Vec::new();
+        let mut symbols: HashMap<String, i32> = HashMap::new();
+        let mut numeric = None;
+        let mut named = None;
+
+        let mut resolve_label = |label: StateLabel| -> i32 {
+            match label {
+                StateLabel::Num(n) => {
+                    numeric.get_or_insert(n);
+                    n
+                }
+                StateLabel::Name(name) => {
+                    if named.is_none() {
+                        named = Some(name.clone());
+                    }
+                    let next = symbols.len() as i32;
+                    *symbols.entry(name.clone()).or_insert_with(|| {
+                        state_names.push(name);
+                        next
+                    })
+                }
+                StateLabel::Relative(_) => unreachable!("relative states are resolved per rule"),
+            }
+        };
+
+        let mut relative = None;
+        let mut rules = Vec::new();
+        for r in labeled_rules {
+            let sparse = matches!(r.adjustments, Adjustments::Sparse(_));
+            let line = r.line;
+            let adjustments = r.adjustments.expand(num_tapes).map_err(|e| match line {
+                Some(line) => format!("line {}: {}", line, e),
+                None => e,
+            })?;
+            if let StateLabel::Relative(_) = r.cur_state {
+                return Err(format!(
+                    "relative state `{}` can only be used as a next state",
+                    r.cur_state
+                ));
+            }
+            let cur_state = resolve_label(r.cur_state);
+            let (next_state, next_offset) = match r.next_state {
+                StateLabel::Relative(offset) => {
+                    relative.get_or_insert(offset);
+                    let next_state = i64::from(cur_state) + i64::from(offset);
+                    let next_state = i32::try_from(next_state)
+                        .ok()
+                        .filter(|s| *s >= 0)
+                        .ok_or_else(|| {
+                            format!(
+                                "relative next state `@{:+}` of state {} is not a valid state",
+                                offset, cur_state
+                            )
+                        })?;
+                    (next_state, Some(offset))
+                }
+                label => (resolve_label(label), None),
+            };
+            rules.push(RawRule {
+                cur_state,
+                next_state,
+                adjustments,
+                sparse,
+                next_offset,
+                probe: r.probe,
+                line: r.line,
+                span: r.span,
+            });
+        }
+
+        if let (Some(n), Some(name)) = (numeric, &named) {
+            return Err(format!(
+                "cannot mix named and numeric states (found state `{}` and state `{}`)",
+                name, n
+            ));
+        }
+        if let (Some(offset), Some(name)) = (relative, &named) {
+            return Err(format!(
+                "relative states require numeric states (found state `{}` and next state `@{:+}`)",
+                name, offset
+            ));
+        }
+        Ok(RawProgram {
+            num_tapes,
+            bounds: None,
+            tape_names: None,
+            start_state: None,
+            accept_states: None,
+            rules,
+            includes: Vec::new(),
+            state_names,
+        })
+    }
+}
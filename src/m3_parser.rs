@@ -1,50 +1,732 @@
+use crate::m3_ast::RawProgram;
 use crate::magnificent;
+use lalrpop_util::lexer::Token;
+use lalrpop_util::ParseError;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
-lalrpop_mod!(pub m3); // generated parser
+lalrpop_mod!(#[allow(clippy::all)] pub m3); // generated parser
 
-pub fn parse_m3(_input: &str) -> Result<magnificent::Program, String> {
-    // let raw_program = grammer::PredParser::new().parse(input)
-    //     .or_else(|e| panic!("m3 parser failed: {}", e))
-    Err("unimplemented".to_string())
+/// Errors that can occur while loading a program from a file
+#[derive(Debug)]
+pub enum ProgramError {
+    /// the program file could not be read
+    Io(io::Error),
+    /// the program text is not syntactically valid m3
+    Parse(String),
+    /// the program parsed but is not a valid program
+    Validation(String),
 }
 
-pub fn validate_raw_program(prog: &magnificent::Program) -> Result<(), String> {
-    for r in prog.iter() {
-        if r.len() != prog.num_tapes() {
-            return Err(format!("Rule {:?} specifies incorrect number of tapes", r));
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::Io(e) => write!(f, "io error: {}", e),
+            ProgramError::Parse(e) => write!(f, "syntax error: {}", e),
+            ProgramError::Validation(e) => write!(f, "invalid program: {}", e),
         }
     }
-    Ok(())
+}
+
+impl std::error::Error for ProgramError {}
+
+/// Parse an m3 program into its raw representation without validating it.
+///
+/// Inconsistent state labels are reported as a validation error, all other failures as a parse
+/// error.
+pub fn parse_raw(input: &str) -> Result<RawProgram, ProgramError> {
+    let mut errors = Vec::new();
+    let result = m3::ProgramParser::new().parse(input, &mut errors, input);
+    // the parser recovers from some syntax errors, the first one is reported
+    if let Some(recovery) = errors.into_iter().next() {
+        return Err(ProgramError::Parse(recovery.error.to_string()));
+    }
+    result.map_err(|e| match e {
+        ParseError::User { error } => ProgramError::Validation(error),
+        e => ProgramError::Parse(e.to_string()),
+    })
+}
+
+/// Parse and validate an m3 program from a string like [`parse_m3`], reporting every syntax
+/// error instead of only the first one.
+///
+/// The parser skips over a malformed adjustment list and carries on with the next rule, so
+/// several broken rules produce one diagnostic each, prefixed with the line and column of the
+/// offending token. A syntax error the parser cannot recover from ends the list. The program is
+/// only validated if there are no syntax errors, and validation reports a single error.
+pub fn parse_m3_all_errors(input: &str) -> Result<magnificent::Program, Vec<String>> {
+    let mut errors = Vec::new();
+    let result = m3::ProgramParser::new().parse(input, &mut errors, input);
+    let mut diagnostics: Vec<String> = errors
+        .into_iter()
+        .map(|recovery| describe_parse_error(input, recovery.error))
+        .collect();
+    match result {
+        // validation errors after a recovery are caused by the placeholder adjustments
+        Err(ParseError::User { .. }) if !diagnostics.is_empty() => {}
+        Err(e) => diagnostics.push(describe_parse_error(input, e)),
+        Ok(_) => {}
+    }
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    parse_m3(input).map_err(|e| vec![e])
+}
+
+/// Return the line and column, both starting at 1, of a byte offset into `input`.
+fn line_col(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, col)
+}
+
+/// Describe a parse error with the line and column where it occurred.
+fn describe_parse_error(input: &str, error: ParseError<usize, Token<'_>, String>) -> String {
+    let at = |offset: usize| {
+        let (line, col) = line_col(input, offset);
+        format!("line {}, column {}", line, col)
+    };
+    match error {
+        ParseError::InvalidToken { location } => format!("{}: invalid token", at(location)),
+        ParseError::UnrecognizedEOF { location, expected } => format!(
+            "{}: unexpected end of input, expected one of {}",
+            at(location),
+            expected.join(", ")
+        ),
+        ParseError::UnrecognizedToken {
+            token: (l, t, _),
+            expected,
+        } => format!(
+            "{}: unrecognized token `{}`, expected one of {}",
+            at(l),
+            t,
+            expected.join(", ")
+        ),
+        ParseError::ExtraToken { token: (l, t, _) } => {
+            format!("{}: extra token `{}`", at(l), t)
+        }
+        ParseError::User { error } => format!("invalid program: {}", error),
+    }
+}
+
+/// Parse and validate an m3 program from a string.
+///
+/// Syntax errors are reported with a `syntax error:` prefix and validation errors (e.g. a rule
+/// with the wrong number of tapes) with an `invalid program:` prefix. Included files are looked
+/// up relative to the current directory.
+pub fn parse_m3(input: &str) -> Result<magnificent::Program, String> {
+    parse_program(input, Path::new("."), &mut Vec::new()).map_err(|e| e.to_string())
+}
+
+/// Parse and validate several m3 programs from one string, e.g. a bundle of test cases.
+///
+/// The programs are separated by lines consisting of `---`, and each one is parsed like
+/// [`parse_m3`], so it needs its own `tapes:` header or rules to infer it from. Chunks that only
+/// contain whitespace are skipped. Errors are prefixed with the index of the failing program and
+/// the line of the input it starts on, line numbers in the message itself are relative to the
+/// start of the program.
+pub fn parse_many(input: &str) -> Result<Vec<magnificent::Program>, String> {
+    let mut chunks = vec![(1, String::new())];
+    for (idx, line) in input.lines().enumerate() {
+        if line.trim() == "---" {
+            chunks.push((idx + 2, String::new()));
+        } else {
+            let (_, chunk) = chunks.last_mut().expect("there is always a chunk");
+            chunk.push_str(line);
+            chunk.push('\n');
+        }
+    }
+    chunks
+        .into_iter()
+        .filter(|(_, chunk)| !chunk.trim().is_empty())
+        .enumerate()
+        .map(|(idx, (line, chunk))| {
+            parse_m3(&chunk)
+                .map_err(|e| format!("program {} starting on line {}: {}", idx, line, e))
+        })
+        .collect()
+}
+
+/// Check that the raw program declares at least one tape, that every rule adjusts exactly
+/// `num_tapes` tapes, and that there is one non-negative bound per tape if bounds are declared
+/// and one distinct name per tape if tape names are declared. States must be non-negative, a
+/// declared start state must be the current state of some rule, and declared accept states must
+/// appear in some rule, unless the program still has includes to resolve.
+///
+/// Errors about a rule give its index in the program and, if known, the source line it starts
+/// on.
+///
+/// On success the warnings from [`magnificent::Rule::validate`] for the rules of the program are
+/// returned, located the same way, along with a warning if the program has no rules. They do not
+/// make the program invalid.
+pub fn validate_raw_program(prog: &RawProgram) -> Result<Vec<String>, String> {
+    if prog.num_tapes == 0 {
+        return Err("program declares 0 tapes but needs at least one".to_string());
+    }
+    if let Some(bounds) = &prog.bounds {
+        if bounds.len() != prog.num_tapes {
+            return Err(format!(
+                "{} bounds given but program declares {} tapes",
+                bounds.len(),
+                prog.num_tapes
+            ));
+        }
+        if let Some(tape) = bounds.iter().position(|b| b.is_some_and(|b| b < 0)) {
+            return Err(format!("bound on tape {} is negative", tape));
+        }
+    }
+    if let Some(names) = &prog.tape_names {
+        magnificent::Program::new(prog.num_tapes, Vec::new()).with_tape_names(names.clone())?;
+    }
+    // included rules may fire in the start state, so it can only be checked once they are in
+    if let Some(start) = prog.start_state {
+        if start < 0 {
+            return Err(format!("start state {} is negative", start));
+        }
+        let fires = prog
+            .rules
+            .iter()
+            .any(|r| r.cur_state == start && r.probe.is_none());
+        if prog.includes.is_empty() && !fires {
+            return Err(format!(
+                "start state {} is not the current state of any rule",
+                state_label(prog, start)
+            ));
+        }
+    }
+    for accept in prog.accept_states.iter().flatten() {
+        if *accept < 0 {
+            return Err(format!("accept state {} is negative", accept));
+        }
+        let appears = prog
+            .rules
+            .iter()
+            .any(|r| r.cur_state == *accept || r.next_state == *accept);
+        if prog.includes.is_empty() && !appears {
+            return Err(format!(
+                "accept state {} does not appear in the program",
+                state_label(prog, *accept)
+            ));
+        }
+    }
+    let mut warnings = Vec::new();
+    if prog.rules.is_empty() {
+        warnings.push("program has no rules and halts immediately".to_string());
+    }
+    for (idx, r) in prog.rules.iter().enumerate() {
+        let location = match r.line {
+            Some(line) => format!("line {}: ", line),
+            None => String::new(),
+        };
+        if r.adjustments.len() != prog.num_tapes {
+            return Err(format!(
+                "{}rule {} has {} adjustments but program declares {} tapes",
+                location,
+                idx,
+                r.adjustments.len(),
+                prog.num_tapes
+            ));
+        }
+        if let Some(state) = [r.cur_state, r.next_state].iter().find(|s| **s < 0) {
+            return Err(format!(
+                "{}rule {} has negative state {}",
+                location, idx, state
+            ));
+        }
+        if let Some(message) = &r.probe {
+            if message.contains(['"', '\n', '\r']) {
+                return Err(format!(
+                    "{}probe rule {} has a message with a double quote or a line break",
+                    location, idx
+                ));
+            }
+            if r.next_state != r.cur_state {
+                return Err(format!(
+                    "{}probe rule {} must not change the state",
+                    location, idx
+                ));
+            }
+            if r.adjustments.iter().any(|a| *a > 0) {
+                return Err(format!(
+                    "{}probe rule {} must not increment any tape",
+                    location, idx
+                ));
+            }
+            continue;
+        }
+        let rule = magnificent::Rule::new(
+            r.cur_state as usize,
+            r.next_state as usize,
+            r.adjustments.clone(),
+        );
+        if let Err(warning) = rule.validate() {
+            warnings.push(format!("{}rule {}: {}", location, idx, warning));
+        }
+    }
+    Ok(warnings)
+}
+
+/// Return the name of a state if the program uses named states, and its number otherwise.
+fn state_label(prog: &RawProgram, state: i32) -> String {
+    match prog.state_names.get(state as usize) {
+        Some(name) => name.clone(),
+        None => state.to_string(),
+    }
+}
+
+/// Convert a validated raw program into a program for the interpreter.
+fn raw_to_program(raw: RawProgram) -> magnificent::Program {
+    let rules = raw
+        .rules
+        .into_iter()
+        .map(|r| match r.probe {
+            Some(message) => magnificent::Rule::probe(r.cur_state as usize, r.adjustments, message),
+            None => {
+                magnificent::Rule::new(r.cur_state as usize, r.next_state as usize, r.adjustments)
+            }
+        })
+        .collect();
+    let program = magnificent::Program::new(raw.num_tapes, rules);
+    let program = match raw.bounds {
+        Some(bounds) => program
+            .with_bounds(bounds)
+            .expect("bounds have been validated"),
+        None => program,
+    };
+    let program = match raw.tape_names {
+        Some(names) => program
+            .with_tape_names(names)
+            .expect("tape names have been validated"),
+        None => program,
+    };
+    let program = match raw.start_state {
+        Some(start) => program
+            .with_start_state(start as usize)
+            .expect("start state has been validated"),
+        None => program,
+    };
+    match raw.accept_states {
+        Some(accept) => program
+            .with_accept_states(accept.into_iter().map(|s| s as usize))
+            .expect("accept states have been validated"),
+        None => program,
+    }
+}
+
+/// Convert a raw program, e.g. one built by hand, into a program for the interpreter.
+///
+/// The raw program is checked with [`validate_raw_program`] first, and its validation warnings
+/// are dropped. Include directives must already be resolved, since the included files cannot be
+/// read here.
+impl TryFrom<RawProgram> for magnificent::Program {
+    type Error = String;
+
+    fn try_from(raw: RawProgram) -> Result<Self, String> {
+        if let Some(include) = raw.includes.first() {
+            return Err(format!(
+                "include \"{}\" has not been resolved",
+                include.path
+            ));
+        }
+        validate_raw_program(&raw)?;
+        Ok(raw_to_program(raw))
+    }
+}
+
+/// Add `context` in front of the message of a parse or validation error.
+fn with_context(e: ProgramError, context: &str) -> ProgramError {
+    match e {
+        ProgramError::Parse(e) => ProgramError::Parse(format!("{}: {}", context, e)),
+        ProgramError::Validation(e) => ProgramError::Validation(format!("{}: {}", context, e)),
+        e => e,
+    }
+}
+
+/// Splice the rules of the files included by `raw` into it, looking up the included files
+/// relative to `dir`.
+///
+/// `stack` holds the canonical paths of the files that are currently being included, and is used
+/// to detect include cycles. Included files must declare the same number of tapes as `raw` and
+/// use numeric states, since named states are resolved separately in every file. Any bounds,
+/// tape names, start state, or accept states declared in an included file are ignored.
+fn resolve_includes(
+    mut raw: RawProgram,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<RawProgram, ProgramError> {
+    if raw.includes.is_empty() {
+        return Ok(raw);
+    }
+    if !raw.state_names.is_empty() {
+        return Err(ProgramError::Validation(
+            "include requires numeric states".to_string(),
+        ));
+    }
+
+    let includes = std::mem::take(&mut raw.includes);
+    let mut own_rules = std::mem::take(&mut raw.rules).into_iter();
+    let mut consumed = 0;
+    for include in includes {
+        raw.rules
+            .extend(own_rules.by_ref().take(include.position - consumed));
+        consumed = include.position;
+
+        let path = dir.join(&include.path);
+        let display = path.display().to_string();
+        let canonical = fs::canonicalize(&path).map_err(|e| {
+            ProgramError::Io(io::Error::new(e.kind(), format!("{}: {}", display, e)))
+        })?;
+        if stack.contains(&canonical) {
+            return Err(ProgramError::Validation(format!(
+                "include cycle detected at {}",
+                display
+            )));
+        }
+        let input = fs::read_to_string(&canonical).map_err(|e| {
+            ProgramError::Io(io::Error::new(e.kind(), format!("{}: {}", display, e)))
+        })?;
+        let included = parse_raw(&input).map_err(|e| with_context(e, &display))?;
+        if included.num_tapes != raw.num_tapes {
+            return Err(ProgramError::Validation(format!(
+                "{} declares {} tapes but the including program declares {}",
+                display, included.num_tapes, raw.num_tapes
+            )));
+        }
+
+        stack.push(canonical);
+        let included_dir = path.parent().unwrap_or(dir).to_path_buf();
+        let mut included = resolve_includes(included, &included_dir, stack)
+            .map_err(|e| with_context(e, &display))?;
+        stack.pop();
+        // check the rules here so that errors point into the included file
+        included.bounds = None;
+        included.tape_names = None;
+        included.start_state = None;
+        included.accept_states = None;
+        validate_raw_program(&included)
+            .map_err(|e| with_context(ProgramError::Validation(e), &display))?;
+        if !included.state_names.is_empty() {
+            return Err(ProgramError::Validation(format!(
+                "{}: include requires numeric states",
+                display
+            )));
+        }
+        raw.rules.extend(included.rules);
+    }
+    raw.rules.extend(own_rules);
+    Ok(raw)
+}
+
+fn parse_program(
+    input: &str,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<magnificent::Program, ProgramError> {
+    let raw = resolve_includes(parse_raw(input)?, dir, stack)?;
+    magnificent::Program::try_from(raw).map_err(ProgramError::Validation)
+}
+
+/// Read the whole program text from `reader`, then parse and validate it like `parse_program`.
+fn parse_reader_in<R: Read>(
+    mut reader: R,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<magnificent::Program, ProgramError> {
+    let mut input = String::new();
+    reader
+        .read_to_string(&mut input)
+        .map_err(ProgramError::Io)?;
+    parse_program(&input, dir, stack)
+}
+
+/// Read, parse, and validate a program from any reader, e.g. stdin or an in-memory buffer.
+///
+/// The reader is read to the end and must contain valid UTF-8. Included files are looked up
+/// relative to the current directory.
+pub fn parse_reader<R: Read>(reader: R) -> Result<magnificent::Program, ProgramError> {
+    parse_reader_in(reader, Path::new("."), &mut Vec::new())
+}
+
+/// Read, parse, and validate the program in the given file.
+///
+/// Every error message is prefixed with `filepath` so callers can tell which file failed.
+/// Included files are looked up relative to the directory containing `filepath`.
+pub fn try_read_program(filepath: &str) -> Result<magnificent::Program, ProgramError> {
+    let with_path =
+        |e: io::Error| ProgramError::Io(io::Error::new(e.kind(), format!("{}: {}", filepath, e)));
+    let file = fs::File::open(filepath).map_err(with_path)?;
+    let path = Path::new(filepath);
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut stack: Vec<PathBuf> = fs::canonicalize(path).into_iter().collect();
+    parse_reader_in(file, dir, &mut stack).map_err(|e| match e {
+        ProgramError::Io(e) => with_path(e),
+        e => with_context(e, filepath),
+    })
+}
+
+/// Read, parse, and validate every `.m3` file in the given directory.
+///
+/// The result pairs each file name with the program or the error message, sorted by file name.
+/// Other files and subdirectories are skipped, and a directory that cannot be read yields no
+/// programs.
+pub fn load_examples(dir: &str) -> Vec<(String, Result<magnificent::Program, String>)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "m3"))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let program = try_read_program(&path.to_string_lossy()).map_err(|e| e.to_string());
+            (name, program)
+        })
+        .collect()
+}
+
+/// A piece of m3 source skipped by the lexer or kept, see `scan_comments`
+enum Segment {
+    /// a line or block comment, with its byte offsets
+    Comment(usize, usize),
+    /// a run of other non-whitespace text, with its byte offsets
+    Code(usize, usize),
+}
+
+/// Split `text` into comments and code, dropping whitespace. Offsets are relative to `text`.
+fn scan_comments(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().expect("position is inside the text");
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+        } else if rest.starts_with("//") || rest.starts_with('#') {
+            let end = rest.find(['\n', '\r']).unwrap_or(rest.len());
+            segments.push(Segment::Comment(pos, pos + end));
+            pos += end;
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            let end = body.find("*/").map_or(rest.len(), |e| e + 4);
+            segments.push(Segment::Comment(pos, pos + end));
+            pos += end;
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '#' || c == '/')
+                .filter(|e| *e > 0)
+                .unwrap_or(c.len_utf8());
+            match segments.last_mut() {
+                Some(Segment::Code(_, e)) if *e == pos => *e = pos + end,
+                _ => segments.push(Segment::Code(pos, pos + end)),
+            }
+            pos += end;
+        }
+    }
+    segments
+}
+
+/// Format a list of m3 values as a vector, e.g. `[acc, x]`
+fn format_vector<T: ToString>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// Parse an m3 program and print it in canonical form, like `rustfmt` for m3 files.
+///
+/// The output starts with the `tapes:` header, followed by the `start:`, `accept:`, `bounds:`,
+/// and `names:` headers if given, and then the rules and include directives in their original
+/// order with one item per line. Each comma is followed by a space, and the adjustments of all
+/// rules are right-aligned in columns, padded with more spaces where needed, so the brackets of
+/// the rules line up. Named states,
+/// relative next states, and sparse adjustments are kept, the latter listing only the tapes a
+/// rule moves.
+///
+/// Comments are preserved: a comment after an item on the same line stays on that line, any
+/// other comment goes on its own line, and a blank line is kept wherever the input had one or
+/// more. Comments between the headers are moved after them, and comments inside an item are
+/// moved after it on its line. Included files are not read, so
+/// the program is only checked on its own.
+pub fn format(input: &str) -> Result<String, String> {
+    let raw = parse_raw(input).map_err(|e| e.to_string())?;
+    validate_raw_program(&raw).map_err(|e| ProgramError::Validation(e).to_string())?;
+
+    let label = |s: i32| match raw.state_names.get(s as usize) {
+        Some(name) => name.clone(),
+        None => s.to_string(),
+    };
+    let mut columns = vec![0; raw.num_tapes];
+    for r in raw.rules.iter().filter(|r| !r.sparse) {
+        for (width, a) in columns.iter_mut().zip(r.adjustments.iter()) {
+            *width = (*width).max(a.to_string().len());
+        }
+    }
+    let label_width = raw
+        .rules
+        .iter()
+        .map(|r| label(r.cur_state).len())
+        .max()
+        .unwrap_or(0);
+
+    // every item with its formatted line, in source order
+    let mut items: Vec<((usize, usize), String)> = Vec::new();
+    for r in raw.rules.iter() {
+        let adjustments = if r.sparse {
+            let entries: Vec<String> = r
+                .adjustments
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| **a != 0)
+                .map(|(tape, a)| format!("{}: {}", tape, a))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        } else {
+            format_vector(
+                &r.adjustments
+                    .iter()
+                    .zip(columns.iter())
+                    .map(|(a, width)| format!("{:>width$}", a, width = width))
+                    .collect::<Vec<String>>(),
+            )
+        };
+        let next_state = match r.next_offset {
+            Some(offset) => format!("@{:+}", offset),
+            None => label(r.next_state),
+        };
+        let mut line = format!(
+            "{:<width$} {} {}",
+            label(r.cur_state),
+            adjustments,
+            next_state,
+            width = label_width
+        );
+        if let Some(message) = &r.probe {
+            line.push_str(&format!(" probe \"{}\"", message));
+        }
+        items.push((r.span.unwrap_or_default(), line));
+    }
+    for include in raw.includes.iter() {
+        let line = format!("include \"{}\"", include.path);
+        items.push((include.span.unwrap_or_default(), line));
+    }
+    items.sort_by_key(|(span, _)| *span);
+    let first_item = items.first().map_or(input.len(), |((l, _), _)| *l);
+
+    let mut header = vec![format!("tapes: {}", raw.num_tapes)];
+    if let Some(start) = raw.start_state {
+        header.push(format!("start: {}", label(start)));
+    }
+    if let Some(accept) = &raw.accept_states {
+        let accept: Vec<String> = accept.iter().map(|s| label(*s)).collect();
+        header.push(format!("accept: {}", format_vector(&accept)));
+    }
+    if let Some(bounds) = &raw.bounds {
+        let bounds: Vec<String> = bounds
+            .iter()
+            .map(|b| b.map_or("_".to_string(), |b| b.to_string()))
+            .collect();
+        header.push(format!("bounds: {}", format_vector(&bounds)));
+    }
+    if let Some(names) = &raw.tape_names {
+        header.push(format!("names: {}", format_vector(names)));
+    }
+
+    // lay out the comments around the header and the items: (offsets, text, may trail)
+    let mut pieces: Vec<((usize, usize), String, bool)> = Vec::new();
+    let prefix = scan_comments(&input[..first_item]);
+    let header_start = prefix.iter().find_map(|seg| match seg {
+        Segment::Code(l, _) => Some(*l),
+        Segment::Comment(..) => None,
+    });
+    let header_end = prefix.iter().rev().find_map(|seg| match seg {
+        Segment::Code(_, r) => Some(*r),
+        Segment::Comment(..) => None,
+    });
+    let header_span = match (header_start, header_end) {
+        (Some(l), Some(r)) => (l, r),
+        _ => (first_item, first_item),
+    };
+    let mut after_header = Vec::new();
+    for seg in prefix {
+        if let Segment::Comment(l, r) = seg {
+            let comment = ((l, r), input[l..r].trim_end().to_string(), false);
+            if l < header_span.0 {
+                pieces.push(comment);
+            } else {
+                after_header.push(comment);
+            }
+        }
+    }
+    pieces.push((header_span, header.join("\n"), false));
+    pieces.extend(after_header);
+    for (idx, (span, line)) in items.iter().enumerate() {
+        pieces.push((*span, line.clone(), false));
+        let next = items.get(idx + 1).map_or(input.len(), |((l, _), _)| *l);
+        // the comments inside the item come first, so they trail it on its line
+        for seg in scan_comments(&input[span.0..next]) {
+            if let Segment::Comment(l, r) = seg {
+                let (l, r) = (span.0 + l, span.0 + r);
+                pieces.push(((l, r), input[l..r].trim_end().to_string(), true));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut prev_end: Option<usize> = None;
+    let mut after_line_comment = false;
+    for ((l, r), text, may_trail) in pieces {
+        let gap = prev_end.map_or("", |e| &input[e.min(l)..l]);
+        if may_trail && prev_end.is_some() && !after_line_comment && !gap.contains('\n') {
+            out.pop();
+            out.push_str("  ");
+        } else if gap.matches('\n').count() >= 2 {
+            out.push('\n');
+        }
+        out.push_str(&text);
+        out.push('\n');
+        // a comment inside an item ends before the item does
+        prev_end = Some(prev_end.map_or(r, |e| e.max(r)));
+        after_line_comment = text.starts_with("//") || text.starts_with('#');
+    }
+    Ok(out)
 }
 
 // Helper function to read / parse programs
 pub fn read_program(filepath: &str) -> magnificent::Program {
-    let input = fs::read_to_string(filepath).expect("failed to read program file");
-    let program = m3::ProgramParser::new()
-        .parse(&input)
-        .expect("failed to parse program file");
-    validate_raw_program(&program).expect("invalid program");
-    program
+    try_read_program(filepath).unwrap_or_else(|e| panic!("failed to read program: {}", e))
 }
 
 #[cfg(test)]
 mod test {
 
     use super::m3;
-    use super::validate_raw_program;
+    use super::{
+        format, load_examples, parse_m3, parse_m3_all_errors, parse_many, parse_raw, parse_reader,
+        try_read_program, validate_raw_program, ProgramError,
+    };
+    use crate::m3_ast::{RawProgram, RawRule};
     use crate::magnificent;
+    use crate::magnificent::State;
+    use std::convert::TryFrom;
     use std::fs;
+    use std::io::Cursor;
 
     #[test]
     pub fn test_parse_m3() {
         let input = r"
             tapes: 2
             0 [1, -1] 0";
-        let program = m3::ProgramParser::new()
-            .parse(input)
-            .expect("m3 parser failed");
-        validate_raw_program(&program).expect("Invalid program");
+        let program = parse_m3(input).expect("m3 parser failed");
         assert_eq!(program.num_tapes(), 2);
         let mut rules_iter = program.iter();
         assert_eq!(
@@ -56,10 +738,7 @@ mod test {
             tapes: 3
             0 [1, -1, 2] 1
             1 [0, 1, 0] 2";
-        let program = m3::ProgramParser::new()
-            .parse(input)
-            .expect("m3 parser failed");
-        validate_raw_program(&program).expect("Invalid program");
+        let program = parse_m3(input).expect("m3 parser failed");
         assert_eq!(program.num_tapes(), 3);
 
         let mut rules_iter = program.iter();
@@ -82,11 +761,409 @@ mod test {
             5 [1, 2, 3, 0, 0] 0
             ";
         let program = m3::ProgramParser::new()
-            .parse(input)
+            .parse(input, &mut Vec::new(), input)
             .expect("m3 parser failed");
         validate_raw_program(&program).expect("Invalid program");
     }
 
+    // Test that comments interleaved with the header and rules are ignored
+    #[test]
+    pub fn test_parse_comments() {
+        let input = r"
+            # a program with comments
+            tapes: 3 // three tapes
+            /* the first rule
+               moves tapes 0 and 1 */
+            0 [1, -1, 2] 1 # trailing comment
+            // a line comment
+            1 [0, 1, 0] 2 /* trailing block comment */
+            ";
+        let program = parse_m3(input).expect("m3 parser failed");
+        let uncommented = parse_m3("tapes: 3\n0 [1, -1, 2] 1\n1 [0, 1, 0] 2").unwrap();
+        assert_eq!(program.num_tapes(), uncommented.num_tapes());
+        assert!(program.iter().eq(uncommented.iter()));
+    }
+
+    // Test that explicit signs are accepted and do not change the parsed values
+    #[test]
+    pub fn test_parse_signs() {
+        let signed = parse_m3("tapes: +3\n0 [+3, -2, +0] 1\n1 [-0, 0, +1] 0").unwrap();
+        let unsigned = parse_m3("tapes: 3\n0 [3, -2, 0] 1\n1 [0, 0, 1] 0").unwrap();
+        assert_eq!(signed.num_tapes(), 3);
+        assert!(signed.iter().eq(unsigned.iter()));
+    }
+
+    // Test that rule clauses may be wrapped over several lines
+    #[test]
+    pub fn test_parse_multi_line_rules() {
+        let input =
+            "tapes: 3\n0 [\n 1,\n -1,\n 2\n] 0\n1 [0, 0, 0] 1\n2\n[  0,\t0, // zero\n\n 0 ]\n  2\n";
+        let program = parse_m3(input).expect("m3 parser failed");
+        let one_line = parse_m3("tapes: 3\n0 [1, -1, 2] 0\n1 [0, 0, 0] 1\n2 [0, 0, 0] 2")
+            .expect("m3 parser failed");
+        assert_eq!(program, one_line);
+
+        let raw = parse_raw(input).unwrap();
+        let lines: Vec<Option<usize>> = raw.rules.iter().map(|r| r.line).collect();
+        assert_eq!(lines, vec![Some(2), Some(7), Some(8)]);
+    }
+
+    // Test that the number of tapes is inferred from the first rule without a header
+    #[test]
+    pub fn test_parse_inferred_tapes() {
+        let program = parse_m3("0 [1, -1, 0] 0\n0 [0, 0, 0] 1").expect("m3 parser failed");
+        assert_eq!(program.num_tapes(), 3);
+        assert_eq!(program.num_rules(), 2);
+
+        let program = parse_m3("tapes: 3\n0 [1, -1, 0] 0").expect("m3 parser failed");
+        assert_eq!(program.num_tapes(), 3);
+
+        let err = parse_m3("0 [1, -1, 0] 0\n0 [0, 0] 1").unwrap_err();
+        assert!(err.contains("rule 1 has 2 adjustments"), "{}", err);
+
+        let err = parse_m3("include \"other.m3\"").unwrap_err();
+        assert!(err.contains("cannot infer the number of tapes"), "{}", err);
+    }
+
+    // Test parsing of the optional bounds header
+    #[test]
+    pub fn test_parse_bounds() {
+        let input = r"
+            tapes: 3
+            bounds: [10, _, 5]
+            0 [1, -1, 1] 0";
+        let raw = parse_raw(input).expect("m3 parser failed");
+        assert_eq!(raw.bounds, Some(vec![Some(10), None, Some(5)]));
+        let program = parse_m3(input).expect("m3 parser failed");
+        assert_eq!(program.bound(0), Some(10));
+        assert_eq!(program.bound(1), None);
+        assert_eq!(program.bound(2), Some(5));
+
+        // bounds survive a round trip through m3 text
+        let reparsed = parse_m3(&program.to_m3_string()).expect("m3 parser failed");
+        assert_eq!(reparsed.bound(2), Some(5));
+
+        let err = parse_m3("tapes: 2\nbounds: [1]\n0 [1, 0] 0").unwrap_err();
+        assert!(err.starts_with("invalid program:"), "{}", err);
+
+        let raw = parse_raw("tapes: 1\n0 [1] 0").expect("m3 parser failed");
+        assert_eq!(raw.bounds, None);
+    }
+
+    // Test parsing of the optional tape names header
+    #[test]
+    pub fn test_parse_tape_names() {
+        let input = r"
+            tapes: 3
+            bounds: [_, _, 5]
+            names: [acc, x, tmp]
+            0 [1, -1, 1] 0";
+        let raw = parse_raw(input).expect("m3 parser failed");
+        let names = vec!["acc".to_string(), "x".to_string(), "tmp".to_string()];
+        assert_eq!(raw.tape_names, Some(names.clone()));
+        let program = parse_m3(input).expect("m3 parser failed");
+        assert_eq!(program.tape_names(), names.as_slice());
+        assert_eq!(program.tape_label(0), "acc");
+        assert!(parse_m3(&program.to_m3_string()).unwrap() == program);
+
+        let program = parse_m3("tapes: 2\n0 [1, -1] 0").unwrap();
+        assert!(program.tape_names().is_empty());
+        assert_eq!(program.tape_label(1), "tape[1]");
+
+        let err = parse_m3("tapes: 2\nnames: [acc]\n0 [1, 0] 0").unwrap_err();
+        assert!(err.contains("1 tape names given"), "{}", err);
+        let err = parse_m3("tapes: 2\nnames: [x, x]\n0 [1, 0] 0").unwrap_err();
+        assert!(err.contains("reuses the name `x`"), "{}", err);
+    }
+
+    // Test parsing of the optional start state header
+    #[test]
+    pub fn test_parse_start_state() {
+        let input = "tapes: 1\nstart: 1\n0 [1] 0\n1 [-1] 1";
+        assert_eq!(parse_raw(input).unwrap().start_state, Some(1));
+        let program = parse_m3(input).expect("m3 parser failed");
+        assert_eq!(program.start_state(), Some(State(1)));
+        assert!(parse_m3(&program.to_m3_string()).unwrap() == program);
+
+        let program = parse_m3("tapes: 1\nstart: dec\ninc [1] inc\ndec [-1] dec").unwrap();
+        assert_eq!(program.start_state(), Some(State(1)));
+        assert_eq!(parse_m3("tapes: 1\n0 [1] 0").unwrap().start_state(), None);
+
+        let err = parse_m3("tapes: 1\nstart: 2\n0 [1] 1").unwrap_err();
+        assert!(err.contains("start state 2"), "{}", err);
+        let err = parse_m3("tapes: 1\nstart: 0\ninc [1] inc").unwrap_err();
+        assert!(
+            err.contains("cannot mix named and numeric states"),
+            "{}",
+            err
+        );
+        let err = parse_m3("tapes: 1\nstart: dec\ninc [1] inc").unwrap_err();
+        assert!(err.contains("`dec` does not appear"), "{}", err);
+    }
+
+    // Test that whitespace around the colon of a header is ignored
+    #[test]
+    pub fn test_parse_header_whitespace() {
+        let expected = parse_m3("tapes: 3\n0 [1, -1, 0] 0").unwrap();
+        for header in [
+            "tapes:3",
+            "tapes : 3",
+            "tapes:\t3",
+            "tapes\n:\n3",
+            "tapes  :3",
+        ] {
+            let input = format!("{}\n0 [1, -1, 0] 0", header);
+            assert_eq!(parse_m3(&input).unwrap(), expected, "{:?}", header);
+        }
+
+        let input = "tapes : 2\nstart :1\naccept\t: [1]\nbounds : [_, 4]\nnames :[a, b]\n\
+                     0 [1, -1] 1\n1 [0, 1] 0";
+        let program = parse_m3(input).unwrap();
+        assert_eq!(program.start_state(), Some(State(1)));
+        assert!(program.is_accepting(1));
+        assert_eq!(program.bound(1), Some(4));
+        assert_eq!(program.tape_names(), &["a", "b"]);
+
+        // header keywords are still valid state names
+        let program =
+            parse_m3("tapes: 1\nstart: start\nstart [1] accept\naccept [-1] start").unwrap();
+        assert_eq!(program.start_state(), Some(State(0)));
+        assert_eq!(program.num_rules(), 2);
+    }
+
+    // Test parsing of probe rules
+    #[test]
+    pub fn test_parse_probe() {
+        let input = "tapes: 2\nloop [0, -1] loop probe \"reached inner loop\"\nloop [1, -1] loop";
+        let raw = parse_raw(input).unwrap();
+        assert_eq!(raw.rules[0].probe.as_deref(), Some("reached inner loop"));
+        assert_eq!(raw.rules[1].probe, None);
+        let program = parse_m3(input).expect("m3 parser failed");
+        let probe = program.iter().next().unwrap();
+        assert_eq!(probe.probe_message(), Some("reached inner loop"));
+        assert_eq!(
+            probe.to_string(),
+            "0 [0, -1] 0 probe \"reached inner loop\""
+        );
+        assert!(parse_m3(&program.to_m3_string()).unwrap() == program);
+        assert_eq!(
+            format(input).unwrap(),
+            "tapes: 2\nloop [0, -1] loop probe \"reached inner loop\"\nloop [1, -1] loop\n"
+        );
+
+        // a probe has to keep its state and only test the tapes
+        for input in [
+            "tapes: 1\n0 [-1] 1 probe \"x\"",
+            "tapes: 1\n0 [1] 0 probe \"x\"",
+        ] {
+            let err = parse_m3(input).unwrap_err();
+            assert!(err.contains("probe rule 0"), "{}", err);
+        }
+        // `probe` is a keyword
+        assert!(parse_m3("tapes: 1\nprobe [1] 0").is_err());
+
+        // a probe cannot be the only rule of the start state
+        let err = parse_m3("tapes: 1\nstart: 1\n1 [-1] 1 probe \"x\"\n0 [1] 0").unwrap_err();
+        assert!(err.contains("start state 1"), "{}", err);
+
+        // a message that could not be written back is an error, not a panic
+        let mut raw = parse_raw("tapes: 1\n0 [-1] 0 probe \"x\"").unwrap();
+        raw.rules[0].probe = Some("say \"x\"".to_string());
+        let err = magnificent::Program::try_from(raw).unwrap_err();
+        assert!(err.contains("probe rule 0"), "{}", err);
+    }
+
+    // Test parsing of the optional accept states header
+    #[test]
+    pub fn test_parse_accept_states() {
+        let input = "tapes: 1\nstart: 0\naccept: [2, 1]\n0 [-1] 1\n1 [-1] 2";
+        assert_eq!(parse_raw(input).unwrap().accept_states, Some(vec![2, 1]));
+        let program = parse_m3(input).expect("m3 parser failed");
+        assert!(program.is_accepting(1) && program.is_accepting(2));
+        assert!(!program.is_accepting(0));
+        assert!(parse_m3(&program.to_m3_string()).unwrap() == program);
+        assert!(format(input)
+            .unwrap()
+            .starts_with("tapes: 1\nstart: 0\naccept: [2, 1]\n"));
+
+        let program = parse_m3("tapes: 1\naccept: [done]\nloop [-1] done").unwrap();
+        assert!(program.is_accepting(1));
+
+        let err = parse_m3("tapes: 1\naccept: [3]\n0 [-1] 1").unwrap_err();
+        assert!(err.contains("accept state 3 does not appear"), "{}", err);
+        let err = parse_m3("tapes: 1\naccept: [done]\nloop [-1] loop").unwrap_err();
+        assert!(
+            err.contains("accept state `done` does not appear"),
+            "{}",
+            err
+        );
+    }
+
+    // Test that included rules are spliced in where the directive appears
+    #[test]
+    pub fn test_include() {
+        let raw = parse_raw("tapes: 1\n0 [1] 0\ninclude \"other.m3\"\n0 [-1] 0").unwrap();
+        assert_eq!(raw.rules.len(), 2);
+        assert_eq!(raw.includes.len(), 1);
+        assert_eq!(raw.includes[0].path, "other.m3");
+        assert_eq!(raw.includes[0].position, 1);
+
+        let program = try_read_program("tests/m3/mult-include.m3").expect("include failed");
+        let mult = try_read_program("examples/mult.m3").unwrap();
+        assert!(program.iter().eq(mult.iter()));
+    }
+
+    // Test that include cycles and tape mismatches are rejected
+    #[test]
+    pub fn test_bad_include() {
+        match try_read_program("tests/m3/cycle-a.m3") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("include cycle"), "{}", e),
+            r => panic!("expected an include cycle, got {:?}", r),
+        }
+        match try_read_program("tests/m3/include-wrong-tapes.m3") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("declares 4 tapes"), "{}", e),
+            r => panic!("expected a tape mismatch, got {:?}", r),
+        }
+    }
+
+    // Test that named states are resolved in order of first appearance
+    #[test]
+    pub fn test_parse_named_states() {
+        let input = r"
+            tapes: 2
+            start [0, -1] loop
+            loop [1, 0] loop
+            loop [0, 0] done
+            start [0, 0] done";
+        let raw = parse_raw(input).expect("m3 parser failed");
+        assert_eq!(raw.state_names, vec!["start", "loop", "done"]);
+
+        let program = parse_m3(input).expect("m3 parser failed");
+        let numeric = parse_m3("tapes: 2\n0 [0, -1] 1\n1 [1, 0] 1\n1 [0, 0] 2\n0 [0, 0] 2");
+        assert!(program.iter().eq(numeric.unwrap().iter()));
+
+        // numeric states have no names
+        let raw = parse_raw("tapes: 1\n0 [1] 1").expect("m3 parser failed");
+        assert!(raw.state_names.is_empty());
+    }
+
+    // Test that mixing named and numeric states is a validation error
+    #[test]
+    pub fn test_parse_mixed_states() {
+        match parse_raw("tapes: 1\nstart [1] 0") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("cannot mix"), "{}", e),
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+    }
+
+    // Test resolving relative next states against the current state of their rule
+    #[test]
+    pub fn test_parse_relative_states() {
+        let input =
+            "tapes: 2\n0 [1, -1] @+0\n0 [0, 0] @+1\n1 [-1, 1] 1\n1 [0, 0] @+1\n2 [0, 0] @-2";
+        let raw = parse_raw(input).expect("m3 parser failed");
+        let states: Vec<(i32, i32)> = raw
+            .rules
+            .iter()
+            .map(|r| (r.cur_state, r.next_state))
+            .collect();
+        assert_eq!(states, vec![(0, 0), (0, 1), (1, 1), (1, 2), (2, 0)]);
+        assert_eq!(raw.rules[1].next_offset, Some(1));
+        assert_eq!(raw.rules[2].next_offset, None);
+        assert!(format(input).unwrap().contains("2 [ 0,  0] @-2"));
+
+        match parse_raw("tapes: 1\n1 [1] @-2") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("`@-2` of state 1"), "{}", e),
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+        match parse_raw("tapes: 1\nloop [1] @+1") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("numeric states"), "{}", e),
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+    }
+
+    // Test converting a hand-built raw program into a program
+    #[test]
+    pub fn test_program_try_from_raw() {
+        let rule = |cur_state, next_state| RawRule {
+            cur_state,
+            next_state,
+            adjustments: vec![1, -1],
+            sparse: false,
+            next_offset: None,
+            probe: None,
+            line: None,
+            span: None,
+        };
+        let mut raw = RawProgram::resolve(2, Vec::new()).unwrap();
+        raw.rules = vec![rule(0, 0), rule(0, 1)];
+        let program = magnificent::Program::try_from(raw).expect("conversion failed");
+        assert_eq!(program.num_rules(), 2);
+        assert_eq!(program.iter().nth(1).unwrap().next_state(), 1);
+
+        let mut raw = RawProgram::resolve(2, Vec::new()).unwrap();
+        raw.rules = vec![rule(0, 0), rule(1, -1)];
+        let err = magnificent::Program::try_from(raw).unwrap_err();
+        assert_eq!(err, "rule 1 has negative state -1");
+
+        let mut raw = RawProgram::resolve(2, Vec::new()).unwrap();
+        raw.rules = vec![RawRule {
+            adjustments: vec![1],
+            ..rule(0, 0)
+        }];
+        let err = magnificent::Program::try_from(raw).unwrap_err();
+        assert!(err.contains("has 1 adjustments"), "{}", err);
+    }
+
+    // Test that sparse adjustments expand to the same rules as the dense form
+    #[test]
+    pub fn test_parse_sparse_adjustments() {
+        let dense =
+            parse_m3("tapes: 8\n0 [0, 0, 0, -1, 0, 0, 1, 0] 0\n0 [0, 0, 0, 0, 0, 0, 0, 0] 1")
+                .expect("m3 parser failed");
+        let sparse = parse_m3("tapes: 8\n0 {3: -1, 6: +1} 0\n0 {} 1").expect("m3 parser failed");
+        assert!(dense == sparse);
+
+        let input = "tapes: 3\n0 {2: 1, 0: -1} 0\n0 [0, 10, 0] 1";
+        let raw = parse_raw(input).unwrap();
+        assert!(raw.rules[0].sparse);
+        assert_eq!(raw.rules[0].adjustments, vec![-1, 0, 1]);
+        assert_eq!(
+            format(input).unwrap(),
+            "tapes: 3\n0 {0: -1, 2: 1} 0\n0 [0, 10, 0] 1\n"
+        );
+
+        match parse_raw("tapes: 2\n0 [1, 0] 0\n0 {2: 1} 0") {
+            Err(ProgramError::Validation(e)) => {
+                assert_eq!(
+                    e,
+                    "line 3: tape 2 is out of range for a program with 2 tapes"
+                )
+            }
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+        let err = parse_m3("tapes: 2\n0 {1: 1, 1: -1} 0").unwrap_err();
+        assert!(err.contains("tape 1 is adjusted more than once"), "{}", err);
+        let err = parse_m3("0 {0: 1} 0").unwrap_err();
+        assert!(err.contains("cannot infer the number of tapes"), "{}", err);
+
+        // a negative header is rejected before the sparse rule is expanded
+        let err = parse_m3("tapes: -1\n0 {0: 1} 0").unwrap_err();
+        assert!(err.contains("number of tapes -1 is negative"), "{}", err);
+        let err = parse_m3("tapes: -1\n0 [1] 0").unwrap_err();
+        assert!(err.contains("number of tapes -1 is negative"), "{}", err);
+    }
+
+    // Test that serializing a parsed program and parsing it again is the identity
+    #[test]
+    pub fn test_round_trip() {
+        let input = fs::read_to_string("examples/6-rule-mult.m3").expect("failed to read file");
+        let program = parse_m3(&input).expect("m3 parser failed");
+        let reparsed = parse_m3(&program.to_m3_string()).expect("m3 parser failed");
+        assert_eq!(program.num_tapes(), reparsed.num_tapes());
+        assert!(program.iter().eq(reparsed.iter()));
+    }
+
     // Test parsing a program with malformed tapes statement
     #[test]
     #[should_panic(expected = "m3 parser failed")]
@@ -95,7 +1172,7 @@ mod test {
             tape 3
             0 [1, -1, 2] 1";
         let program = m3::ProgramParser::new()
-            .parse(input)
+            .parse(input, &mut Vec::new(), input)
             .expect("m3 parser failed");
         validate_raw_program(&program).expect("Invalid program");
     }
@@ -108,11 +1185,37 @@ mod test {
             tapes: 3
             0 [1, -1, 2]";
         let program = m3::ProgramParser::new()
-            .parse(input)
+            .parse(input, &mut Vec::new(), input)
             .expect("m3 parser failed");
         validate_raw_program(&program).expect("Invalid program");
     }
 
+    // Test that the parser recovers from broken rules and reports each of them
+    #[test]
+    pub fn test_parse_all_errors() {
+        let input = "tapes: 2\n0 [1, x] 1\n1 [1, -1] 1\n1 [1 1] 0\n1 {0: -1 1: 1} 0\n";
+        let errors = parse_m3_all_errors(input).unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].starts_with("line 2, column 7: unrecognized token `x`"));
+        assert!(errors[1].starts_with("line 4, column 6: unrecognized token `1`"));
+        assert!(errors[2].starts_with("line 5, column 10: unrecognized token `1`"));
+
+        // the first error is the one reported by `parse_m3`
+        let error = parse_m3(input).unwrap_err();
+        assert!(error.starts_with("syntax error: Unrecognized token `x`"));
+
+        // unrecoverable errors and validation errors are reported on their own
+        let errors = parse_m3_all_errors("tapes: 2\n0 [1, -1]").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("line 2, column 10: unexpected end of input"));
+        let errors = parse_m3_all_errors("tapes: 2\n0 [1] 0").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("invalid program:"), "{:?}", errors);
+
+        let program = parse_m3_all_errors("tapes: 2\n0 [1, -1] 0").unwrap();
+        assert_eq!(program, parse_m3("tapes: 2\n0 [1, -1] 0").unwrap());
+    }
+
     // Test parsing a program with invalid rule width
     #[test]
     #[should_panic(expected = "Invalid program")]
@@ -120,25 +1223,245 @@ mod test {
         let input = r"
             tapes: 1
             0 [1, -1, 2, 0] 0";
-        let program = m3::ProgramParser::new().parse(input).unwrap(); //.expect("m3 parser failed");
+        let program = m3::ProgramParser::new()
+            .parse(input, &mut Vec::new(), input)
+            .unwrap(); //.expect("m3 parser failed");
         validate_raw_program(&program).expect("Invalid program");
     }
 
+    // Test hexadecimal and underscore separated integer literals
+    #[test]
+    pub fn test_parse_integer_literals() {
+        let raw = parse_raw("tapes: 0x3\n0 [0x10, -0x1, 1_000] 0\n0 [+0XfF, -0xff, 0x_1_0] 1")
+            .expect("m3 parser failed");
+        assert_eq!(raw.num_tapes, 3);
+        assert_eq!(raw.rules[0].adjustments, vec![16, -1, 1000]);
+        assert_eq!(raw.rules[1].adjustments, vec![255, -255, 16]);
+
+        let raw = parse_raw("tapes: 2\n0 [-0x8000_0000, 2_147_483_647] 0").unwrap();
+        assert_eq!(raw.rules[0].adjustments, vec![i32::MIN, i32::MAX]);
+
+        match parse_raw("tapes: 1\n0 [0x8000_0000] 0") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("out of range"), "{}", e),
+            r => panic!("expected an out of range literal, got {:?}", r),
+        }
+        match parse_raw("tapes: 1\n0 [99999999999999999999] 0") {
+            Err(ProgramError::Validation(e)) => assert!(e.contains("invalid integer"), "{}", e),
+            r => panic!("expected an invalid literal, got {:?}", r),
+        }
+    }
+
+    // Test that degenerate rules are reported as warnings
+    #[test]
+    pub fn test_validation_warnings() {
+        let raw = parse_raw("tapes: 2\n0 [1, -1] 0\n0 [0, 0] 1\n1 [0, 0] 1").unwrap();
+        let warnings = validate_raw_program(&raw).expect("Invalid program");
+        assert_eq!(
+            warnings,
+            vec!["line 4: rule 2: rule `1 [0, 0] 1` does not change the machine and loops forever once it fires"]
+        );
+        let raw = parse_raw("tapes: 2\n0 [1, -1] 0").unwrap();
+        assert!(validate_raw_program(&raw).unwrap().is_empty());
+    }
+
+    // Test that programs without tapes are rejected and programs without rules flagged
+    #[test]
+    pub fn test_validate_degenerate_programs() {
+        let err = parse_m3("tapes: 0\n0 [] 0").unwrap_err();
+        assert!(
+            err.contains("program declares 0 tapes but needs at least one"),
+            "{}",
+            err
+        );
+
+        let raw = RawProgram::resolve(1, Vec::new()).unwrap();
+        assert_eq!(
+            validate_raw_program(&raw).unwrap(),
+            vec!["program has no rules and halts immediately"]
+        );
+
+        let raw = parse_raw("tapes: 1\n0 [-1] 0").unwrap();
+        assert!(validate_raw_program(&raw).unwrap().is_empty());
+        assert_eq!(parse_m3("tapes: 1\n0 [-1] 0").unwrap().num_tapes(), 1);
+    }
+
+    // Test that the source location of every rule is recorded
+    #[test]
+    pub fn test_rule_locations() {
+        let input = "tapes: 2\n\n0 [1, -1] 0\n  /* block\n comment */ 0 [0, 0] 1 // done\n";
+        let raw = parse_raw(input).expect("m3 parser failed");
+        assert_eq!(raw.rules[0].line, Some(3));
+        assert_eq!(raw.rules[1].line, Some(5));
+        let (start, end) = raw.rules[1].span.unwrap();
+        assert_eq!(&input[start..end], "0 [0, 0] 1");
+        let (start, end) = raw.rules[0].span.unwrap();
+        assert_eq!(&input[start..end], "0 [1, -1] 0");
+    }
+
+    // Test that rule width errors point at the offending rule
+    #[test]
+    pub fn test_rule_width_error_location() {
+        let input = "tapes: 3\n0 [1, 0, 0] 0\n\n// a comment\n0 [1, -1, 2, 0] 1\n";
+        let raw = parse_raw(input).expect("m3 parser failed");
+        assert_eq!(raw.rules[0].line, Some(2));
+        assert_eq!(raw.rules[1].line, Some(5));
+        assert_eq!(
+            validate_raw_program(&raw).unwrap_err(),
+            "line 5: rule 1 has 4 adjustments but program declares 3 tapes"
+        );
+    }
+
+    // Test the non-panicking parser entry point
+    #[test]
+    pub fn test_parse_m3_result() {
+        let program = parse_m3("tapes: 2\n0 [1, -1] 0").expect("m3 parser failed");
+        assert_eq!(program.num_tapes(), 2);
+        assert_eq!(program.num_rules(), 1);
+
+        let err = parse_m3("tapes: 2\n0 [1, -1]").unwrap_err();
+        assert!(err.starts_with("syntax error:"), "{}", err);
+
+        let err = parse_m3("tapes: 1\n0 [1, -1] 0").unwrap_err();
+        assert!(err.starts_with("invalid program:"), "{}", err);
+    }
+
+    // Test that file loading errors are reported with the offending path
+    #[test]
+    pub fn test_try_read_program() {
+        let program = try_read_program("examples/adder.m3").expect("failed to read program");
+        assert_eq!(program.num_tapes(), 2);
+
+        match try_read_program("examples/does-not-exist.m3") {
+            Err(ProgramError::Io(e)) => assert!(e.to_string().contains("does-not-exist.m3")),
+            r => panic!("expected an io error, got {:?}", r),
+        }
+    }
+
+    // Test parsing a program from an in-memory reader
+    #[test]
+    pub fn test_parse_reader() {
+        let input = "tapes: 2\n0 [1, -1] 0\n";
+        let program = parse_reader(Cursor::new(input.as_bytes())).expect("failed to parse");
+        assert!(program == parse_m3(input).unwrap());
+
+        let bytes: &[u8] = &[0xff, 0xfe];
+        match parse_reader(Cursor::new(bytes)) {
+            Err(ProgramError::Io(_)) => {}
+            r => panic!("expected an io error, got {:?}", r),
+        }
+        assert!(matches!(
+            parse_reader(Cursor::new("tapes: 2\n0 [1] 0".as_bytes())),
+            Err(ProgramError::Validation(_))
+        ));
+    }
+
+    // Test that the formatter normalizes spacing and keeps comments
+    #[test]
+    pub fn test_format() {
+        let tight = format("tapes: 3\n0 [1,-1,2] 1").unwrap();
+        let loose = format("tapes:3\n  0 [ 1 , -1, 2 ]   1").unwrap();
+        assert_eq!(tight, "tapes: 3\n0 [1, -1, 2] 1\n");
+        assert_eq!(tight, loose);
+
+        let input = r#"
+            # leading comment
+            tapes: 2
+            names: [acc,x]
+
+            start [1,-1] start // move x to acc
+            /* own line */ start [0,-10] done
+            include "other.m3"
+            "#;
+        let expected = r#"# leading comment
+tapes: 2
+names: [acc, x]
+
+start [1,  -1] start  // move x to acc
+/* own line */
+start [0, -10] done
+include "other.m3"
+"#;
+        assert_eq!(format(input).unwrap(), expected);
+
+        // comments inside a rule are kept after it
+        assert_eq!(
+            format("tapes: 1\n0 [-1]/*x*/0").unwrap(),
+            "tapes: 1\n0 [-1] 0  /*x*/\n"
+        );
+        let input = "tapes: 2\n0 [1, // one\n -1] /* two */ 0 // three\n1 [0, 0] 1";
+        let formatted = format(input).unwrap();
+        assert_eq!(
+            formatted,
+            "tapes: 2\n0 [1, -1] 0  // one\n/* two */  // three\n1 [0,  0] 1\n"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        for path in ["examples/6-rule-mult.m3", "examples/marvellous-mult.m3"] {
+            let formatted = format(&fs::read_to_string(path).unwrap()).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted);
+            assert!(parse_m3(&formatted).unwrap() == try_read_program(path).unwrap());
+        }
+
+        let err = format("tapes: 2\n0 [1] 0").unwrap_err();
+        assert!(err.starts_with("invalid program:"), "{}", err);
+    }
+
+    // Test parsing several programs separated by `---` lines
+    #[test]
+    pub fn test_parse_many() {
+        let input = r"
+            tapes: 2
+            0 [1, -1] 0
+
+            ---
+            // the multiplier
+            tapes: 4
+            0 [1, -1,  1,  0] 0
+            0 [0,  0,  0,  0] 1
+            1 [0,  1, -1,  0] 1
+            1 [0,  0,  0, -1] 0
+            ---
+            ";
+        let programs = parse_many(input).expect("failed to parse programs");
+        assert_eq!(programs.len(), 2);
+        assert_eq!(programs[0].num_tapes(), 2);
+        assert_eq!(programs[1].num_tapes(), 4);
+        assert_eq!(programs[1].num_rules(), 4);
+
+        let err = parse_many("tapes: 1\n0 [1] 0\n---\ntapes: 2\n0 [1] 0\n").unwrap_err();
+        assert!(err.starts_with("program 1 starting on line 4: "), "{}", err);
+        assert!(parse_many("").unwrap().is_empty());
+    }
+
+    // Test that only m3 files are loaded from a directory
+    #[test]
+    pub fn test_load_examples() {
+        let examples = load_examples("tests/m3");
+        let names: Vec<&str> = examples.iter().map(|(name, _)| name.as_str()).collect();
+        assert!(names.contains(&"mult-include.m3"));
+        assert!(names.windows(2).all(|w| w[0] < w[1]));
+        assert!(names.iter().all(|name| name.ends_with(".m3")));
+        let (_, cycle) = examples
+            .iter()
+            .find(|(name, _)| name == "cycle-a.m3")
+            .unwrap();
+        assert!(cycle.as_ref().unwrap_err().contains("include cycle"));
+
+        assert!(load_examples("does-not-exist").is_empty());
+    }
+
     // Test parsing of a file on disk
     #[test]
     pub fn test_parse_adder() {
         const ADDER_PROGRAM: &str = "examples/adder.m3";
         let input = fs::read_to_string(ADDER_PROGRAM).expect("failed to read program file");
-        let program = m3::ProgramParser::new()
-            .parse(&input)
-            .expect("failed to parse program file");
-        validate_raw_program(&program).expect("invalid program");
+        let program = parse_m3(&input).expect("failed to parse program file");
 
         // Interpret the parsed program to make sure it works
         let machine = magnificent::Machine::new(0, vec![1, 1]);
         let end_machine = magnificent::interpret(machine, &program, 100);
         assert!(end_machine.is_ok());
-        let (_, end_machine) = end_machine.unwrap();
+        let (_, end_machine, _) = end_machine.unwrap();
         assert_eq!(end_machine.tape_pos(0), 2);
     }
 
@@ -147,16 +1470,13 @@ mod test {
     pub fn test_parse_mult() {
         const MULT_PROGRAM: &str = "examples/mult.m3";
         let input = fs::read_to_string(MULT_PROGRAM).expect("failed to read program file");
-        let program = m3::ProgramParser::new()
-            .parse(&input)
-            .expect("failed to parse program file");
-        validate_raw_program(&program).expect("invalid program");
+        let program = parse_m3(&input).expect("failed to parse program file");
 
         // Interpret the parsed program to make sure it works
         let machine = magnificent::Machine::new(0, vec![0, 2, 0, 3 - 1]);
         let end_machine = magnificent::interpret(machine, &program, 100);
         assert!(end_machine.is_ok());
-        let (_, end_machine) = end_machine.unwrap();
+        let (_, end_machine, _) = end_machine.unwrap();
         assert_eq!(end_machine.tape_pos(0), 6);
     }
 
@@ -165,10 +1485,7 @@ mod test {
     pub fn test_parse_6_rule_mult() {
         const MULT_PROGRAM: &str = "examples/6-rule-mult.m3";
         let input = fs::read_to_string(MULT_PROGRAM).expect("failed to read program file");
-        let program = m3::ProgramParser::new()
-            .parse(&input)
-            .expect("failed to parse program file");
-        validate_raw_program(&program).expect("invalid program");
+        let program = parse_m3(&input).expect("failed to parse program file");
 
         // Interpret the parsed program to make sure it works
         let x = 7;
@@ -176,7 +1493,7 @@ mod test {
         let machine = magnificent::Machine::new(0, vec![0, x, y, 0]);
         let end_machine = magnificent::interpret(machine, &program, 1000);
         assert!(end_machine.is_ok());
-        let (_, end_machine) = end_machine.unwrap();
+        let (_, end_machine, _) = end_machine.unwrap();
         assert_eq!(end_machine.tape_pos(0), x * y);
     }
 }
@@ -21,36 +21,298 @@
 //! process continues until either no rule in the program applies or the interpreter runs out of
 //! fuel.
 
-use std::slice::Iter;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use core::convert::{Infallible, TryFrom};
+use core::fmt;
+use core::ops::{ControlFlow, Deref, Index, IndexMut};
+use core::slice::{Iter, IterMut};
+
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, string::ToString, vec, vec::Vec};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bigint")]
+pub mod big;
+
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+
+pub mod gen;
 
 /// Error conditions the interpreter may return
+///
+/// The machine type `M` is only different from [`Machine`] for the arbitrary precision
+/// interpreter in the `big` module.
+#[derive(Debug)]
+pub enum ErrorCode<M = Machine> {
+    /// a tape id listed in the clause of the rule at index `rule` is invalid
+    BadClause { rule: usize },
+    /// interpreter out of fuel after `steps` steps, `machine` is the last configuration reached
+    OutOfFuel { steps: u64, machine: M },
+    /// the machine returned to a configuration it was in `period` steps earlier
+    Cycle { period: u64 },
+    /// no rule in the program fires in the initial machine state `state`
+    InvalidStart { state: State },
+    /// firing a rule would move the head of tape `tape` past `i32::MAX`
+    Overflow { tape: TapeId },
+    /// the rule at index `rule` fired more often than its cap allows, see
+    /// [`interpret_with_limits`]; `machine` is the configuration after the offending firing
+    RuleCapExceeded { rule: usize, steps: u64, machine: M },
+    /// the time budget of [`interpret_timed`] ran out after `steps` steps
+    Timeout { steps: u64 },
+    /// [`interpret_auto`] cannot prove termination of the program; supply fuel
+    TerminationUnknown,
+}
+
+/// Describes the error in a sentence fragment, e.g. for the command line and the wasm bindings
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCode::BadClause { rule } => write!(f, "rule {} has a bad clause", rule),
+            ErrorCode::OutOfFuel { steps, machine } => write!(
+                f,
+                "out of fuel after {} steps in state {} with tapes {:?}",
+                steps,
+                machine.machine_state(),
+                machine.tapes()
+            ),
+            ErrorCode::Cycle { period } => {
+                write!(f, "the machine repeats itself every {} steps", period)
+            }
+            ErrorCode::InvalidStart { state } => {
+                write!(f, "no rule fires in the initial state {}", state)
+            }
+            ErrorCode::Overflow { tape } => write!(f, "tape {} overflowed", tape),
+            ErrorCode::RuleCapExceeded {
+                rule,
+                steps,
+                machine,
+            } => write!(
+                f,
+                "rule {} exceeded its cap after {} steps in state {} with tapes {:?}",
+                rule,
+                steps,
+                machine.machine_state(),
+                machine.tapes()
+            ),
+            ErrorCode::Timeout { steps } => write!(f, "timed out after {} steps", steps),
+            ErrorCode::TerminationUnknown => {
+                write!(f, "cannot prove that the program terminates, supply fuel")
+            }
+        }
+    }
+}
+
+/// How a run of [`interpret_until`] ended
 #[derive(Debug)]
-pub enum ErrorCode {
-    /// a tape id listed in the clause is invalid
-    BadClause,
-    /// interpreter out of fuel
-    OutOfFuel,
+pub enum RunOutcome {
+    /// no rule applies to `machine`, which was reached after `steps` steps, for the given reason
+    Halted {
+        steps: u64,
+        machine: Machine,
+        reason: HaltReason,
+    },
+    /// the step limit was reached while some rule still applies to `machine`
+    StepLimit { steps: u64, machine: Machine },
+}
+
+/// Why a successful run of [`interpret`] or one of its variants halted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HaltReason {
+    /// the machine halted in one of the program's accept states, see
+    /// [`Program::with_accept_states`]
+    Accepted,
+    /// the machine halted normally: at least one rule fired before the machine reached a
+    /// configuration where no rule applies
+    Halted,
+    /// no rule applied to the initial machine, so no rule ever fired, e.g. because the machine
+    /// was started in the wrong state
+    NoRuleFired,
+}
+
+/// A snapshot of a machine during interpretation, see [`interpret_traced`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Configuration {
+    /// machine state
+    pub state: State,
+    /// tape head positions
+    pub tapes: Vec<i32>,
+    /// index of the rule that fired in this configuration, `None` if the machine halted
+    pub fired_rule: Option<usize>,
+}
+
+/// A copy of a machine's state and tape head positions, see [`Machine::snapshot`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MachineSnapshot {
+    /// machine state
+    pub state: State,
+    /// tape head positions
+    pub tapes: Vec<i32>,
+}
+
+/// How the rules of a program use one tape, see [`Program::tape_usage`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TapeUsage {
+    /// some rule moves the tape head up
+    pub increments: bool,
+    /// some rule moves the tape head down
+    pub decrements: bool,
+    /// indices of the rules that move the tape head, in program order
+    pub touched_by: Vec<usize>,
+}
+
+/// Statistics gathered over a run, see [`interpret_with_stats`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RunStats {
+    /// highest position each tape head reached, including the initial configuration
+    pub max_tape: Vec<i32>,
+    /// number of rules that fired
+    pub total_steps: u64,
+    /// number of times each rule fired, indexed by rule position in the program
+    pub firings_per_rule: Vec<u64>,
+}
+
+/// A difference between two machines, see [`diff_machines`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TapeDiff {
+    /// the machines are in different states
+    State { a: State, b: State },
+    /// the head of the given tape is at different positions
+    Tape { tape: TapeId, a: i32, b: i32 },
+}
+
+/// Outcome of a single interpreter step, see [`Machine::step`]
+#[derive(Debug, Eq, PartialEq)]
+pub enum StepResult {
+    /// the rule at the given index in the program fired
+    Fired(usize),
+    /// no rule in the program applies, the machine has halted
+    Halted,
+    /// the rule at the given index does not match the number of tapes in the machine
+    BadClause(usize),
+    /// the rule at the given index applies, but firing it would overflow the given tape
+    Overflow(usize, TapeId),
+}
+
+/// Policy for choosing which rule fires when several apply, see [`interpret_with_strategy`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// fire the first applicable rule in program order, as [`interpret`] does
+    FirstMatch,
+    /// fire the last applicable rule in program order
+    LastMatch,
+    /// fire the applicable rule with the largest total absolute adjustment, the first such rule
+    /// in program order on ties
+    MaxAdjustment,
+}
+
+/// Numeric type of tape head positions
+///
+/// The interpreter only needs to compare positions and move them by the adjustments of a rule,
+/// so the same guard and action logic works for `i32` tapes and, with the `bigint` feature, for
+/// the arbitrary precision tapes in the `big` module.
+pub trait TapeValue: Clone + Ord {
+    /// Convert an adjustment or a bound into a tape position
+    fn from_i32(n: i32) -> Self;
+    /// Return the position moved by `amt`, or `None` if it cannot be represented
+    fn checked_adjust(&self, amt: i32) -> Option<Self>;
+}
+
+/// Define a newtype around `usize` for a kind of index, convertible from and to `usize` and
+/// comparable with plain integers.
+macro_rules! index_newtype {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+        pub struct $name(pub usize);
+
+        impl From<usize> for $name {
+            fn from(n: usize) -> Self {
+                $name(n)
+            }
+        }
+
+        impl From<$name> for usize {
+            fn from(n: $name) -> Self {
+                n.0
+            }
+        }
+
+        impl PartialEq<usize> for $name {
+            fn eq(&self, other: &usize) -> bool {
+                self.0 == *other
+            }
+        }
+
+        /// Displayed as the plain number
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
 }
 
-/// Machine states are non-negative integers
-pub type State = usize;
+index_newtype!(
+    /// Machine states are non-negative integers
+    ///
+    /// Functions taking a state accept anything that converts into one, such as a `usize`.
+    State
+);
 
-/// Tapes are identified using non-negative integers
-pub type TapeId = usize;
+index_newtype!(
+    /// Tapes are identified using non-negative integers
+    ///
+    /// Functions taking a tape accept anything that converts into one, such as a `usize`.
+    TapeId
+);
 
 /// Tape state is a tape head position (non-negative integer) for each tape
-#[derive(Debug)]
-pub struct TapeState(pub Vec<i32>);
+///
+/// A tape state dereferences to a slice of head positions, so it can be indexed by tape and
+/// iterated over like one.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TapeState(Vec<i32>);
 
 /// A Magnificent Minsky Machine
-#[derive(Debug)]
+///
+/// With the `serde` feature a deserialized machine is checked like [`Machine::try_new`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "MachineFields")
+)]
 pub struct Machine {
     machine_state: State,
     tape_state: TapeState,
 }
 
+/// The serialized fields of a [`Machine`], checked when they are converted into one
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct MachineFields {
+    machine_state: State,
+    tape_state: Vec<i32>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<MachineFields> for Machine {
+    type Error = String;
+
+    fn try_from(fields: MachineFields) -> Result<Self, String> {
+        Machine::try_new(fields.machine_state, fields.tape_state)
+    }
+}
+
 /// A Rule, part of a Minsky Machine program
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Rule {
     // current state that the rule applies to
     cur_state: State,
@@ -59,16 +321,126 @@ pub struct Rule {
     // Rule clause specifies decrements and increments to make to the tape state, provided that
     // the decrements can actually be made without passing bottom on any tape.
     rule: Vec<i32>,
+    // Message of a probe rule, which never fires, see `Rule::probe`
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    probe: Option<String>,
 }
 
 /// A program consists of a number of tapes and a list of rules
-#[derive(Debug)]
+///
+/// With the `serde` feature a deserialized program is checked like one built with
+/// [`Program::try_new`] and the `with_*` methods, and must pass [`Program::validate`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "ProgramFields")
+)]
 pub struct Program {
     // Number of tapes used in the program. This value must match the size of the machine's
     // TapeState.
     num_tapes: usize,
     // Ordered sequence of rules that make up the program
     rules: Vec<Rule>,
+    // Optional upper bound on the head position of each tape. Either empty, if no tape is
+    // bounded, or one entry per tape.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    bounds: Vec<Option<i32>>,
+    // Optional name of each tape, used only for diagnostics. Either empty or one entry per tape.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    tape_names: Vec<String>,
+    // Optional state the program is meant to be started in
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    start_state: Option<State>,
+    // States in which halting counts as success, empty if the program declares none
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeSet::is_empty")
+    )]
+    accept_states: BTreeSet<State>,
+}
+
+/// The serialized fields of a [`Program`], checked when they are converted into one
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct ProgramFields {
+    num_tapes: usize,
+    rules: Vec<Rule>,
+    #[serde(default)]
+    bounds: Vec<Option<i32>>,
+    #[serde(default)]
+    tape_names: Vec<String>,
+    #[serde(default)]
+    start_state: Option<State>,
+    #[serde(default)]
+    accept_states: BTreeSet<State>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ProgramFields> for Program {
+    type Error = String;
+
+    fn try_from(fields: ProgramFields) -> Result<Self, String> {
+        let quoted = fields.rules.iter().position(|r| {
+            r.probe_message()
+                .is_some_and(|m| m.contains(['"', '\n', '\r']))
+        });
+        if let Some(idx) = quoted {
+            return Err(format!(
+                "probe message of rule {} cannot be written as m3",
+                idx
+            ));
+        }
+        let mut program = Program::try_new(fields.num_tapes, fields.rules)?;
+        program.validate()?;
+        if !fields.bounds.is_empty() {
+            program = program.with_bounds(fields.bounds)?;
+        }
+        if !fields.tape_names.is_empty() {
+            program = program.with_tape_names(fields.tape_names)?;
+        }
+        if let Some(start) = fields.start_state {
+            program = program.with_start_state(start)?;
+        }
+        program.with_accept_states(fields.accept_states)
+    }
+}
+
+/// Build a [`Program`] from rules written like m3 rules, with the next state before the
+/// adjustments.
+///
+/// ```text
+/// program! {
+///     tapes: 2;
+///     0 => 0 [1, -1];
+///     0 => 1 [0, 0];
+/// }
+/// ```
+///
+/// is the program with the rules `Rule::new(0, 0, vec![1, -1])` and `Rule::new(0, 1, vec![0,
+/// 0])`. States are literals or parenthesized expressions, adjustments are expressions. The
+/// rules are checked with [`Program::try_new`] and the macro panics if one of them does not
+/// adjust exactly `tapes` tapes.
+#[macro_export]
+macro_rules! program {
+    (tapes: $n:expr; $($cur:tt => $next:tt [$($adj:expr),* $(,)?]);* $(;)?) => {{
+        // parenthesized states are passed on as they are
+        #[allow(unused_parens)]
+        let rules = [$($crate::magnificent::Rule::new($cur, $next, [$($adj),*].to_vec())),*];
+        $crate::magnificent::Program::try_new($n, rules.to_vec()).expect("invalid program")
+    }};
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -77,14 +449,54 @@ pub struct Program {
 impl Rule {
     /// Create a new Rule by specifying the current state it should fire in, the next state the
     /// machine should transition to, and the tape head adjustments.
-    pub fn new(cur_state: State, next_state: State, rule: Vec<i32>) -> Self {
+    pub fn new(cur_state: impl Into<State>, next_state: impl Into<State>, rule: Vec<i32>) -> Self {
         Rule {
-            cur_state,
-            next_state,
+            cur_state: cur_state.into(),
+            next_state: next_state.into(),
             rule,
+            probe: None,
+        }
+    }
+
+    /// Create a probe rule, which reports `message` instead of firing.
+    ///
+    /// A probe is checked like any other rule, in program order, and is satisfied when the
+    /// machine is in `state` and the guard, the negative entries of `guard`, can be taken from
+    /// the tapes. It then hands its message to the callback of [`interpret_with_probes`] but
+    /// moves no tape and keeps the state, and the interpreter goes on to the next rule. So a
+    /// probe is not a step and cannot keep a machine from halting. Positive entries of `guard`
+    /// are ignored.
+    ///
+    /// Panics if the message contains a double quote or a line break, since it could not be
+    /// written back as m3.
+    pub fn probe(state: impl Into<State>, guard: Vec<i32>, message: impl Into<String>) -> Self {
+        let message = message.into();
+        assert!(
+            !message.contains(['"', '\n', '\r']),
+            "probe message {:?} cannot be written as m3",
+            message
+        );
+        let state = state.into();
+        Rule {
+            cur_state: state,
+            next_state: state,
+            rule: guard,
+            probe: Some(message),
         }
     }
 
+    /// Return the message of a probe rule, or `None` if the rule is not a probe.
+    pub fn probe_message(&self) -> Option<&str> {
+        self.probe.as_deref()
+    }
+
+    /// Return whether the rule is a probe.
+    ///
+    /// A probe never fires, so analyses and transformations of what a program does skip it.
+    pub fn is_probe(&self) -> bool {
+        self.probe.is_some()
+    }
+
     /// Return the state that this rule fires in.
     pub fn cur_state(&self) -> State {
         self.cur_state
@@ -107,22 +519,165 @@ impl Rule {
     }
 
     /// Iterate over the tape head adjustments that the rule specifies
-    pub fn iter(&self) -> Iter<i32> {
+    pub fn iter(&self) -> Iter<'_, i32> {
         self.rule.iter()
     }
-}
 
-impl TapeState {
-    /// Examine a tape state and determine whether a rule is satisfied, i.e. can the tapes be
-    /// moved backwards by the amounts specified in the rule?
+    /// Return the net change of every tape head position when the rule fires.
     ///
-    /// This method assumes that the number of tapes and the size of the rule are equal.
-    fn test_rule(&self, rule: &Rule) -> bool {
-        assert!(self.0.len() == rule.rule.len());
-        self.0
+    /// This is the full adjustment vector, guard and action together, as used when reasoning
+    /// about how positions evolve over a run rather than about when the rule may fire.
+    pub fn net_effect(&self) -> &[i32] {
+        &self.rule
+    }
+
+    /// Fuse rule `a` and a rule `b` that fires right after it into a single rule.
+    ///
+    /// The fused rule fires where `a` does, moves every tape by the sum of both adjustments, and
+    /// transitions to the next state of `b`. This is only sound if `b` fires whenever `a` has
+    /// fired, so `None` is returned unless `b` fires in the next state of `a` and has no guard.
+    /// The sum must also keep the guard of `a`, so `None` is returned if `b` moves up a tape that
+    /// `a` moves down, since the fused rule could then fire where `a` cannot. It is also returned
+    /// if the rules have different widths or a summed adjustment overflows.
+    /// The caller must still make sure that `b` is the first rule of its state, and that its
+    /// action cannot push a bounded tape past its bound, since neither depends on the rules
+    /// alone.
+    pub fn compose(a: &Rule, b: &Rule) -> Option<Rule> {
+        if a.next_state != b.cur_state || a.len() != b.len() || !b.guard().is_empty() {
+            return None;
+        }
+        if a.is_probe() || b.is_probe() {
+            return None;
+        }
+        if a.iter().zip(b.iter()).any(|(x, y)| *x < 0 && *y > 0) {
+            return None;
+        }
+        let rule = a.iter().zip(b.iter()).map(|(x, y)| x.checked_add(*y));
+        Some(Rule::new(
+            a.cur_state,
+            b.next_state,
+            rule.collect::<Option<_>>()?,
+        ))
+    }
+
+    /// Return the guard of the rule, i.e. the negative tape head adjustments along with the tapes
+    /// they apply to.
+    pub fn guard(&self) -> Vec<(TapeId, i32)> {
+        self.adjustments_where(|a| a < 0)
+    }
+
+    /// Return the action of the rule, i.e. the positive tape head adjustments along with the
+    /// tapes they apply to.
+    pub fn action(&self) -> Vec<(TapeId, i32)> {
+        self.adjustments_where(|a| a > 0)
+    }
+
+    /// Check the rule for degenerate forms that are valid but almost certainly a mistake.
+    ///
+    /// Currently this flags a rule that fires in its own next state without adjusting any tape:
+    /// once it fires nothing changes, so it fires again forever. An all-zero rule that changes
+    /// state is fine, and so is a probe, which never fires.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.is_probe()
+            && self.cur_state == self.next_state
+            && self.rule.iter().all(|a| *a == 0)
+        {
+            return Err(format!(
+                "rule `{}` does not change the machine and loops forever once it fires",
+                self
+            ));
+        }
+        Ok(())
+    }
+
+    fn adjustments_where<P: Fn(i32) -> bool>(&self, pred: P) -> Vec<(TapeId, i32)> {
+        self.rule
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| pred(**a))
+            .map(|(t, a)| (TapeId(t), *a))
+            .collect()
+    }
+}
+
+impl TapeValue for i32 {
+    fn from_i32(n: i32) -> Self {
+        n
+    }
+
+    fn checked_adjust(&self, amt: i32) -> Option<Self> {
+        self.checked_add(amt)
+    }
+}
+
+/// Examine tape head positions and determine whether a rule is satisfied, i.e. can the tapes be
+/// moved backwards by the amounts specified in the rule?
+///
+/// Tapes with an upper bound in `bounds` must also stay at or below the bound after the rule is
+/// applied. Tapes without an entry in `bounds` are unbounded.
+///
+/// This function assumes that the number of tapes and the size of the rule are equal.
+fn test_tapes<T: TapeValue>(tapes: &[T], rule: &Rule, bounds: &[Option<i32>]) -> bool {
+    assert!(tapes.len() == rule.rule.len());
+    let guard_ok = tapes.iter().zip(rule.iter()).all(|(tp, amt)| {
+        // `-amt` overflows for `i32::MIN`, so test the position after the move instead
+        *amt >= 0 || tp.checked_adjust(*amt).is_some_and(|p| p >= T::from_i32(0))
+    });
+    guard_ok
+        && tapes
             .iter()
             .zip(rule.iter())
-            .all(|(tp, amt)| *amt >= 0 || *tp >= -(*amt))
+            .zip(bounds.iter())
+            .all(|((tp, amt), bound)| {
+                bound.map_or(true, |b| {
+                    *amt <= 0 || tp.checked_adjust(*amt).is_some_and(|p| p <= T::from_i32(b))
+                })
+            })
+}
+
+/// Return the first tape whose head position would overflow if `rule` were applied.
+fn overflowing_tape<T: TapeValue>(tapes: &[T], rule: &Rule) -> Option<TapeId> {
+    tapes
+        .iter()
+        .zip(rule.iter())
+        .position(|(tp, amt)| tp.checked_adjust(*amt).is_none())
+        .map(TapeId)
+}
+
+/// Apply the decrements/increments given in `rule` to the tape head positions.
+///
+/// `test_tapes` and `overflowing_tape` must always be called first, this function does not check
+/// that the decrements can be made safely and panics if a tape overflows.
+fn apply_tapes<T: TapeValue>(tapes: &mut [T], rule: &Rule) {
+    for (tp, amt) in tapes.iter_mut().zip(rule.iter()) {
+        *tp = tp.checked_adjust(*amt).expect("tape overflow");
+    }
+}
+
+impl TapeState {
+    /// Create a tape state from the head position of each tape.
+    pub fn new(positions: Vec<i32>) -> Self {
+        TapeState(positions)
+    }
+
+    /// Return the head positions as a slice.
+    pub fn as_slice(&self) -> &[i32] {
+        &self.0
+    }
+
+    /// Consume the tape state and return the head positions.
+    pub fn into_inner(self) -> Vec<i32> {
+        self.0
+    }
+
+    /// Determine whether a rule is satisfied by the tape state, see `test_tapes`.
+    fn test_rule(&self, rule: &Rule, bounds: &[Option<i32>]) -> bool {
+        test_tapes(&self.0, rule, bounds)
+    }
+
+    /// Return the first tape that would overflow if `rule` were applied.
+    fn overflowing_tape(&self, rule: &Rule) -> Option<TapeId> {
+        overflowing_tape(&self.0, rule)
     }
 
     /// Apply the decrements/increments given in `rule` to `self`.
@@ -132,9 +687,7 @@ impl TapeState {
     /// This method assumes that self.0 and rule.rule are the same length, it does not examine the
     /// current state of `rule`, and it does not check that the decrements can be made safely.
     fn apply_rule(&mut self, rule: &Rule) {
-        for (t, amt) in self.0.iter_mut().zip(rule.iter()) {
-            *t += *amt;
-        }
+        apply_tapes(&mut self.0, rule)
     }
 
     /// Check that the tape positions are all non-negative
@@ -143,199 +696,3379 @@ impl TapeState {
     }
 }
 
-impl Program {
-    /// Create a new program by specifying the number of tapes it operates on andf the ordered
-    /// sequence of rules to apply.
-    pub fn new(num_tapes: usize, rules: Vec<Rule>) -> Self {
-        Program { num_tapes, rules }
+impl From<Vec<i32>> for TapeState {
+    fn from(positions: Vec<i32>) -> Self {
+        TapeState(positions)
     }
+}
 
-    /// Return the number of tapes the program operates on.
-    pub fn num_tapes(&self) -> usize {
-        self.num_tapes
+impl Deref for TapeState {
+    type Target = [i32];
+
+    fn deref(&self) -> &[i32] {
+        &self.0
     }
+}
 
-    /// Return the number of rules.
-    pub fn num_rules(&self) -> usize {
-        self.rules.len()
+impl Index<usize> for TapeState {
+    type Output = i32;
+
+    fn index(&self, tape: usize) -> &i32 {
+        &self.0[tape]
     }
+}
 
-    /// Iterate over the rules in the program in order.
-    pub fn iter(&self) -> Iter<Rule> {
-        self.rules.iter()
+impl IndexMut<usize> for TapeState {
+    fn index_mut(&mut self, tape: usize) -> &mut i32 {
+        &mut self.0[tape]
     }
 }
 
-impl Machine {
-    /// Create a new machine given an initial machine state and tape head positions.
-    pub fn new(machine_state: usize, tape_state: Vec<i32>) -> Self {
-        Machine {
-            machine_state,
-            tape_state: TapeState(tape_state),
+impl IntoIterator for TapeState {
+    type Item = i32;
+    type IntoIter = alloc::vec::IntoIter<i32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TapeState {
+    type Item = &'a i32;
+    type IntoIter = Iter<'a, i32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut TapeState {
+    type Item = &'a mut i32;
+    type IntoIter = IterMut<'a, i32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
+
+impl Program {
+    /// Create a new program by specifying the number of tapes it operates on andf the ordered
+    /// sequence of rules to apply.
+    pub fn new(num_tapes: usize, rules: Vec<Rule>) -> Self {
+        Program {
+            num_tapes,
+            rules,
+            bounds: Vec::new(),
+            tape_names: Vec::new(),
+            start_state: None,
+            accept_states: BTreeSet::new(),
         }
     }
 
-    /// Try to apply the give rule to the machine.
+    /// Set an upper bound on the head position of each tape, `None` meaning unbounded.
     ///
-    /// If the rule's guard is satisfied, move the tapes in the guard backwards and the tapes in
-    /// the action forward. Then update the machine's state. If successful, return `true`,
-    /// otherwise `false`.
-    pub fn apply_rule(&mut self, rule: &Rule) -> bool {
-        if self.machine_state == rule.cur_state && self.tape_state.test_rule(&rule) {
-            self.tape_state.apply_rule(&rule);
-            assert!(self.tape_state.is_valid());
-            self.machine_state = rule.next_state;
-            return true;
+    /// A rule whose action would move a tape head above its bound does not fire, just as if its
+    /// guard were not satisfied. Returns an error if there is not exactly one entry per tape or
+    /// a bound is negative.
+    pub fn with_bounds(mut self, bounds: Vec<Option<i32>>) -> Result<Self, String> {
+        if bounds.len() != self.num_tapes {
+            return Err(format!(
+                "{} bounds given but program declares {} tapes",
+                bounds.len(),
+                self.num_tapes
+            ));
         }
-        false
+        if let Some(tape) = bounds.iter().position(|b| b.is_some_and(|b| b < 0)) {
+            return Err(format!("bound on tape {} is negative", tape));
+        }
+        self.bounds = if bounds.iter().all(Option::is_none) {
+            Vec::new()
+        } else {
+            bounds
+        };
+        Ok(self)
     }
 
-    /// Return the current tape head position for the indicated tape.
-    pub fn tape_pos(&self, id: usize) -> i32 {
-        self.tape_state.0[id]
+    /// Return the upper bound on the head position of the given tape, if any.
+    pub fn bound(&self, tape: impl Into<TapeId>) -> Option<i32> {
+        self.bounds.get(tape.into().0).copied().flatten()
     }
-}
 
-/// Interpret the given program starting with the initial machine.
-///
-/// Try to apply rules in the program in the order they appear.
-///   - When a rule applies, apply it and start over from the first rule in the program.
-///   - When no rules apply to a given machine, halt and return the machine.
-pub fn interpret(
-    initial_machine: Machine,
-    program: &Program,
-    fuel: u64,
-) -> Result<(u64, Machine), ErrorCode> {
-    let mut machine = initial_machine;
-    let mut counter: u64 = 0;
-    loop {
-        let mut changed = false;
-        println!("{}: {:?}", machine.machine_state, machine.tape_state);
-        for rule in program.iter() {
-            if machine.apply_rule(&rule) {
-                changed = true;
-                counter += 1;
-                break;
-            }
-        }
-        if !changed {
-            return Ok((counter, machine));
+    /// Name the tapes of the program, e.g. `acc` for an accumulator.
+    ///
+    /// Names are only used for diagnostics such as traces and [`to_dot`], they do not affect
+    /// interpretation. Returns an error if there is not exactly one name per tape or a name is
+    /// used twice.
+    pub fn with_tape_names(mut self, names: Vec<String>) -> Result<Self, String> {
+        if names.len() != self.num_tapes {
+            return Err(format!(
+                "{} tape names given but program declares {} tapes",
+                names.len(),
+                self.num_tapes
+            ));
         }
-        if counter >= fuel {
-            return Err(ErrorCode::OutOfFuel);
+        if let Some((i, name)) = names
+            .iter()
+            .enumerate()
+            .find(|(i, name)| names[..*i].contains(name))
+        {
+            return Err(format!("tape {} reuses the name `{}`", i, name));
         }
+        self.tape_names = names;
+        Ok(self)
     }
-}
 
-//////////////////////////////////////////////////////////////////////////////
-// Tests
+    /// Declare the state the program is meant to be started in.
+    ///
+    /// This only documents the entry point of the program, e.g. as the default initial state of
+    /// a machine, the interpreter runs from whatever state a machine is in. Returns an error if no
+    /// rule fires in `state`, probes do not count since they never fire.
+    pub fn with_start_state(mut self, state: impl Into<State>) -> Result<Self, String> {
+        let state = state.into();
+        if !self
+            .rules
+            .iter()
+            .any(|r| r.cur_state == state && !r.is_probe())
+        {
+            return Err(format!(
+                "start state {} is not the current state of any rule",
+                state
+            ));
+        }
+        self.start_state = Some(state);
+        Ok(self)
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Return the declared start state of the program, if any.
+    pub fn start_state(&self) -> Option<State> {
+        self.start_state
+    }
 
-    #[test]
-    fn test_basic() {
-        let rule = Rule::new(0, 0, vec![-1, -2, 3, 4]);
-        assert_eq!(rule.cur_state, 0);
-        assert_eq!(rule.next_state, 0);
-        assert_eq!(rule.rule, vec![-1, -2, 3, 4]);
+    /// Declare the states in which halting counts as success.
+    ///
+    /// Without accept states a machine that halts has either finished or got stuck, and there
+    /// is no telling which. With them [`interpret`] reports `HaltReason::Accepted` for a machine
+    /// that halts in an accept state, so the program can be used as a recognizer. Returns an
+    /// error if a state does not appear in the program.
+    pub fn with_accept_states<S: Into<State>>(
+        mut self,
+        states: impl IntoIterator<Item = S>,
+    ) -> Result<Self, String> {
+        let known = self.states();
+        let states: BTreeSet<State> = states.into_iter().map(Into::into).collect();
+        if let Some(state) = states.iter().find(|s| !known.contains(s)) {
+            return Err(format!(
+                "accept state {} does not appear in the program",
+                state
+            ));
+        }
+        self.accept_states = states;
+        Ok(self)
     }
 
-    #[test]
-    fn test_test_rule() {
-        let tape_state: TapeState = TapeState(vec![2, 2]);
-        let rule0 = Rule::new(0, 0, vec![0, -1]);
-        let rule1 = Rule::new(0, 0, vec![0, 5]);
-        let rule2 = Rule::new(0, 0, vec![2, 0]);
-        let rule3 = Rule::new(0, 0, vec![3, 3]);
-        let false_rule = Rule::new(0, 0, vec![-7, 0]);
-        assert!(tape_state.test_rule(&rule0));
-        assert!(tape_state.test_rule(&rule1));
-        assert!(tape_state.test_rule(&rule2));
-        assert!(tape_state.test_rule(&rule3));
-        assert!(!tape_state.test_rule(&false_rule));
+    /// Return the declared accept states of the program, empty if there are none.
+    pub fn accept_states(&self) -> &BTreeSet<State> {
+        &self.accept_states
     }
 
-    #[test]
-    #[should_panic]
-    fn test_bad_rule() {
-        let tape_state: TapeState = TapeState(vec![2, 2]);
-        let wide_rule = Rule::new(0, 0, vec![0, 1, 2]);
-        let _b = tape_state.test_rule(&wide_rule);
+    /// Return whether `state` is one of the program's accept states.
+    pub fn is_accepting(&self, state: impl Into<State>) -> bool {
+        self.accept_states.contains(&state.into())
     }
 
-    #[test]
-    fn test_move_tapes() {
-        // Test move_backwards
-        let mut tape_state: TapeState = TapeState(vec![2, 2]);
-        let rule = Rule::new(0, 0, vec![-1, -1]);
-        tape_state.apply_rule(&rule);
-        assert_eq!(tape_state.0[0], 1);
-        assert_eq!(tape_state.0[1], 1);
+    /// Return the names of the tapes, empty if the tapes are not named.
+    pub fn tape_names(&self) -> &[String] {
+        &self.tape_names
+    }
 
-        // Test move_forwards
-        let rule = Rule::new(0, 0, vec![1, 9]);
-        tape_state.apply_rule(&rule);
-        assert_eq!(tape_state.0[0], 2);
-        assert_eq!(tape_state.0[1], 10);
+    /// Return the name of the given tape if it has one, and `tape[i]` otherwise.
+    pub fn tape_label(&self, tape: impl Into<TapeId>) -> String {
+        let tape = tape.into();
+        match self.tape_names.get(tape.0) {
+            Some(name) => name.clone(),
+            None => format!("tape[{}]", tape),
+        }
     }
 
-    #[test]
-    fn test_apply_rule() {
-        let tape_state: TapeState = TapeState(vec![0, 0]);
-        // Rule 0: no guard, no machine state transition
-        let rule0 = Rule::new(0, 0, vec![1, 1]);
-        // Rule 1: move tape 0 back 1, tape 1 forward 2, transition to new machine state
-        let rule1 = Rule::new(0, 1, vec![-1, 2]);
-        // Rule 2: move backwards only, preserve machine state
-        let rule2 = Rule::new(1, 1, vec![-2, -2]);
+    /// Create a new program like `new`, checking that every rule adjusts exactly `num_tapes`
+    /// tapes.
+    pub fn try_new(num_tapes: usize, rules: Vec<Rule>) -> Result<Self, String> {
+        let mut program = Program::new(num_tapes, Vec::with_capacity(rules.len()));
+        for rule in rules {
+            program.push_rule(rule)?;
+        }
+        Ok(program)
+    }
 
-        // Initial machine state
+    /// Check the program for mistakes.
+    ///
+    /// A program without tapes is rejected, as is a rule that does not adjust exactly
+    /// `num_tapes` tapes. On success the warnings about degenerate but valid forms are returned:
+    /// a program without rules, which halts immediately on every machine, and the warnings from
+    /// [`Rule::validate`] for each rule.
+    pub fn validate(&self) -> Result<Vec<String>, String> {
+        if self.num_tapes == 0 {
+            return Err("program declares 0 tapes but needs at least one".to_string());
+        }
+        let mut warnings = Vec::new();
+        if self.rules.is_empty() {
+            warnings.push("program has no rules and halts immediately".to_string());
+        }
+        for (idx, rule) in self.rules.iter().enumerate() {
+            if rule.len() != self.num_tapes {
+                return Err(format!(
+                    "rule {} has {} adjustments but program declares {} tapes",
+                    idx,
+                    rule.len(),
+                    self.num_tapes
+                ));
+            }
+            if let Err(warning) = rule.validate() {
+                warnings.push(format!("rule {}: {}", idx, warning));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Append a rule to the end of the program.
+    ///
+    /// Returns an error if the rule does not adjust exactly `num_tapes` tapes.
+    pub fn push_rule(&mut self, rule: Rule) -> Result<(), String> {
+        if rule.len() != self.num_tapes {
+            return Err(format!(
+                "rule {} has {} adjustments but program declares {} tapes",
+                self.rules.len(),
+                rule.len(),
+                self.num_tapes
+            ));
+        }
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    /// Concatenate two programs, shifting all states of `b` by `offset`.
+    ///
+    /// The rules of `a` come first, followed by the shifted rules of `b`. Choosing an offset
+    /// greater than every state of `a`, e.g. one more than the largest state in `a.states()`,
+    /// keeps the states of the two programs apart, so `b` can be run after `a` by adding a rule
+    /// that transitions from a state of `a` to a shifted state of `b`.
+    ///
+    /// Returns an error if the programs operate on different numbers of tapes or both declare
+    /// different tape bounds. If only one of them is bounded the result has its bounds. The
+    /// tape names and start state of `a` are kept if it has any, otherwise those of `b`. The
+    /// accept states of both programs are accept states of the result.
+    pub fn merge(a: &Program, b: &Program, offset: impl Into<State>) -> Result<Program, String> {
+        let offset = offset.into().0;
+        if a.num_tapes != b.num_tapes {
+            return Err(format!(
+                "cannot merge programs with {} and {} tapes",
+                a.num_tapes, b.num_tapes
+            ));
+        }
+        if !a.bounds.is_empty() && !b.bounds.is_empty() && a.bounds != b.bounds {
+            return Err("cannot merge programs with different tape bounds".to_string());
+        }
+        let shifted = b.rules.iter().map(|r| Rule {
+            cur_state: State(r.cur_state.0 + offset),
+            next_state: State(r.next_state.0 + offset),
+            rule: r.rule.clone(),
+            probe: r.probe.clone(),
+        });
+        let bounds = if a.bounds.is_empty() {
+            b.bounds.clone()
+        } else {
+            a.bounds.clone()
+        };
+        let tape_names = if a.tape_names.is_empty() {
+            b.tape_names.clone()
+        } else {
+            a.tape_names.clone()
+        };
+        let start_state = a
+            .start_state
+            .or_else(|| b.start_state.map(|s| State(s.0 + offset)));
+        let b_accept = b.accept_states.iter().map(|s| State(s.0 + offset));
+        Ok(Program {
+            num_tapes: a.num_tapes,
+            rules: a.rules.iter().cloned().chain(shifted).collect(),
+            bounds,
+            tape_names,
+            start_state,
+            accept_states: a.accept_states.iter().copied().chain(b_accept).collect(),
+        })
+    }
+
+    /// Return the number of tapes the program operates on.
+    pub fn num_tapes(&self) -> usize {
+        self.num_tapes
+    }
+
+    /// Return the number of rules.
+    pub fn num_rules(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Iterate over the rules in the program in order.
+    pub fn iter(&self) -> Iter<'_, Rule> {
+        self.rules.iter()
+    }
+
+    /// Render the program as m3 source text.
+    ///
+    /// The output consists of a `tapes:` header followed by one rule per line, and can be read
+    /// back in with [`m3_parser::parse_m3`].
+    ///
+    /// [`m3_parser::parse_m3`]: crate::m3_parser::parse_m3
+    pub fn to_m3_string(&self) -> String {
+        self.to_string()
+    }
+
+    /// Return the set of states that appear in the program, either as a state that a rule fires
+    /// in or as a state that a rule transitions to.
+    pub fn states(&self) -> BTreeSet<State> {
+        self.rules
+            .iter()
+            .flat_map(|r| vec![r.cur_state, r.next_state])
+            .collect()
+    }
+
+    /// Return the number of distinct states that appear in the program, see [`Program::states`].
+    pub fn num_states(&self) -> usize {
+        self.states().len()
+    }
+
+    /// Map each state of the program to its position among the sorted states.
+    ///
+    /// The new states are `0..num_states` and the map preserves the order of the states, so a
+    /// program whose states are already dense is mapped onto itself.
+    pub fn state_renumbering(&self) -> BTreeMap<State, State> {
+        self.states().into_iter().zip((0..).map(State)).collect()
+    }
+
+    /// Return a copy of the program with its states renumbered to `0..num_states`, see
+    /// [`Program::state_renumbering`].
+    ///
+    /// The renumbered program behaves like the original: a machine started in state `s` of the
+    /// original program runs the same as one started in the image of `s`. The start and accept
+    /// states are renumbered along with the rules.
+    pub fn renumber_states(&self) -> Program {
+        self.renumber_with(&self.state_renumbering())
+    }
+
+    /// Return a copy of the program without its probe rules, see [`Rule::probe`].
+    ///
+    /// Probes never change a machine, so the result runs exactly like the original. Use this
+    /// before handing a program to a transformation that would treat probes as ordinary rules.
+    /// Accept states that only appeared in probes are dropped, as is a start state that no
+    /// longer has a rule.
+    pub fn without_probes(&self) -> Program {
+        let rules: Vec<Rule> = self
+            .rules
+            .iter()
+            .filter(|r| !r.is_probe())
+            .cloned()
+            .collect();
+        let appears = |s: &State| {
+            rules
+                .iter()
+                .any(|r| r.cur_state == *s || r.next_state == *s)
+        };
+        Program {
+            start_state: self
+                .start_state
+                .filter(|s| rules.iter().any(|r| r.cur_state == *s)),
+            accept_states: self.accept_states.iter().copied().filter(appears).collect(),
+            rules,
+            ..self.clone()
+        }
+    }
+
+    /// Map each state of the program to its position in order of first appearance.
+    ///
+    /// The start state comes first, then the states of the rules in order, each rule's current
+    /// state before its next state, and last the accept states in ascending order, which only
+    /// adds those that no rule mentions. Unlike [`Program::state_renumbering`] the result does
+    /// not depend on how the states were numbered, only on where they occur.
+    fn first_appearance_renumbering(&self) -> BTreeMap<State, State> {
+        let mut map = BTreeMap::new();
+        let states = self
+            .start_state
+            .iter()
+            .chain(
+                self.rules
+                    .iter()
+                    .flat_map(|r| [&r.cur_state, &r.next_state]),
+            )
+            .chain(self.accept_states.iter());
+        for s in states {
+            let next = State(map.len());
+            map.entry(*s).or_insert(next);
+        }
+        map
+    }
+
+    /// Apply a state renumbering to the rules, start state, and accept states.
+    fn renumber_with(&self, map: &BTreeMap<State, State>) -> Program {
+        let rules = self
+            .rules
+            .iter()
+            .map(|r| Rule {
+                cur_state: map[&r.cur_state],
+                next_state: map[&r.next_state],
+                rule: r.rule.clone(),
+                probe: r.probe.clone(),
+            })
+            .collect();
+        Program {
+            rules,
+            start_state: self.start_state.map(|s| map[&s]),
+            accept_states: self.accept_states.iter().map(|s| map[s]).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Return the transition graph of the program as a matrix.
+    ///
+    /// The states are renumbered to `0..num_states` as by [`Program::state_renumbering`], and
+    /// entry `[i][j]` counts the rules that transition from state `i` to state `j`.
+    pub fn adjacency_matrix(&self) -> Vec<Vec<u32>> {
+        let map = self.state_renumbering();
+        let mut matrix = vec![vec![0; map.len()]; map.len()];
+        for rule in self.rules.iter() {
+            matrix[map[&rule.cur_state].0][map[&rule.next_state].0] += 1;
+        }
+        matrix
+    }
+
+    /// Return the strongly connected components of the transition graph.
+    ///
+    /// Two states are in the same component if each can be reached from the other, so every
+    /// loop of the program lies within one component. A state that is not on any loop forms a
+    /// component of its own. The states of a component are sorted, and the components are in
+    /// reverse topological order: no rule leads from a component to one listed after it.
+    pub fn sccs(&self) -> Vec<Vec<State>> {
+        let states: Vec<State> = self.states().into_iter().collect();
+        let matrix = self.adjacency_matrix();
+        let mut tarjan = Tarjan {
+            matrix: &matrix,
+            index: vec![None; states.len()],
+            lowlink: vec![0; states.len()],
+            on_stack: vec![false; states.len()],
+            stack: Vec::new(),
+            next_index: 0,
+            sccs: Vec::new(),
+        };
+        for v in 0..states.len() {
+            if tarjan.index[v].is_none() {
+                tarjan.visit(v);
+            }
+        }
+        tarjan
+            .sccs
+            .into_iter()
+            .map(|mut scc| {
+                scc.sort_unstable();
+                scc.into_iter().map(|v| states[v]).collect()
+            })
+            .collect()
+    }
+
+    /// Summarize for each tape which rules move it and in which direction.
+    ///
+    /// A tape that is only decremented is an input consumed by the program, one that is only
+    /// incremented an output, and one that is both scratch space. Rules with the wrong number of
+    /// adjustments are summarized as far as they go.
+    pub fn tape_usage(&self) -> Vec<TapeUsage> {
+        let mut usage = vec![TapeUsage::default(); self.num_tapes];
+        for (idx, rule) in self.rules.iter().enumerate() {
+            for (tape, a) in usage.iter_mut().zip(rule.iter()) {
+                if *a != 0 {
+                    tape.increments |= *a > 0;
+                    tape.decrements |= *a < 0;
+                    tape.touched_by.push(idx);
+                }
+            }
+        }
+        usage
+    }
+
+    /// Report the tapes that are moved in more than one phase of the program run from `start`.
+    ///
+    /// The phases are the components of [`Program::sccs`] that contain a state reachable from
+    /// `start`, see [`reachable_states`], and a phase id is the position of its component in
+    /// that list. A rule moves its tapes in the phase of the state it fires in, probes move
+    /// nothing. Phases usually compute independent steps of a larger program, so a tape shared
+    /// between them is often a scratch tape that was accidentally reused. Each tape is reported
+    /// with the sorted ids of the phases that move it, in order of the tapes.
+    pub fn tape_phase_report(&self, start: impl Into<State>) -> Vec<(TapeId, Vec<usize>)> {
+        let reachable = reachable_states(self, start);
+        let mut phase_of = BTreeMap::new();
+        for (phase, scc) in self.sccs().into_iter().enumerate() {
+            if scc.iter().any(|s| reachable.contains(s)) {
+                phase_of.extend(scc.into_iter().map(|s| (s, phase)));
+            }
+        }
+        self.tape_usage()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(tape, usage)| {
+                let mut phases: Vec<usize> = usage
+                    .touched_by
+                    .iter()
+                    .map(|idx| &self.rules[*idx])
+                    .filter(|rule| !rule.is_probe())
+                    .filter_map(|rule| phase_of.get(&rule.cur_state).copied())
+                    .collect();
+                phases.sort_unstable();
+                phases.dedup();
+                (phases.len() > 1).then_some((TapeId(tape), phases))
+            })
+            .collect()
+    }
+
+    /// Compute an index from each state to the positions of the rules that fire in it.
+    ///
+    /// The positions for each state are in increasing order, so trying them in order preserves
+    /// the first-applicable-rule semantics of the interpreter.
+    pub fn rules_by_state(&self) -> BTreeMap<State, Vec<usize>> {
+        let mut index: BTreeMap<State, Vec<usize>> = BTreeMap::new();
+        for (idx, rule) in self.rules.iter().enumerate() {
+            index.entry(rule.cur_state).or_default().push(idx);
+        }
+        index
+    }
+}
+
+/// Format a list of integers as an m3 vector, e.g. `[1, -1, 0]`
+fn fmt_vector<T: fmt::Display>(f: &mut fmt::Formatter, v: &[T]) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, a) in v.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", a)?;
+    }
+    write!(f, "]")
+}
+
+/// Rules are displayed in m3 syntax, e.g. `0 [1, -1] 1`.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", self.cur_state)?;
+        fmt_vector(f, &self.rule)?;
+        write!(f, " {}", self.next_state)?;
+        match &self.probe {
+            Some(message) => write!(f, " probe \"{}\"", message),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Programs are displayed as m3 source text, with one rule per line.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "tapes: {}", self.num_tapes)?;
+        if let Some(start) = self.start_state {
+            writeln!(f, "start: {}", start)?;
+        }
+        if !self.accept_states.is_empty() {
+            write!(f, "accept: ")?;
+            fmt_vector(f, &self.accept_states.iter().collect::<Vec<_>>())?;
+            writeln!(f)?;
+        }
+        if !self.bounds.is_empty() {
+            let bounds: Vec<String> = self
+                .bounds
+                .iter()
+                .map(|b| b.map_or("_".to_string(), |b| b.to_string()))
+                .collect();
+            write!(f, "bounds: ")?;
+            fmt_vector(f, &bounds)?;
+            writeln!(f)?;
+        }
+        if !self.tape_names.is_empty() {
+            write!(f, "names: ")?;
+            fmt_vector(f, &self.tape_names)?;
+            writeln!(f)?;
+        }
+        for rule in self.rules.iter() {
+            writeln!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine {
+    /// Create a new machine given an initial machine state and tape head positions.
+    pub fn new(machine_state: impl Into<State>, tape_state: Vec<i32>) -> Self {
+        Machine {
+            machine_state: machine_state.into(),
+            tape_state: TapeState::new(tape_state),
+        }
+    }
+
+    /// Create a new machine like `new`, checking that every tape head position is non-negative.
+    pub fn try_new(machine_state: impl Into<State>, tape_state: Vec<i32>) -> Result<Self, String> {
+        match tape_state.iter().position(|tp| *tp < 0) {
+            Some(tape) => Err(format!(
+                "tape {} has negative initial position {}",
+                tape, tape_state[tape]
+            )),
+            None => Ok(Machine::new(machine_state, tape_state)),
+        }
+    }
+
+    /// Put the machine back into the given state with the given tape head positions, so it can
+    /// be reused for another run, see [`interpret_mut`].
+    pub fn reset(&mut self, machine_state: impl Into<State>, tape_state: Vec<i32>) {
+        self.machine_state = machine_state.into();
+        self.tape_state.0.clear();
+        self.tape_state.0.extend_from_slice(&tape_state);
+    }
+
+    /// Take a snapshot of the machine that can later be turned back into a machine with
+    /// `restore`, e.g. to checkpoint a long run.
+    pub fn snapshot(&self) -> MachineSnapshot {
+        MachineSnapshot {
+            state: self.machine_state,
+            tapes: self.tape_state.to_vec(),
+        }
+    }
+
+    /// Create a machine from a snapshot taken with `snapshot`.
+    pub fn restore(snapshot: MachineSnapshot) -> Machine {
+        Machine::new(snapshot.state, snapshot.tapes)
+    }
+
+    /// Return a hash of the machine state and tape head positions.
+    ///
+    /// The hash is 64-bit FNV-1a, so it is the same on every platform and across runs, and
+    /// cheaper to keep around than the configuration itself. Different machines may hash to the
+    /// same value, so equal hashes must be confirmed by comparing the machines with `==`.
+    pub fn configuration_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+        let state = self.machine_state.0 as u64;
+        let words = core::iter::once(state).chain(self.tapes().iter().map(|p| *p as u32 as u64));
+        words
+            .flat_map(|w| w.to_le_bytes())
+            .fold(FNV_OFFSET, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// Try to apply the give rule to the machine.
+    ///
+    /// If the rule's guard is satisfied, move the tapes in the guard backwards and the tapes in
+    /// the action forward. Then update the machine's state. If successful, return `true`,
+    /// otherwise `false`. A rule with the wrong number of tape adjustments or that would move a
+    /// tape head past `i32::MAX` is not applied, see `try_apply_rule` to tell these apart.
+    pub fn apply_rule(&mut self, rule: &Rule) -> bool {
+        matches!(self.try_apply_rule(rule), Ok(true))
+    }
+
+    /// Try to apply the given rule to the machine like `apply_rule`, reporting malformed rules as
+    /// errors.
+    ///
+    /// Returns `Ok(true)` if the rule fired and `Ok(false)` if the machine is in another state or
+    /// the guard is not satisfied. A rule with the wrong number of tape adjustments is reported as
+    /// `ErrorCode::BadClause` with index 0, since a lone rule has no position in a program, and a
+    /// rule that would move a tape head past `i32::MAX` as `ErrorCode::Overflow`. The machine is
+    /// left untouched on error.
+    pub fn try_apply_rule(&mut self, rule: &Rule) -> Result<bool, ErrorCode> {
+        self.apply_bounded_rule(rule, &[])
+    }
+
+    /// Try to apply the given rule to the machine like `try_apply_rule`, respecting the given
+    /// upper bounds on tape head positions.
+    fn apply_bounded_rule(
+        &mut self,
+        rule: &Rule,
+        bounds: &[Option<i32>],
+    ) -> Result<bool, ErrorCode> {
+        if rule.len() != self.tape_state.len() {
+            return Err(ErrorCode::BadClause { rule: 0 });
+        }
+        if rule.is_probe() {
+            return Ok(false);
+        }
+        if self.machine_state == rule.cur_state && self.tape_state.test_rule(rule, bounds) {
+            if let Some(tape) = self.tape_state.overflowing_tape(rule) {
+                return Err(ErrorCode::Overflow { tape });
+            }
+            self.tape_state.apply_rule(rule);
+            assert!(self.tape_state.is_valid());
+            self.machine_state = rule.next_state;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Return the current machine state.
+    pub fn machine_state(&self) -> State {
+        self.machine_state
+    }
+
+    /// Return the number of tapes in the machine.
+    pub fn num_tapes(&self) -> usize {
+        self.tape_state.len()
+    }
+
+    /// Return the current tape head positions for all tapes.
+    pub fn tapes(&self) -> &[i32] {
+        &self.tape_state
+    }
+
+    /// Return the tape state of the machine.
+    pub fn tape_state(&self) -> &TapeState {
+        &self.tape_state
+    }
+
+    /// Return the current tape head position for the indicated tape.
+    ///
+    /// Panics if `id` is not a valid tape id, see `tape_pos_checked` for a non-panicking variant.
+    pub fn tape_pos(&self, id: impl Into<TapeId>) -> i32 {
+        self.tape_state[id.into().0]
+    }
+
+    /// Return the current tape head position for the indicated tape, or `None` if `id` is not a
+    /// valid tape id.
+    pub fn tape_pos_checked(&self, id: impl Into<TapeId>) -> Option<i32> {
+        self.tape_state.get(id.into().0).copied()
+    }
+
+    /// Apply at most one rule from the program to the machine.
+    ///
+    /// Rules are tried in the order they appear in the program and the first one that applies
+    /// is fired. The returned index is the position of the rule in `Program::iter`. If a rule
+    /// that could fire in the current state has the wrong number of tape adjustments, the
+    /// machine is left untouched and `StepResult::BadClause` is returned. Likewise if the first
+    /// applicable rule would move a tape head past `i32::MAX`, `StepResult::Overflow` is
+    /// returned.
+    pub fn step(&mut self, program: &Program) -> StepResult {
+        self.step_among(program, 0..program.num_rules())
+    }
+
+    /// Apply at most one rule from the program, only considering the rules at the given indices
+    /// in the order they are given.
+    fn step_among<I>(&mut self, program: &Program, candidates: I) -> StepResult
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        step_machine(self, program, candidates, Strategy::FirstMatch, |_, _| {})
+    }
+
+    /// Return every rule of the program that could fire in the current configuration, together
+    /// with its index, in program order.
+    ///
+    /// Rules with the wrong number of tape adjustments are never applicable.
+    pub fn applicable_rules<'a>(&self, program: &'a Program) -> Vec<(usize, &'a Rule)> {
+        program
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| {
+                self.machine_state == rule.cur_state
+                    && rule.len() == self.tape_state.len()
+                    && !rule.is_probe()
+                    && self.tape_state.test_rule(rule, &program.bounds)
+            })
+            .collect()
+    }
+
+    /// Determine whether the machine is halted, i.e. no rule among the given candidates can
+    /// fire. A candidate with the wrong number of tape adjustments counts as able to fire, a
+    /// probe never fires.
+    fn is_halted<I>(&self, program: &Program, candidates: I) -> bool
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        candidates.into_iter().all(|idx| {
+            let rule = &program.rules[idx];
+            self.machine_state != rule.cur_state
+                || (rule.len() == self.tape_state.len()
+                    && (rule.is_probe() || !self.tape_state.test_rule(rule, &program.bounds)))
+        })
+    }
+}
+
+/// Return the indices of the rules that fire in `state` according to `index`.
+fn candidates(
+    index: &BTreeMap<State, Vec<usize>>,
+    state: State,
+) -> impl Iterator<Item = usize> + '_ {
+    index.get(&state).into_iter().flatten().copied()
+}
+
+/// A machine the interpreter can run, so that the same step and loop work for tapes of any
+/// [`TapeValue`] type
+trait RunMachine: Clone {
+    type Value: TapeValue;
+
+    /// Return the current machine state.
+    fn state(&self) -> State;
+
+    /// Return the current tape head positions.
+    fn positions(&self) -> &[Self::Value];
+
+    /// Fire `rule`, whose guard, bounds, and overflow have been checked.
+    fn fire(&mut self, rule: &Rule);
+
+    /// Keep firing the self-looping `rule`, which just fired and is the first rule of its state,
+    /// while it applies and at most `limit` more times, and return how often it fired.
+    ///
+    /// A machine whose runs are traced step by step must not skip steps, so the default fires
+    /// nothing.
+    fn repeat(&mut self, _rule: &Rule, _bounds: &[Option<i32>], _limit: u64) -> u64 {
+        0
+    }
+}
+
+impl RunMachine for Machine {
+    type Value = i32;
+
+    fn state(&self) -> State {
+        self.machine_state
+    }
+
+    fn positions(&self) -> &[i32] {
+        &self.tape_state.0
+    }
+
+    fn fire(&mut self, rule: &Rule) {
+        apply_tapes(&mut self.tape_state.0, rule);
+        self.machine_state = rule.next_state;
+    }
+}
+
+/// Apply at most one of the rules at the given indices to the machine, chosen according to
+/// `strategy`, see [`Machine::step`] and [`interpret_with_strategy`].
+///
+/// With `Strategy::FirstMatch` the candidates are tried in the order they are given and `probe`
+/// is called with the message of every satisfied probe rule that comes before the rule that
+/// fires, along with the machine. The other strategies collect every applicable rule first and
+/// reach no probes.
+fn step_machine<M, I, P>(
+    machine: &mut M,
+    program: &Program,
+    candidates: I,
+    strategy: Strategy,
+    mut probe: P,
+) -> StepResult
+where
+    M: RunMachine,
+    I: IntoIterator<Item = usize>,
+    P: FnMut(&str, &M),
+{
+    if strategy != Strategy::FirstMatch {
+        let mut applicable = Vec::new();
+        for idx in candidates {
+            let rule = &program.rules[idx];
+            if machine.state() != rule.cur_state {
+                continue;
+            }
+            if rule.len() != machine.positions().len() {
+                return StepResult::BadClause(idx);
+            }
+            if !rule.is_probe() && test_tapes(machine.positions(), rule, &program.bounds) {
+                applicable.push(idx);
+            }
+        }
+        let chosen = match strategy {
+            Strategy::FirstMatch => applicable.first().copied(),
+            Strategy::LastMatch => applicable.last().copied(),
+            Strategy::MaxAdjustment => {
+                let total = |idx: usize| -> i64 {
+                    program.rules[idx].iter().map(|a| (*a as i64).abs()).sum()
+                };
+                // `max_by_key` keeps the last maximum, so search from the back
+                applicable
+                    .iter()
+                    .rev()
+                    .copied()
+                    .max_by_key(|idx| total(*idx))
+            }
+        };
+        return match chosen {
+            Some(idx) => step_machine(machine, program, Some(idx), Strategy::FirstMatch, probe),
+            None => StepResult::Halted,
+        };
+    }
+    for idx in candidates {
+        let rule = &program.rules[idx];
+        if machine.state() != rule.cur_state {
+            continue;
+        }
+        if rule.len() != machine.positions().len() {
+            return StepResult::BadClause(idx);
+        }
+        if let Some(message) = &rule.probe {
+            if test_tapes(machine.positions(), rule, &[]) {
+                probe(message, machine);
+            }
+            continue;
+        }
+        if test_tapes(machine.positions(), rule, &program.bounds) {
+            if let Some(tape) = overflowing_tape(machine.positions(), rule) {
+                return StepResult::Overflow(idx, tape);
+            }
+            machine.fire(rule);
+            return StepResult::Fired(idx);
+        }
+    }
+    StepResult::Halted
+}
+
+/// Something that happens during a run of [`run_machine`], handed to its hook
+enum RunEvent<'a, M> {
+    /// The machine before a search for a rule to apply, every `sample_every` steps, and the last
+    /// machine before the run stops unless that was just sampled
+    Sample(&'a M),
+    /// A probe with the given message was reached in the machine
+    Probe(&'a str, &'a M),
+    /// The rule at the given index fired the given number of times in a row, leaving the machine
+    /// as given
+    Fired(usize, u64, &'a M),
+}
+
+/// How a run of [`run_machine`] ended when it did not fail
+enum RunEnd<B> {
+    /// No rule could fire, for the given reason
+    Halted(HaltReason),
+    /// The hook stopped the run with the given value
+    Stopped(B),
+}
+
+/// Run the program on the machine until it halts, runs out of `fuel`, or `hook` stops it.
+///
+/// This is the loop behind the interpreters, firing rules chosen by `strategy`. `hook` sees every
+/// [`RunEvent`] along with the number of steps taken so far, and stops the run by returning
+/// `ControlFlow::Break`. That is ignored for probes, which are reached in the middle of a step,
+/// and for the last sample of a run, which ends anyway. Without `fuel` the run only ends when
+/// the machine halts or is stopped. Only a first match strategy fires a rule more than once per
+/// event, see [`RunMachine::repeat`].
+fn run_machine<M, B, H>(
+    machine: &mut M,
+    program: &Program,
+    fuel: Option<u64>,
+    strategy: Strategy,
+    sample_every: u64,
+    mut hook: H,
+) -> Result<(u64, RunEnd<B>), ErrorCode<M>>
+where
+    M: RunMachine,
+    H: FnMut(RunEvent<'_, M>, u64) -> ControlFlow<B>,
+{
+    let sample_every = sample_every.max(1);
+    let mut counter: u64 = 0;
+    let index = program.rules_by_state();
+    loop {
+        let sampled = counter % sample_every == 0;
+        if sampled {
+            if let ControlFlow::Break(value) = hook(RunEvent::Sample(machine), counter) {
+                return Ok((counter, RunEnd::Stopped(value)));
+            }
+        }
+        let state = machine.state();
+        let candidates = candidates(&index, state);
+        let step = step_machine(machine, program, candidates, strategy, |message, m| {
+            let _ = hook(RunEvent::Probe(message, m), counter);
+        });
+        match step {
+            StepResult::Fired(idx) => {
+                let rule = &program.rules[idx];
+                let mut times = 1;
+                let first = index[&state][0] == idx;
+                if strategy == Strategy::FirstMatch && rule.next_state == state && first {
+                    let limit = fuel.map_or(u64::MAX, |fuel| fuel.saturating_sub(counter + 1));
+                    times += machine.repeat(rule, &program.bounds, limit);
+                }
+                counter += times;
+                if let ControlFlow::Break(value) =
+                    hook(RunEvent::Fired(idx, times, machine), counter)
+                {
+                    return Ok((counter, RunEnd::Stopped(value)));
+                }
+                if fuel.is_some_and(|fuel| counter >= fuel) {
+                    let _ = hook(RunEvent::Sample(machine), counter);
+                    return Err(ErrorCode::OutOfFuel {
+                        steps: counter,
+                        machine: machine.clone(),
+                    });
+                }
+            }
+            StepResult::Halted => {
+                if !sampled {
+                    let _ = hook(RunEvent::Sample(machine), counter);
+                }
+                let reason = halt_reason(program, counter, state);
+                return Ok((counter, RunEnd::Halted(reason)));
+            }
+            StepResult::BadClause(rule) => {
+                if !sampled {
+                    let _ = hook(RunEvent::Sample(machine), counter);
+                }
+                return Err(ErrorCode::BadClause { rule });
+            }
+            StepResult::Overflow(_, tape) => {
+                if !sampled {
+                    let _ = hook(RunEvent::Sample(machine), counter);
+                }
+                return Err(ErrorCode::Overflow { tape });
+            }
+        }
+    }
+}
+
+/// Run the program on the machine like [`run_machine`] with a hook that never stops the run,
+/// returning the number of steps and why the machine halted.
+fn run_until_halted<M, H>(
+    machine: &mut M,
+    program: &Program,
+    fuel: u64,
+    strategy: Strategy,
+    sample_every: u64,
+    mut hook: H,
+) -> Result<(u64, HaltReason), ErrorCode<M>>
+where
+    M: RunMachine,
+    H: FnMut(RunEvent<'_, M>, u64),
+{
+    let run = run_machine(
+        machine,
+        program,
+        Some(fuel),
+        strategy,
+        sample_every,
+        |event, steps| {
+            hook(event, steps);
+            ControlFlow::<Infallible>::Continue(())
+        },
+    );
+    match run? {
+        (steps, RunEnd::Halted(reason)) => Ok((steps, reason)),
+        (_, RunEnd::Stopped(never)) => match never {},
+    }
+}
+
+/// Tell why a run that took `steps` steps halted with the machine in `state`.
+fn halt_reason(program: &Program, steps: u64, state: State) -> HaltReason {
+    if program.accept_states.contains(&state) {
+        HaltReason::Accepted
+    } else if steps == 0 {
+        HaltReason::NoRuleFired
+    } else {
+        HaltReason::Halted
+    }
+}
+
+/// Estimate how much fuel the program needs to halt when started on the given machine.
+///
+/// This is only a heuristic: the estimate is `(t + 1)^2 * (r + 1)` where `t` is the sum of the
+/// initial tape positions and `r` is the number of rules, which covers programs that loop over
+/// their inputs a couple of times, like the adder and the multiplier in [`crate::arith`]. It may
+/// overestimate badly for simple programs and underestimate programs that grow their tapes, such
+/// as exponentiation. Callers are free to scale the result.
+pub fn estimate_fuel(program: &Program, machine: &Machine) -> u64 {
+    let total: u64 = machine.tapes().iter().map(|tp| (*tp).max(0) as u64).sum();
+    let rules = program.num_rules() as u64 + 1;
+    (total + 1).saturating_mul(total + 1).saturating_mul(rules)
+}
+
+/// Interpret the given program starting with the initial machine.
+///
+/// Try to apply rules in the program in the order they appear.
+///   - When a rule applies, apply it and start over from the first rule in the program.
+///   - When no rules apply to a given machine, halt and return the machine.
+///
+/// On success the number of steps taken and the final machine are returned along with the
+/// reason the machine halted, which tells a machine that did some work apart from one that
+/// halted right away. If the program declares accept states, halting in one of them is
+/// reported as `HaltReason::Accepted` and halting anywhere else means the machine got stuck.
+pub fn interpret(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    interpret_with_trace(initial_machine, program, fuel, |_, _| {})
+}
+
+/// Interpret the given program like [`interpret`], updating the given machine in place.
+///
+/// When the run ends the machine is left in its final configuration. Together with
+/// `Machine::reset` this lets a caller reuse one machine for many runs. If the interpreter runs
+/// out of fuel the returned error holds a copy of the machine.
+pub fn interpret_mut(
+    machine: &mut Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(u64, HaltReason), ErrorCode> {
+    run_until_halted(machine, program, fuel, Strategy::FirstMatch, 1, |_, _| {})
+}
+
+/// Interpret the given program starting from each of the initial machines.
+///
+/// The results are in the same order as `inits`. With the `rayon` feature enabled the runs are
+/// spread over a thread pool, otherwise they are run one after the other.
+pub fn interpret_batch(
+    program: &Program,
+    inits: Vec<Machine>,
+    fuel: u64,
+) -> Vec<Result<(u64, Machine, HaltReason), ErrorCode>> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        inits
+            .into_par_iter()
+            .map(|m| interpret(m, program, fuel))
+            .collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        inits
+            .into_iter()
+            .map(|m| interpret(m, program, fuel))
+            .collect()
+    }
+}
+
+/// Interpret the given program like [`interpret`], after checking that some rule fires in the
+/// initial machine state.
+///
+/// A machine that starts in a state no rule fires in halts immediately, which is usually a
+/// mistake. In that case `ErrorCode::InvalidStart` is returned instead.
+pub fn interpret_checked(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    let state = initial_machine.machine_state;
+    if !program.iter().any(|r| r.cur_state == state) {
+        return Err(ErrorCode::InvalidStart { state });
+    }
+    interpret(initial_machine, program, fuel)
+}
+
+/// Interpret the given program like [`interpret`], calling `trace` at every step.
+///
+/// The callback receives the machine before each search for a rule to apply, along with the
+/// number of rules that have fired so far. It is called once more with the final machine before
+/// the interpreter halts or runs out of fuel, so it sees every configuration of the run.
+pub fn interpret_with_trace<F>(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+    trace: F,
+) -> Result<(u64, Machine, HaltReason), ErrorCode>
+where
+    F: FnMut(&Machine, u64),
+{
+    interpret_with_sampled_trace(initial_machine, program, fuel, 1, trace)
+}
+
+/// Interpret the given program like [`interpret_with_trace`], calling `trace` only every
+/// `sample_every` steps.
+///
+/// The callback receives the machine whenever the number of rules that have fired so far is a
+/// multiple of `sample_every`, so always for the initial machine, and once more with the last
+/// machine before the interpreter stops unless that was just sampled. This holds whether the
+/// machine halts, runs out of fuel, or reaches a bad clause or an overflow, so the last
+/// configuration of a failed run is always seen. A run that halts after `n` steps thus calls it
+/// `n / sample_every + 1` times, plus one unless `sample_every` divides `n`. A `sample_every` of
+/// 0 is treated as 1.
+pub fn interpret_with_sampled_trace<F>(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+    sample_every: u64,
+    mut trace: F,
+) -> Result<(u64, Machine, HaltReason), ErrorCode>
+where
+    F: FnMut(&Machine, u64),
+{
+    let mut machine = initial_machine;
+    let (steps, reason) = run_until_halted(
+        &mut machine,
+        program,
+        fuel,
+        Strategy::FirstMatch,
+        sample_every,
+        |event, steps| {
+            if let RunEvent::Sample(m) = event {
+                trace(m, steps)
+            }
+        },
+    )?;
+    Ok((steps, machine, reason))
+}
+
+/// Interpret the given program like [`interpret_with_trace`], calling `on_probe` whenever a probe
+/// rule is reached.
+///
+/// The callback receives the message of the probe, the machine, and the number of rules that
+/// have fired so far. A probe is reached when it is tried before the rule that fires, i.e. when
+/// the machine is in its state and its guard holds. Probes never change the machine and never
+/// count as a step, so a program made only of probes halts at once.
+pub fn interpret_with_probes<F>(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+    mut on_probe: F,
+) -> Result<(u64, Machine, HaltReason), ErrorCode>
+where
+    F: FnMut(&str, &Machine, u64),
+{
+    let mut machine = initial_machine;
+    let (steps, reason) = run_until_halted(
+        &mut machine,
+        program,
+        fuel,
+        Strategy::FirstMatch,
+        1,
+        |event, steps| {
+            if let RunEvent::Probe(message, m) = event {
+                on_probe(message, m, steps)
+            }
+        },
+    )?;
+    Ok((steps, machine, reason))
+}
+
+/// Interpret the given program like [`interpret`], choosing which applicable rule fires at each
+/// step according to `strategy`.
+///
+/// With `Strategy::FirstMatch` this behaves exactly like [`interpret`]. The other strategies
+/// collect every rule that applies in the current configuration before picking one.
+pub fn interpret_with_strategy(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+    strategy: Strategy,
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    let mut machine = initial_machine;
+    let (steps, reason) = run_until_halted(&mut machine, program, fuel, strategy, 1, |_, _| {})?;
+    Ok((steps, machine, reason))
+}
+
+/// Interpret the given program like [`interpret`], recording every configuration of the machine.
+///
+/// The trace holds one configuration per step, giving the machine before the step and the rule
+/// that fired, followed by the final configuration with no fired rule. Since every configuration
+/// stores a copy of all tape positions, the trace uses memory proportional to the number of steps
+/// times the number of tapes; its length is at most `fuel + 1`.
+pub fn interpret_traced(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(Vec<Configuration>, Machine, HaltReason), ErrorCode> {
+    let mut trace: Vec<Configuration> = Vec::new();
+    let mut machine = initial_machine;
+    let strategy = Strategy::FirstMatch;
+    let (_, reason) =
+        run_until_halted(
+            &mut machine,
+            program,
+            fuel,
+            strategy,
+            1,
+            |event, _| match event {
+                RunEvent::Sample(m) => trace.push(Configuration {
+                    state: m.machine_state,
+                    tapes: m.tape_state.to_vec(),
+                    fired_rule: None,
+                }),
+                RunEvent::Fired(idx, _, _) => {
+                    if let Some(config) = trace.last_mut() {
+                        config.fired_rule = Some(idx);
+                    }
+                }
+                RunEvent::Probe(..) => {}
+            },
+        )?;
+    Ok((trace, machine, reason))
+}
+
+/// Interpret the given program like [`interpret`] and return the indices of the rules that fired,
+/// in order.
+///
+/// The sequence is a compact signature of a run that is easy to compare against the expected
+/// control flow of a program.
+pub fn fired_rule_sequence(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<Vec<usize>, ErrorCode> {
+    let mut fired = Vec::new();
+    let mut machine = initial_machine;
+    let strategy = Strategy::FirstMatch;
+    run_until_halted(&mut machine, program, fuel, strategy, 1, |event, _| {
+        if let RunEvent::Fired(idx, times, _) = event {
+            fired.extend(core::iter::repeat(idx).take(times as usize));
+        }
+    })?;
+    Ok(fired)
+}
+
+/// Interpret the given program like [`interpret`], counting how many times each rule fires.
+///
+/// The counts are indexed by rule position in the program, so the hot rules of a program are
+/// the ones with the largest counts.
+pub fn interpret_profiled(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(Vec<u64>, Machine, HaltReason), ErrorCode> {
+    let (stats, machine, reason) = interpret_with_stats(initial_machine, program, fuel)?;
+    Ok((stats.firings_per_rule, machine, reason))
+}
+
+/// Interpret the given program like [`interpret`], gathering [`RunStats`] along the way.
+///
+/// Besides the rule firing counts of [`interpret_profiled`], the stats record the highest
+/// position each tape head reaches during the run, e.g. the high-water mark of a scratch tape
+/// that is back at zero when the machine halts.
+pub fn interpret_with_stats(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(RunStats, Machine, HaltReason), ErrorCode> {
+    let mut stats = RunStats {
+        max_tape: initial_machine.tapes().to_vec(),
+        total_steps: 0,
+        firings_per_rule: vec![0; program.num_rules()],
+    };
+    let mut machine = initial_machine;
+    let strategy = Strategy::FirstMatch;
+    let (_, reason) =
+        run_until_halted(&mut machine, program, fuel, strategy, 1, |event, steps| {
+            if let RunEvent::Fired(idx, times, m) = event {
+                stats.firings_per_rule[idx] += times;
+                stats.total_steps = steps;
+                for (max, pos) in stats.max_tape.iter_mut().zip(m.tapes()) {
+                    *max = (*max).max(*pos);
+                }
+            }
+        })?;
+    Ok((stats, machine, reason))
+}
+
+/// Interpret the given program like [`interpret`], deriving the fuel from a linear monovariant.
+///
+/// If [`find_ranking_weights`] finds weights `w` under which every rule decreases the weighted
+/// sum of the tape positions, each step decreases `w · tapes` by at least 1 and the sum never
+/// goes below 0, so the run halts within `w · tapes` steps of the initial machine. That bound is
+/// used as the fuel. If there are no such weights `ErrorCode::TerminationUnknown` is returned
+/// without running the program.
+pub fn interpret_auto(
+    initial_machine: Machine,
+    program: &Program,
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    let weights = find_ranking_weights(program).ok_or(ErrorCode::TerminationUnknown)?;
+    // each product fits in an `i64`, but their sum over all tapes may not
+    let weighted: i128 = initial_machine
+        .tapes()
+        .iter()
+        .zip(weights.iter())
+        .map(|(a, w)| i128::from(*a) * i128::from(*w))
+        .sum();
+    // the bound is reached by a run that halts after exactly `bound` steps, which the fuel
+    // check would otherwise report as running out of fuel
+    let bound = u64::try_from(weighted.max(0)).unwrap_or(u64::MAX);
+    interpret(initial_machine, program, bound.saturating_add(1))
+}
+
+/// Interpret the given program like [`interpret`], also capping how often each rule may fire.
+///
+/// `per_rule_caps` is indexed by rule position in the program, `None` or a missing entry means
+/// the rule may fire any number of times. The run stops with `ErrorCode::RuleCapExceeded` as soon
+/// as a rule fires more often than its cap, which points at the phase of a program that runs
+/// away, while the global `fuel` still bounds the run as a whole.
+pub fn interpret_with_limits(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+    per_rule_caps: &[Option<u64>],
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    let mut counts = vec![0; program.num_rules()];
+    let mut machine = initial_machine;
+    let strategy = Strategy::FirstMatch;
+    let run = run_machine(
+        &mut machine,
+        program,
+        Some(fuel),
+        strategy,
+        1,
+        |event, steps| {
+            if let RunEvent::Fired(idx, times, m) = event {
+                counts[idx] += times;
+                if per_rule_caps
+                    .get(idx)
+                    .copied()
+                    .flatten()
+                    .is_some_and(|cap| counts[idx] > cap)
+                {
+                    return ControlFlow::Break(ErrorCode::RuleCapExceeded {
+                        rule: idx,
+                        steps,
+                        machine: m.clone(),
+                    });
+                }
+            }
+            ControlFlow::Continue(())
+        },
+    )?;
+    match run {
+        (steps, RunEnd::Halted(reason)) => Ok((steps, machine, reason)),
+        (_, RunEnd::Stopped(error)) => Err(error),
+    }
+}
+
+/// Number of steps between clock reads in [`interpret_timed`]
+#[cfg(feature = "std")]
+const TIMEOUT_STRIDE: u64 = 1024;
+
+/// Interpret the given program like [`interpret`] with a wall-clock budget instead of fuel.
+///
+/// Reading the clock on every step would dominate the run time, so the elapsed time is only
+/// checked every `TIMEOUT_STRIDE` steps and a run may overshoot `timeout` by that many steps.
+/// When the budget is exceeded `ErrorCode::Timeout` is returned. This function needs the `std`
+/// feature.
+#[cfg(feature = "std")]
+pub fn interpret_timed(
+    initial_machine: Machine,
+    program: &Program,
+    timeout: std::time::Duration,
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    let start = std::time::Instant::now();
+    let mut machine = initial_machine;
+    let run = run_machine(
+        &mut machine,
+        program,
+        None,
+        Strategy::FirstMatch,
+        1,
+        |event, steps| match event {
+            RunEvent::Fired(..) if steps % TIMEOUT_STRIDE == 0 && start.elapsed() >= timeout => {
+                ControlFlow::Break(ErrorCode::Timeout { steps })
+            }
+            _ => ControlFlow::Continue(()),
+        },
+    )?;
+    match run {
+        (steps, RunEnd::Halted(reason)) => Ok((steps, machine, reason)),
+        (_, RunEnd::Stopped(error)) => Err(error),
+    }
+}
+
+/// Iterator over the configurations of a running machine, see [`run_iter`]
+pub struct RunIterator<'a> {
+    machine: Machine,
+    program: &'a Program,
+    index: BTreeMap<State, Vec<usize>>,
+    done: bool,
+}
+
+impl<'a> RunIterator<'a> {
+    /// Return the machine in its current configuration, which is the final machine once the
+    /// iterator is exhausted.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+}
+
+impl<'a> Iterator for RunIterator<'a> {
+    type Item = Configuration;
+
+    fn next(&mut self) -> Option<Configuration> {
+        if self.done {
+            return None;
+        }
+        let mut config = Configuration {
+            state: self.machine.machine_state,
+            tapes: self.machine.tape_state.to_vec(),
+            fired_rule: None,
+        };
+        let candidates = candidates(&self.index, self.machine.machine_state);
+        match self.machine.step_among(self.program, candidates) {
+            StepResult::Fired(idx) => config.fired_rule = Some(idx),
+            StepResult::Halted => self.done = true,
+            StepResult::BadClause(_) | StepResult::Overflow(..) => {
+                self.done = true;
+                return None;
+            }
+        }
+        Some(config)
+    }
+}
+
+/// Run the given program lazily, yielding the configuration of the machine before every step.
+///
+/// The configurations are the same as those recorded by [`interpret_traced`]: the last one is the
+/// halted configuration, with no fired rule, and is yielded exactly once. There is no fuel, so
+/// the iterator never ends for a program that runs forever. If a rule with the wrong number of
+/// tapes is reached, or a tape would overflow, the iterator ends without yielding the
+/// configuration, use [`interpret`] to get the error.
+pub fn run_iter(machine: Machine, program: &Program) -> RunIterator<'_> {
+    RunIterator {
+        machine,
+        program,
+        index: program.rules_by_state(),
+        done: false,
+    }
+}
+
+/// Interpret the given program until it halts or `max_steps` rules have fired.
+///
+/// With `max_steps` set to `None` there is no limit and interpretation only stops when the
+/// machine halts, so this will not return for a program that runs forever. Unlike the fuel of
+/// [`interpret`], a run that halts after exactly `max_steps` steps is reported as halted.
+pub fn interpret_until(
+    initial_machine: Machine,
+    program: &Program,
+    max_steps: Option<u64>,
+) -> Result<RunOutcome, ErrorCode> {
+    let mut machine = initial_machine;
+    let index = program.rules_by_state();
+    let run = run_machine(
+        &mut machine,
+        program,
+        None,
+        Strategy::FirstMatch,
+        1,
+        |event, steps| {
+            match event {
+                // a machine that halts exactly at the limit is still reported as halted
+                RunEvent::Sample(m)
+                    if max_steps.is_some_and(|max| steps >= max)
+                        && !m.is_halted(program, candidates(&index, m.machine_state)) =>
+                {
+                    ControlFlow::Break(())
+                }
+                _ => ControlFlow::Continue(()),
+            }
+        },
+    )?;
+    Ok(match run {
+        (steps, RunEnd::Halted(reason)) => RunOutcome::Halted {
+            steps,
+            machine,
+            reason,
+        },
+        (steps, RunEnd::Stopped(())) => RunOutcome::StepLimit { steps, machine },
+    })
+}
+
+/// Interpret the given program until the machine enters the state `target` or halts.
+///
+/// The result is the number of steps taken, the final machine, and `None` if `target` was
+/// reached. The target is only checked after a rule fires, so a machine that starts in `target`
+/// runs until it returns to it. If the machine halts without entering `target` the run still
+/// succeeds, with the reason the machine halted in place of `None`. Running out of fuel before
+/// either happens is reported as `ErrorCode::OutOfFuel`, like in [`interpret`].
+pub fn interpret_until_state(
+    initial_machine: Machine,
+    program: &Program,
+    target: impl Into<State>,
+    fuel: u64,
+) -> Result<(u64, Machine, Option<HaltReason>), ErrorCode> {
+    let target = target.into();
+    let mut machine = initial_machine;
+    let strategy = Strategy::FirstMatch;
+    let run = run_machine(
+        &mut machine,
+        program,
+        Some(fuel),
+        strategy,
+        1,
+        |event, _| match event {
+            RunEvent::Fired(_, _, m) if m.machine_state == target => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        },
+    )?;
+    match run {
+        (steps, RunEnd::Halted(reason)) => Ok((steps, machine, Some(reason))),
+        (steps, RunEnd::Stopped(())) => Ok((steps, machine, None)),
+    }
+}
+
+/// Interpret the given program like [`interpret`], additionally checking for cycles.
+///
+/// The configurations (machine state and tape positions) of the last `window` steps are
+/// remembered. If the machine returns to one of them, interpretation stops with
+/// `ErrorCode::Cycle` giving the number of steps between the two occurrences. Since a cycle is
+/// only reported for an exact repeat, a machine whose tapes grow without bound is never flagged
+/// and will eventually run out of fuel instead. Cycles longer than `window` are not detected.
+///
+/// Configurations are looked up by [`Machine::configuration_hash`], and a matching hash is only
+/// reported as a cycle once the configurations compare equal.
+pub fn interpret_with_cycle_detection(
+    initial_machine: Machine,
+    program: &Program,
+    fuel: u64,
+    window: usize,
+) -> Result<(u64, Machine, HaltReason), ErrorCode> {
+    let mut machine = initial_machine;
+    // the steps at which each hash was seen, and the configurations of the last `window` steps
+    let mut seen: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let mut history: VecDeque<(u64, Machine)> = VecDeque::new();
+    let strategy = Strategy::FirstMatch;
+    let run = run_machine(
+        &mut machine,
+        program,
+        Some(fuel),
+        strategy,
+        1,
+        |event, steps| {
+            let m = match event {
+                RunEvent::Sample(m) => m,
+                _ => return ControlFlow::Continue(()),
+            };
+            let hash = m.configuration_hash();
+            let first_step = steps - history.len() as u64;
+            let repeat = seen.get(&hash).and_then(|seen_at| {
+                seen_at
+                    .iter()
+                    .find(|step| history[(**step - first_step) as usize].1 == *m)
+            });
+            if let Some(step) = repeat {
+                return ControlFlow::Break(steps - step);
+            }
+            if window > 0 {
+                if history.len() == window {
+                    if let Some((oldest, _)) = history.pop_front() {
+                        let seen_at = seen.get_mut(&oldest).expect("hash of a remembered step");
+                        seen_at.retain(|step| *step != first_step);
+                        if seen_at.is_empty() {
+                            seen.remove(&oldest);
+                        }
+                    }
+                }
+                seen.entry(hash).or_default().push(steps);
+                history.push_back((hash, m.clone()));
+            }
+            ControlFlow::Continue(())
+        },
+    )?;
+    match run {
+        (steps, RunEnd::Halted(reason)) => Ok((steps, machine, reason)),
+        (_, RunEnd::Stopped(period)) => Err(ErrorCode::Cycle { period }),
+    }
+}
+
+/// Maximum length of the tape drawn by [`render_ascii`], not counting the head
+const RENDER_WIDTH: u64 = 40;
+
+/// Render the machine state and tape head positions as a small text diagram.
+///
+/// There is one line per tape, drawing the tape from position 0 up to the head `●`, followed by
+/// the exact position:
+///
+/// ```text
+/// state: 1
+/// tape 0: ———● (pos 3)
+/// tape 1: ● (pos 0)
+/// ```
+///
+/// Each unit is one character as long as every head is within 40 of the bottom of its tape.
+/// Otherwise all tapes are scaled down by the same factor so the largest position is drawn 40
+/// characters long, keeping the output bounded and the tapes comparable. A negative position,
+/// which only a machine built with `Machine::new` can have, is drawn like position 0. Call it
+/// from the callback of [`interpret_with_trace`] to animate a run in the terminal.
+pub fn render_ascii(machine: &Machine) -> String {
+    let drawn = |pos: &i32| (*pos).max(0) as u64;
+    let max = machine.tapes().iter().map(drawn).max().unwrap_or(0);
+    let label_width = machine.num_tapes().saturating_sub(1).to_string().len();
+    let mut out = format!("state: {}\n", machine.machine_state);
+    for (tape, pos) in machine.tapes().iter().enumerate() {
+        let len = if max <= RENDER_WIDTH {
+            drawn(pos)
+        } else {
+            drawn(pos) * RENDER_WIDTH / max
+        };
+        out.push_str(&format!(
+            "tape {:>width$}: {}● (pos {})\n",
+            tape,
+            "—".repeat(len as usize),
+            pos,
+            width = label_width
+        ));
+    }
+    out
+}
+
+/// List the differences between two machines.
+///
+/// A state mismatch is listed first, followed by one entry per tape whose head positions differ,
+/// in tape order. Only the tapes both machines have are compared, so a machine can be compared
+/// against a prefix of the tapes of a wider one. The result is empty if the machines agree.
+pub fn diff_machines(a: &Machine, b: &Machine) -> Vec<TapeDiff> {
+    let mut diffs = Vec::new();
+    if a.machine_state != b.machine_state {
+        diffs.push(TapeDiff::State {
+            a: a.machine_state,
+            b: b.machine_state,
+        });
+    }
+    for (tape, (pos_a, pos_b)) in a.tapes().iter().zip(b.tapes()).enumerate() {
+        if pos_a != pos_b {
+            diffs.push(TapeDiff::Tape {
+                tape: TapeId(tape),
+                a: *pos_a,
+                b: *pos_b,
+            });
+        }
+    }
+    diffs
+}
+
+/// Render the state transition graph of a program in Graphviz DOT format.
+///
+/// There is one node per state mentioned in the program and one edge per rule, from the state the
+/// rule fires in to the state it transitions to, labeled with the rule's tape adjustments. If the
+/// tapes are named, the label only lists the tapes the rule moves, e.g. `acc+1, x-1`.
+pub fn to_dot(program: &Program) -> String {
+    let states: BTreeSet<State> = program
+        .iter()
+        .flat_map(|r| vec![r.cur_state, r.next_state])
+        .collect();
+    let mut out = String::from("digraph program {\n");
+    for state in states {
+        out.push_str(&format!("    {};\n", state));
+    }
+    for rule in program.iter() {
+        let label = if program.tape_names.is_empty() {
+            let adjustments: Vec<String> = rule.iter().map(|a| a.to_string()).collect();
+            format!("[{}]", adjustments.join(", "))
+        } else {
+            // only the tapes the rule moves, e.g. `acc+1, x-1`
+            let adjustments: Vec<String> = rule
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| **a != 0)
+                .map(|(t, a)| format!("{}{:+}", program.tape_label(t), a))
+                .collect();
+            adjustments.join(", ")
+        };
+        out.push_str(&format!(
+            "    {} -> {} [label=\"{}\"];\n",
+            rule.cur_state, rule.next_state, label
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Generate Rust source for a function that runs `program`.
+///
+/// The generated function has the signature
+///
+/// ```text
+/// pub fn fn_name(state: usize, tapes: &mut [i32], fuel: u64) -> Result<(u64, usize), &'static str>
+/// ```
+///
+/// and behaves like [`interpret`]: it returns the number of steps taken and the final state
+/// when no rule applies, or an error when the run takes `fuel` steps, a tape overflows, or a rule
+/// of the wrong width is reached. A rule that would overflow a tape leaves the tapes untouched,
+/// as the interpreter leaves the machine. It matches on the machine state and tests the rules
+/// of that state in program order with their guards and bounds inlined, so no rule is scanned
+/// at run time. `fn_name` must be a valid Rust identifier.
+pub fn codegen_rust(program: &Program, fn_name: &str) -> String {
+    let mut out = format!(
+        "/// Run the m3 program with {} tapes and {} rules, see `minsky::magnificent::interpret`.\n",
+        program.num_tapes(),
+        program.num_rules()
+    );
+    out.push_str(&format!(
+        "pub fn {}(\n    mut state: usize,\n    tapes: &mut [i32],\n    fuel: u64,\n) -> Result<(u64, usize), &'static str> {{\n",
+        fn_name
+    ));
+    out.push_str(&format!(
+        "    assert_eq!(tapes.len(), {}, \"wrong number of tapes\");\n",
+        program.num_tapes()
+    ));
+    out.push_str("    let mut steps: u64 = 0;\n    loop {\n        match state {\n");
+
+    let index = program.rules_by_state();
+    for state in index.keys().copied() {
+        let rules: Vec<usize> = candidates(&index, state)
+            .filter(|idx| !program.rules[*idx].is_probe())
+            .collect();
+        if rules.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("            {} => {{\n", state));
+        let mut exhaustive = false;
+        for (pos, idx) in rules.iter().enumerate() {
+            let rule = &program.rules[*idx];
+            let keyword = if pos == 0 {
+                "                "
+            } else {
+                " else "
+            };
+            if rule.len() != program.num_tapes() {
+                let body = format!(
+                    "return Err(\"rule {} has the wrong number of tapes\");",
+                    idx
+                );
+                out.push_str(&codegen_branch(keyword, None, &[body]));
+                exhaustive = true;
+                break;
+            }
+            // the overflow checks all come before the first move, so a rule that would overflow
+            // leaves the tapes untouched like in the interpreter
+            let mut conditions = Vec::new();
+            let mut checks = Vec::new();
+            let mut moves = Vec::new();
+            for (tape, amt) in rule.iter().enumerate() {
+                if *amt < 0 {
+                    // compared as i64, since `-i32::MIN` is not an i32
+                    conditions.push(format!(
+                        "i64::from(tapes[{}]) >= {}",
+                        tape,
+                        -i64::from(*amt)
+                    ));
+                    moves.push(format!("tapes[{}] += {};", tape, amt));
+                } else if *amt > 0 {
+                    match program.bound(tape) {
+                        Some(b) => conditions.push(format!("tapes[{}] <= {}", tape, b - amt)),
+                        None => checks.push(format!(
+                            "if tapes[{0}] > {1} {{ return Err(\"tape {0} overflowed\"); }}",
+                            tape,
+                            i32::MAX - amt
+                        )),
+                    }
+                    moves.push(format!("tapes[{}] += {};", tape, amt));
+                }
+            }
+            let mut body = checks;
+            body.extend(moves);
+            body.push(format!("state = {};", rule.next_state));
+            if conditions.is_empty() {
+                out.push_str(&codegen_branch(keyword, None, &body));
+                exhaustive = true;
+                break;
+            }
+            out.push_str(&codegen_branch(
+                keyword,
+                Some(&conditions.join(" && ")),
+                &body,
+            ));
+        }
+        if !exhaustive {
+            out.push_str(&codegen_branch(
+                " else ",
+                None,
+                &["return Ok((steps, state));".to_string()],
+            ));
+        }
+        out.push_str("\n            }\n");
+    }
+    out.push_str("            _ => return Ok((steps, state)),\n        }\n");
+    out.push_str("        steps += 1;\n        if steps >= fuel {\n");
+    out.push_str("            return Err(\"out of fuel\");\n        }\n    }\n}\n");
+    out
+}
+
+/// Emit one branch of an if-else chain in a state arm of [`codegen_rust`].
+///
+/// `prefix` is the indentation for the first branch, or `" else "` to continue the chain. Without
+/// a condition the branch is a plain block, or a final `else` block when continuing a chain.
+fn codegen_branch(prefix: &str, condition: Option<&str>, body: &[String]) -> String {
+    let mut out = prefix.to_string();
+    match condition {
+        Some(cond) => out.push_str(&format!("if {} {{\n", cond)),
+        None => out.push_str("{\n"),
+    }
+    for line in body {
+        out.push_str(&format!("                    {}\n", line));
+    }
+    out.push_str("                }");
+    out
+}
+
+/// Where a counter machine instruction jumps to, see [`to_counter_machine`]
+#[derive(Clone, Copy)]
+enum Jump {
+    /// the first instruction of the given state
+    State(State),
+    /// the instruction at the given offset in the same state
+    Local(usize),
+}
+
+/// A counter machine instruction, see [`to_counter_machine`]
+enum CounterInstr {
+    Inc(TapeId, Jump),
+    Dec(TapeId, Jump, Jump),
+    Halt,
+}
+
+/// Largest total increment of a rule that [`to_counter_machine`] expands into `INC` instructions
+const COUNTER_MAX_INCREMENTS: i64 = 1 << 16;
+
+/// Export a program as a classic counter machine listing of `INC`, `DEC`, and `HALT`
+/// instructions.
+///
+/// The instructions are numbered from 0 and registers are numbered like the tapes:
+///
+/// ```text
+/// 0: DEC 1 1 2
+/// 1: INC 0 0
+/// 2: HALT
+/// ```
+///
+/// `INC r j` increments register `r` and jumps to `j`, `DEC r j k` decrements register `r` and
+/// jumps to `j` if it is positive and jumps to `k` otherwise, and `HALT` stops the machine.
+/// Instruction 0 is the program's start state, or state 0 if none is declared, and states that
+/// cannot be reached from it are left out.
+///
+/// This only works for programs whose rules decrement at most one tape, by one, which is the
+/// jump-on-zero test: a rule that fires in a state is only tried once the rules before it failed,
+/// so it becomes the zero branch of the previous rule's `DEC`. A rule without a guard ends the
+/// chain of its state, and a state without an applicable rule halts. Every unit a rule
+/// increments becomes one `INC`, so a rule that increments its tapes by more than 65536 in total
+/// is rejected rather than expanded. An error is returned for any other rule and for programs
+/// with bounded tapes.
+pub fn to_counter_machine(program: &Program) -> Result<String, String> {
+    if program.bounds.iter().any(|b| b.is_some()) {
+        return Err("bounded tapes cannot be exported to a counter machine".to_string());
+    }
+    let index = program.rules_by_state();
+    let start = program.start_state().unwrap_or_default();
+    let mut order = vec![start];
+    let mut blocks = Vec::new();
+    let mut next = 0;
+    while next < order.len() {
+        let rules: Vec<usize> = candidates(&index, order[next])
+            .filter(|idx| !program.rules[*idx].is_probe())
+            .collect();
+        let mut block = Vec::new();
+        counter_block(program, &rules, &mut block)?;
+        for instr in block.iter() {
+            let targets = match instr {
+                CounterInstr::Inc(_, j) => vec![*j],
+                CounterInstr::Dec(_, j, k) => vec![*j, *k],
+                CounterInstr::Halt => Vec::new(),
+            };
+            for target in targets {
+                if let Jump::State(s) = target {
+                    if !order.contains(&s) {
+                        order.push(s);
+                    }
+                }
+            }
+        }
+        blocks.push(block);
+        next += 1;
+    }
+
+    let mut entries = BTreeMap::new();
+    let mut offset = 0;
+    for (state, block) in order.iter().zip(blocks.iter()) {
+        entries.insert(*state, offset);
+        offset += block.len();
+    }
+    let mut out = String::new();
+    let mut offset = 0;
+    for block in blocks.iter() {
+        let resolve = |j: &Jump| match j {
+            Jump::State(s) => entries[s],
+            Jump::Local(i) => offset + i,
+        };
+        for (i, instr) in block.iter().enumerate() {
+            let text = match instr {
+                CounterInstr::Inc(r, j) => format!("INC {} {}", r, resolve(j)),
+                CounterInstr::Dec(r, j, k) => format!("DEC {} {} {}", r, resolve(j), resolve(k)),
+                CounterInstr::Halt => "HALT".to_string(),
+            };
+            out.push_str(&format!("{}: {}\n", offset + i, text));
+        }
+        offset += block.len();
+    }
+    Ok(out)
+}
+
+/// Append the instructions that try the given rules in order to `block`, see
+/// [`to_counter_machine`].
+fn counter_block(
+    program: &Program,
+    rules: &[usize],
+    block: &mut Vec<CounterInstr>,
+) -> Result<(), String> {
+    let (idx, rest) = match rules.split_first() {
+        Some(split) => split,
+        None => {
+            block.push(CounterInstr::Halt);
+            return Ok(());
+        }
+    };
+    let rule = &program.rules[*idx];
+    if rule.len() != program.num_tapes {
+        return Err(format!("rule {} has the wrong number of tapes", idx));
+    }
+    let dec = match rule.guard().as_slice() {
+        [] => None,
+        [(tape, -1)] => {
+            let dec = block.len();
+            block.push(CounterInstr::Halt);
+            Some((dec, *tape))
+        }
+        _ => {
+            return Err(format!(
+                "rule {} does not decrement exactly one tape by one",
+                idx
+            ))
+        }
+    };
+
+    // the increments of the rule, followed by a jump to its next state
+    let total: i64 = rule.action().iter().map(|(_, amt)| i64::from(*amt)).sum();
+    if total > COUNTER_MAX_INCREMENTS {
+        return Err(format!(
+            "rule {} increments its tapes by {}, more than the {} a listing may expand",
+            idx, total, COUNTER_MAX_INCREMENTS
+        ));
+    }
+    let mut incs: Vec<TapeId> = rule
+        .action()
+        .into_iter()
+        .flat_map(|(tape, amt)| core::iter::repeat(tape).take(amt as usize))
+        .collect();
+    if incs.is_empty() {
+        // there is no plain jump, so increment and decrement a register instead
+        block.push(CounterInstr::Inc(TapeId(0), Jump::Local(block.len() + 1)));
+        let target = Jump::State(rule.next_state);
+        block.push(CounterInstr::Dec(TapeId(0), target, target));
+    } else {
+        let last = incs.pop().unwrap();
+        for tape in incs {
+            block.push(CounterInstr::Inc(tape, Jump::Local(block.len() + 1)));
+        }
+        block.push(CounterInstr::Inc(last, Jump::State(rule.next_state)));
+    }
+
+    // the remaining rules are only tried if the decremented tape is zero
+    if let Some((dec, tape)) = dec {
+        block[dec] = CounterInstr::Dec(tape, Jump::Local(dec + 1), Jump::Local(block.len()));
+        counter_block(program, rest, block)?;
+    }
+    Ok(())
+}
+
+/// Combine two programs into one that runs `a` until it halts in one of its accept states and
+/// then runs `b` on the final tapes of `a`.
+///
+/// The states of `b` are renumbered to follow the largest state of `a`, see [`Program::merge`].
+/// For every accept state of `a` a bridge rule that adjusts no tape and moves to the start state
+/// of `b` is added after the rules of `a`. Since the interpreter fires the first applicable rule,
+/// a bridge rule only fires where `a` would have halted in that accept state. Halting in any
+/// other state, i.e. getting stuck, still halts the combined program. The start state of `b` is
+/// its declared start state, or state 0. The combined program keeps the start state of `a`, if
+/// declared, and the accept states of `b`.
+///
+/// The combined program takes one more step than the two programs separately, probes of both
+/// programs are kept. It is an error if `a` declares no accept states, or if the programs have
+/// different numbers of tapes or different tape bounds.
+pub fn compose_sequential(a: &Program, b: &Program) -> Result<Program, String> {
+    if a.accept_states.is_empty() {
+        return Err("the first program has no accept states to continue from".to_string());
+    }
+    // accept states appear in the rules, so the largest state of `a` is among its states
+    let offset = a.states().iter().next_back().map_or(0, |s| s.0 + 1);
+    let b_start = State(b.start_state().unwrap_or_default().0 + offset);
+    let mut bridged = a.clone();
+    for state in a.accept_states.iter() {
+        bridged
+            .rules
+            .push(Rule::new(*state, b_start, vec![0; a.num_tapes]));
+    }
+    let mut composed = Program::merge(&bridged, b, offset)?;
+    composed.start_state = a.start_state;
+    // the first program no longer halts, so only the accept states of the second one count
+    composed.accept_states = b
+        .accept_states
+        .iter()
+        .map(|s| State(s.0 + offset))
+        .collect();
+    Ok(composed)
+}
+
+/// Return a copy of the program with its rules in the given order.
+///
+/// Rule `i` of the result is rule `order[i]` of `program`. Since the interpreter fires the first
+/// rule that applies, moving a rule ahead of another one of the same state can change what the
+/// program does, see [`preserves_semantics`]. Rules of different states never compete, so
+/// reordering them is always safe. It is an error if `order` is not a permutation of the rule
+/// indices.
+pub fn reorder_rules(program: &Program, order: &[usize]) -> Result<Program, String> {
+    if order.len() != program.num_rules() {
+        return Err(format!(
+            "permutation has {} entries but the program has {} rules",
+            order.len(),
+            program.num_rules()
+        ));
+    }
+    let mut seen = vec![false; order.len()];
+    for idx in order {
+        match seen.get_mut(*idx) {
+            None => return Err(format!("rule index {} is out of range", idx)),
+            Some(true) => return Err(format!("rule {} appears more than once", idx)),
+            Some(s) => *s = true,
+        }
+    }
+    Ok(Program {
+        rules: order
+            .iter()
+            .map(|idx| program.rules[*idx].clone())
+            .collect(),
+        ..program.clone()
+    })
+}
+
+/// Determine whether two programs agree when run from each of the sample machines.
+///
+/// The runs from a sample agree if both halt in the same configuration for the same reason, or
+/// both fail with the same kind of error, e.g. both run out of `fuel`. The number of steps taken
+/// is not compared. This is meant to check a [`reorder_rules`] permutation on representative
+/// inputs; agreement on the samples does not prove the programs equivalent.
+pub fn preserves_semantics(
+    orig: &Program,
+    reordered: &Program,
+    samples: &[Machine],
+    fuel: u64,
+) -> bool {
+    samples.iter().all(|machine| {
+        match (
+            interpret(machine.clone(), orig, fuel),
+            interpret(machine.clone(), reordered, fuel),
+        ) {
+            (Ok((_, a, ra)), Ok((_, b, rb))) => a == b && ra == rb,
+            (Err(a), Err(b)) => core::mem::discriminant(&a) == core::mem::discriminant(&b),
+            _ => false,
+        }
+    })
+}
+
+/// State of Tarjan's strongly connected components algorithm, see [`Program::sccs`]
+struct Tarjan<'a> {
+    matrix: &'a [Vec<u32>],
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    sccs: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.next_index);
+        self.lowlink[v] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        for w in 0..self.matrix.len() {
+            if self.matrix[v][w] == 0 {
+                continue;
+            }
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
+                }
+                Some(index) if self.on_stack[w] => self.lowlink[v] = self.lowlink[v].min(index),
+                Some(_) => {}
+            }
+        }
+        if Some(self.lowlink[v]) == self.index[v] {
+            let mut scc = Vec::new();
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                scc.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
+
+/// Determine whether two programs are equal up to how their states are numbered.
+///
+/// Both programs are renumbered in order of first appearance, starting with the start state,
+/// then following the rules, and appending any accept state no rule mentions, and the results
+/// are compared with `==`. So the programs must have
+/// the same rules in the same order, the same tapes, and corresponding start and accept states.
+pub fn structurally_equivalent(a: &Program, b: &Program) -> bool {
+    a.renumber_with(&a.first_appearance_renumbering())
+        == b.renumber_with(&b.first_appearance_renumbering())
+}
+
+/// Compute the set of states reachable from `start` in the state transition graph of a program.
+///
+/// This follows `cur_state -> next_state` edges of every rule, ignoring guards, so a state in the
+/// result may still never be reached by an actual run. The start state is always reachable.
+pub fn reachable_states(program: &Program, start: impl Into<State>) -> BTreeSet<State> {
+    let start = start.into();
+    let mut reachable = BTreeSet::new();
+    let mut pending = vec![start];
+    reachable.insert(start);
+    while let Some(state) = pending.pop() {
+        for rule in program.iter().filter(|r| r.cur_state == state) {
+            if reachable.insert(rule.next_state) {
+                pending.push(rule.next_state);
+            }
+        }
+    }
+    reachable
+}
+
+/// Return the indices of the rules that can never fire because the state they fire in is not
+/// reachable from `start`, see [`reachable_states`].
+pub fn dead_rules(program: &Program, start: impl Into<State>) -> Vec<usize> {
+    let reachable = reachable_states(program, start);
+    program
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| !reachable.contains(&r.cur_state))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Search for non-negative tape weights under which no rule increases the weighted sum of the
+/// tape positions and at least one rule decreases it.
+///
+/// Given weights `w`, the quantity `w · tapes` is bounded below by 0 and, since `w ·
+/// net_effect(r) <= 0` for every rule `r`, never increases. The rules with `w · net_effect(r) <
+/// 0` decrease it by at least 1 each time they fire, so they can only fire finitely often in any
+/// run. If every rule decreases it the program terminates on every machine, otherwise the
+/// weights certify termination of the remaining rules once these have stopped firing.
+///
+/// The weights are found by solving a small linear program, maximizing the total decrease over
+/// all rules with every weight at most 1, and are returned as integers without a common factor.
+/// Returns `None` if no such weights exist, e.g. if some rules undo each other's effects, or if
+/// the adjustments are too large for the linear program to be solved exactly.
+pub fn find_linear_invariant(program: &Program) -> Option<Vec<i32>> {
+    let n = program.num_tapes();
+    let effects: Vec<&[i32]> = program
+        .iter()
+        .filter(|r| !r.is_probe())
+        .map(|r| r.net_effect())
+        .collect();
+    // `e_r · w <= 0` for every rule and `w_t <= 1` for every tape, maximizing `-sum_r e_r · w`
+    let mut rows: Vec<(Vec<i128>, i128)> = effects.iter().map(|e| (effect_row(e, n), 0)).collect();
+    rows.extend((0..n).map(|t| (unit_row(t, n), 1)));
+    let mut objective = vec![0; n];
+    for effect in effects.iter() {
+        for (c, a) in objective.iter_mut().zip(effect.iter()) {
+            *c -= *a as i128;
+        }
+    }
+    let (weights, value) = maximize(&rows, &objective)?;
+    if value <= 0 {
+        return None;
+    }
+    reduced_weights(&weights)
+}
+
+/// Search for non-negative tape weights under which every rule decreases the weighted sum of
+/// the tape positions by at least 1.
+///
+/// Unlike [`find_linear_invariant`], which may leave some rules at a decrease of 0, this is a
+/// ranking function for the whole program: with weights `w` every step decreases `w · tapes`
+/// and the sum never goes below 0, so every run halts within `w · tapes` steps. The weights are
+/// found by maximizing a common decrease `d <= 1` with `e_r · w + d <= 0` for every rule and
+/// every weight at most 1, which has a positive optimum exactly if such weights exist. They are
+/// returned as integers without a common factor. Returns `None` if no such weights exist or the
+/// adjustments are too large for the linear program to be solved exactly.
+pub fn find_ranking_weights(program: &Program) -> Option<Vec<i32>> {
+    let n = program.num_tapes();
+    // the variables are the weights followed by the common decrease `d`
+    let mut rows: Vec<(Vec<i128>, i128)> = program
+        .iter()
+        .filter(|r| !r.is_probe())
+        .map(|r| {
+            let mut row = effect_row(r.net_effect(), n + 1);
+            row[n] = 1;
+            (row, 0)
+        })
+        .collect();
+    rows.extend((0..=n).map(|t| (unit_row(t, n + 1), 1)));
+    let (solution, value) = maximize(&rows, &unit_row(n, n + 1))?;
+    if value <= 0 {
+        return None;
+    }
+    reduced_weights(&solution[..n])
+}
+
+/// The coefficients of a rule's effect as a row of a linear program with `cols` variables
+fn effect_row(effect: &[i32], cols: usize) -> Vec<i128> {
+    let mut row = vec![0; cols];
+    for (c, a) in row.iter_mut().zip(effect.iter()) {
+        *c = *a as i128;
+    }
+    row
+}
+
+/// The row selecting variable `var` of a linear program with `cols` variables
+fn unit_row(var: usize, cols: usize) -> Vec<i128> {
+    let mut row = vec![0; cols];
+    row[var] = 1;
+    row
+}
+
+/// Divide the weights by their greatest common divisor and convert them to `i32`.
+fn reduced_weights(weights: &[i128]) -> Option<Vec<i32>> {
+    let divisor = weights.iter().fold(0, |g, w| gcd(g, *w)).max(1);
+    weights
+        .iter()
+        .map(|w| i32::try_from(w / divisor).ok())
+        .collect()
+}
+
+/// Maximize `objective · x` subject to `a · x <= b` for every row `(a, b)` and `x >= 0`.
+///
+/// Every `b` must be non-negative, so the origin is feasible and no first phase is needed. The
+/// problem is solved with a fraction-free simplex method: the tableau is kept integral and
+/// represents the actual tableau divided by a positive `denom`. Returns the optimal `x` and the
+/// optimal value, both multiplied by the same positive factor, or `None` if the problem is
+/// unbounded or an entry of the tableau overflows `i128`.
+fn maximize(rows: &[(Vec<i128>, i128)], objective: &[i128]) -> Option<(Vec<i128>, i128)> {
+    let n = objective.len();
+    let m = rows.len();
+    let cols = n + m + 1;
+    let mut tableau: Vec<Vec<i128>> = Vec::with_capacity(m + 1);
+    for (i, (a, b)) in rows.iter().enumerate() {
+        let mut row = vec![0; cols];
+        row[..n].copy_from_slice(a);
+        row[n + i] = 1;
+        row[cols - 1] = *b;
+        tableau.push(row);
+    }
+    // the objective row holds the negated coefficients
+    let mut last = vec![0; cols];
+    for (c, o) in last.iter_mut().zip(objective.iter()) {
+        *c = -o;
+    }
+    tableau.push(last);
+    let mut basis: Vec<usize> = (n..n + m).collect();
+    let mut denom: i128 = 1;
+
+    // Bland's rule: enter the first improving column, leave the first row with the best ratio
+    while let Some(col) = (0..cols - 1).find(|j| tableau[m][*j] < 0) {
+        let mut best: Option<usize> = None;
+        for i in (0..m).filter(|i| tableau[*i][col] > 0) {
+            let better = match best {
+                None => true,
+                Some(b) => {
+                    let ratio_i = tableau[i][cols - 1].checked_mul(tableau[b][col])?;
+                    let ratio_b = tableau[b][cols - 1].checked_mul(tableau[i][col])?;
+                    ratio_i.cmp(&ratio_b).then(basis[i].cmp(&basis[b])).is_lt()
+                }
+            };
+            if better {
+                best = Some(i);
+            }
+        }
+        let row = best?;
+        let pivot = tableau[row][col];
+        let pivot_row = tableau[row].clone();
+        for (_, other) in tableau.iter_mut().enumerate().filter(|(i, _)| *i != row) {
+            let factor = other[col];
+            for (a, p) in other.iter_mut().zip(pivot_row.iter()) {
+                *a = pivot
+                    .checked_mul(*a)?
+                    .checked_sub(factor.checked_mul(*p)?)?
+                    / denom;
+            }
+        }
+        denom = pivot;
+        basis[row] = col;
+    }
+
+    let mut solution = vec![0; n];
+    for (row, var) in basis.iter().enumerate() {
+        if *var < n {
+            solution[*var] = tableau[row][cols - 1];
+        }
+    }
+    Some((solution, tableau[m][cols - 1]))
+}
+
+/// Greatest common divisor of two non-negative integers
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// Tests
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_basic() {
+        let rule = Rule::new(0, 0, vec![-1, -2, 3, 4]);
+        assert_eq!(rule.cur_state, 0);
+        assert_eq!(rule.next_state, 0);
+        assert_eq!(rule.rule, vec![-1, -2, 3, 4]);
+    }
+
+    #[test]
+    fn test_guard_action() {
+        let rule = Rule::new(0, 0, vec![-1, -2, 3, 0, 4]);
+        assert_eq!(rule.guard(), vec![(TapeId(0), -1), (TapeId(1), -2)]);
+        assert_eq!(rule.action(), vec![(TapeId(2), 3), (TapeId(4), 4)]);
+
+        let rule = Rule::new(0, 1, vec![0, 0]);
+        assert!(rule.guard().is_empty());
+        assert!(rule.action().is_empty());
+    }
+
+    #[test]
+    fn test_compose() {
+        let a = Rule::new(0, 1, vec![1, -1, 0]);
+        let b = Rule::new(1, 2, vec![2, 0, 1]);
+        assert_eq!(Rule::compose(&a, &b), Some(Rule::new(0, 2, vec![3, -1, 1])));
+
+        // the fused rule makes the same moves in one step
+        let fused = Rule::compose(&a, &b).unwrap();
+        let mut machine = Machine::new(0, vec![0, 1, 0]);
+        assert!(machine.apply_rule(&a) && machine.apply_rule(&b));
+        let mut fused_machine = Machine::new(0, vec![0, 1, 0]);
+        assert!(fused_machine.apply_rule(&fused));
+        assert_eq!(machine, fused_machine);
+
+        // b may not fire after a if it has a guard or fires in another state
+        let guarded = Rule::new(1, 2, vec![0, 0, -1]);
+        assert_eq!(Rule::compose(&a, &guarded), None);
+        assert_eq!(Rule::compose(&a, &Rule::new(2, 2, vec![0, 0, 1])), None);
+        assert_eq!(Rule::compose(&a, &Rule::new(1, 2, vec![0, 1])), None);
+
+        // summing would drop the guard of a: [1, 1, 1] fires on tape 1 at 0, a does not
+        let refill = Rule::new(1, 2, vec![0, 2, 1]);
+        assert_eq!(Rule::compose(&a, &refill), None);
+        let mut blocked = Machine::new(0, vec![0, 0, 0]);
+        assert!(!blocked.apply_rule(&a));
+        assert!(blocked.apply_rule(&Rule::new(0, 2, vec![1, 1, 1])));
+    }
+
+    #[test]
+    fn test_test_rule() {
+        let tape_state: TapeState = TapeState::from(vec![2, 2]);
+        let rule0 = Rule::new(0, 0, vec![0, -1]);
+        let rule1 = Rule::new(0, 0, vec![0, 5]);
+        let rule2 = Rule::new(0, 0, vec![2, 0]);
+        let rule3 = Rule::new(0, 0, vec![3, 3]);
+        let false_rule = Rule::new(0, 0, vec![-7, 0]);
+        assert!(tape_state.test_rule(&rule0, &[]));
+        assert!(tape_state.test_rule(&rule1, &[]));
+        assert!(tape_state.test_rule(&rule2, &[]));
+        assert!(tape_state.test_rule(&rule3, &[]));
+        assert!(!tape_state.test_rule(&false_rule, &[]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_rule() {
+        let tape_state: TapeState = TapeState::from(vec![2, 2]);
+        let wide_rule = Rule::new(0, 0, vec![0, 1, 2]);
+        let _b = tape_state.test_rule(&wide_rule, &[]);
+    }
+
+    #[test]
+    fn test_move_tapes() {
+        // Test move_backwards
+        let mut tape_state: TapeState = TapeState::from(vec![2, 2]);
+        let rule = Rule::new(0, 0, vec![-1, -1]);
+        tape_state.apply_rule(&rule);
+        assert_eq!(tape_state[0], 1);
+        assert_eq!(tape_state[1], 1);
+
+        // Test move_forwards
+        let rule = Rule::new(0, 0, vec![1, 9]);
+        tape_state.apply_rule(&rule);
+        assert_eq!(tape_state[0], 2);
+        assert_eq!(tape_state[1], 10);
+    }
+
+    #[test]
+    fn test_try_apply_rule() {
+        let mut machine = Machine::new(0, vec![1, 0]);
+        assert!(matches!(
+            machine.try_apply_rule(&Rule::new(0, 1, vec![-1, 1])),
+            Ok(true)
+        ));
+        assert_eq!(machine.tapes(), &[0, 1]);
+        // wrong state, then unsatisfied guard
+        assert!(matches!(
+            machine.try_apply_rule(&Rule::new(0, 0, vec![0, 1])),
+            Ok(false)
+        ));
+        assert!(matches!(
+            machine.try_apply_rule(&Rule::new(1, 1, vec![-1, 0])),
+            Ok(false)
+        ));
+
+        // a rule of the wrong width is an error instead of a panic
+        let r = machine.try_apply_rule(&Rule::new(1, 1, vec![1]));
+        assert!(matches!(r, Err(ErrorCode::BadClause { rule: 0 })));
+        assert!(!machine.apply_rule(&Rule::new(1, 1, vec![1, 0, 0])));
+
+        let mut machine = Machine::new(0, vec![0, i32::MAX]);
+        let r = machine.try_apply_rule(&Rule::new(0, 0, vec![1, 1]));
+        assert!(matches!(r, Err(ErrorCode::Overflow { tape: TapeId(1) })));
+        assert_eq!(machine.tapes(), &[0, i32::MAX]);
+    }
+
+    #[test]
+    fn test_apply_rule() {
+        let tape_state: TapeState = TapeState::from(vec![0, 0]);
+        // Rule 0: no guard, no machine state transition
+        let rule0 = Rule::new(0, 0, vec![1, 1]);
+        // Rule 1: move tape 0 back 1, tape 1 forward 2, transition to new machine state
+        let rule1 = Rule::new(0, 1, vec![-1, 2]);
+        // Rule 2: move backwards only, preserve machine state
+        let rule2 = Rule::new(1, 1, vec![-2, -2]);
+
+        // Initial machine state
         let mut machine = Machine {
-            machine_state: 0,
+            machine_state: State(0),
             tape_state,
         };
-        // tape_state: (0, 0), (1, 0), machine_state: 0
-        assert_eq!(machine.machine_state, 0);
-        assert_eq!(machine.tape_state.0, vec![0, 0]);
+        // tape_state: (0, 0), (1, 0), machine_state: 0
+        assert_eq!(machine.machine_state, 0);
+        assert_eq!(*machine.tape_state, vec![0, 0]);
+
+        // Rule 1 doesn't apply b/c of the guard
+        assert!(!machine.apply_rule(&rule1));
+        // Rule 2 doesn't apply b/c the machine state is wrong
+        assert!(!machine.apply_rule(&rule2));
+        // tape_state: [0, 0], machine_state: 0
+        assert_eq!(machine.machine_state, 0);
+        assert_eq!(*machine.tape_state, vec![0, 0]);
+
+        // Rule 0 always applies
+        assert!(machine.apply_rule(&rule0));
+        // tape_state: [1, 1], machine_state: 0
+        assert_eq!(machine.machine_state, 0);
+        assert_eq!(*machine.tape_state, vec![1, 1]);
+
+        assert!(machine.apply_rule(&rule0));
+        assert_eq!(machine.machine_state, 0);
+        assert_eq!(*machine.tape_state, vec![2, 2]);
+
+        assert!(machine.apply_rule(&rule0));
+        assert!(machine.apply_rule(&rule0));
+
+        // Try rule1:
+        assert!(machine.apply_rule(&rule1));
+        assert_eq!(machine.machine_state, 1);
+        assert_eq!(*machine.tape_state, vec![3, 6]);
+
+        // Rule 1 doesn't apply anymore b/c we're in state 1
+        assert!(!machine.apply_rule(&rule1));
+
+        // Rule 2 applies:
+        assert!(machine.apply_rule(&rule2));
+        assert_eq!(machine.machine_state, 1);
+        assert_eq!(*machine.tape_state, vec![1, 4]);
+
+        // Rule 2 doesn't apply anymore b/c tape 0 can't move back 2
+        assert!(!machine.apply_rule(&rule2));
+    }
+
+    #[test]
+    fn test_step() {
+        let rule0 = Rule::new(0, 0, vec![1, -1]);
+        let rule1 = Rule::new(0, 1, vec![0, 0]);
+        let program = Program::new(2, vec![rule0, rule1]);
+        let mut machine = Machine::new(0, vec![0, 2]);
+
+        assert_eq!(machine.step(&program), StepResult::Fired(0));
+        assert_eq!(*machine.tape_state, vec![1, 1]);
+        assert_eq!(machine.step(&program), StepResult::Fired(0));
+        assert_eq!(machine.step(&program), StepResult::Fired(1));
+        assert_eq!(machine.machine_state, 1);
+        assert_eq!(*machine.tape_state, vec![2, 0]);
+        assert_eq!(machine.step(&program), StepResult::Halted);
+
+        // a rule of the wrong width is reported, not applied
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, 1, 1])]);
+        let mut machine = Machine::new(0, vec![0, 0]);
+        assert_eq!(machine.step(&program), StepResult::BadClause(0));
+        assert_eq!(*machine.tape_state, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_machine_accessors() {
+        let machine = Machine::new(3, vec![4, 0, 7]);
+        assert_eq!(machine.machine_state(), 3);
+        assert_eq!(machine.num_tapes(), 3);
+        assert_eq!(machine.tapes(), &[4, 0, 7]);
+        assert_eq!(machine.tape_pos(2), 7);
+        assert_eq!(machine.tape_pos_checked(2), Some(7));
+        assert_eq!(machine.tape_pos_checked(3), None);
+    }
+
+    #[test]
+    fn test_try_new() {
+        let rules = vec![Rule::new(0, 0, vec![1, -1]), Rule::new(0, 1, vec![0, 0])];
+        let program = Program::try_new(2, rules).unwrap();
+        assert_eq!(program.num_rules(), 2);
+
+        let rules = vec![Rule::new(0, 0, vec![1, -1]), Rule::new(0, 1, vec![0, 0, 0])];
+        let err = Program::try_new(2, rules).unwrap_err();
+        assert_eq!(err, "rule 1 has 3 adjustments but program declares 2 tapes");
+
+        let mut program = Program::new(1, vec![]);
+        assert!(program.push_rule(Rule::new(0, 0, vec![1])).is_ok());
+        assert!(program.push_rule(Rule::new(0, 0, vec![1, 1])).is_err());
+        assert_eq!(program.num_rules(), 1);
+    }
+
+    #[test]
+    fn test_tape_state_traits() {
+        let mut tape_state = TapeState::from(vec![3, 0, 7]);
+        assert_eq!(tape_state.len(), 3);
+        assert_eq!(tape_state[2], 7);
+        tape_state[1] += 2;
+        for tp in &mut tape_state {
+            *tp += 1;
+        }
+        assert_eq!(tape_state.iter().sum::<i32>(), 15);
+        assert_eq!((&tape_state).into_iter().max(), Some(&8));
+        assert_eq!(tape_state.as_slice(), &[4, 3, 8]);
+        assert_eq!(
+            tape_state.clone().into_iter().collect::<Vec<_>>(),
+            vec![4, 3, 8]
+        );
+        assert_eq!(tape_state.into_inner(), vec![4, 3, 8]);
+
+        let machine = Machine::new(0, vec![1, 2]);
+        assert_eq!(machine.tape_state()[1], 2);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let tape_state = TapeState::from(vec![2, 2]);
+        let rule = Rule::new(0, 0, vec![1, -1]);
+        assert!(tape_state.test_rule(&rule, &[Some(3), None]));
+        assert!(!tape_state.test_rule(&rule, &[Some(2), None]));
+        // decrements are never limited by a bound
+        assert!(tape_state.test_rule(&rule, &[Some(3), Some(0)]));
+
+        // the adder stops once the bounded tape 0 is full
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])])
+            .with_bounds(vec![Some(3), None])
+            .unwrap();
+        assert_eq!(program.bound(0), Some(3));
+        assert_eq!(program.bound(1), None);
+        let (steps, end_machine, _) =
+            interpret(Machine::new(0, vec![0, 5]), &program, 100).unwrap();
+        assert_eq!(steps, 3);
+        assert_eq!(end_machine.tapes(), &[3, 2]);
+
+        let program = Program::new(2, vec![]);
+        assert!(program.clone().with_bounds(vec![Some(3)]).is_err());
+        assert!(program.with_bounds(vec![Some(-1), None]).is_err());
+    }
+
+    #[test]
+    fn test_rules_by_state() {
+        let rule0 = Rule::new(0, 0, vec![1, -1]);
+        let rule1 = Rule::new(1, 0, vec![0, 0]);
+        let rule2 = Rule::new(0, 1, vec![0, 0]);
+        let program = Program::new(2, vec![rule0, rule1, rule2]);
+        let index = program.rules_by_state();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[&State(0)], vec![0, 2]);
+        assert_eq!(index[&State(1)], vec![1]);
+    }
+
+    #[test]
+    fn test_display() {
+        let rule = Rule::new(0, 2, vec![1, -1, 0]);
+        assert_eq!(rule.to_string(), "0 [1, -1, 0] 2");
+        let program = Program::new(3, vec![rule]);
+        assert_eq!(format!("{}", program), "tapes: 3\n0 [1, -1, 0] 2\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let program = crate::arith::mult_program();
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(json.starts_with(r#"{"num_tapes":4,"rules":[{"cur_state":0"#));
+        let decoded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, program);
+
+        let machine = Machine::new(1, vec![0, 3, 0, 2]);
+        let json = serde_json::to_string(&machine).unwrap();
+        assert_eq!(json, r#"{"machine_state":1,"tape_state":[0,3,0,2]}"#);
+        let decoded: Machine = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, machine);
+
+        let snapshot = machine.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(json, r#"{"state":1,"tapes":[0,3,0,2]}"#);
+        let decoded: MachineSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        // the optional parts of a program survive the round trip
+        let program = Program::new(2, vec![Rule::new(0, 1, vec![-1, 1])])
+            .with_bounds(vec![None, Some(5)])
+            .unwrap()
+            .with_tape_names(vec!["x".to_string(), "y".to_string()])
+            .unwrap()
+            .with_start_state(0)
+            .unwrap()
+            .with_accept_states(vec![1])
+            .unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let decoded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, program);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_validation() {
+        let rule = r#"{"cur_state":0,"next_state":0,"rule":[-1,1]}"#;
+        let program = |extra: &str| format!(r#"{{"num_tapes":2,"rules":[{}]{}}}"#, rule, extra);
+        assert!(serde_json::from_str::<Program>(&program("")).is_ok());
+        for (extra, message) in [
+            (
+                r#","bounds":[5]"#,
+                "1 bounds given but program declares 2 tapes",
+            ),
+            (r#","tape_names":["a","a"]"#, "tape 1 reuses the name `a`"),
+            (
+                r#","start_state":1"#,
+                "start state 1 is not the current state",
+            ),
+            (r#","accept_states":[7]"#, "accept state 7 does not appear"),
+        ] {
+            let err = serde_json::from_str::<Program>(&program(extra)).unwrap_err();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+        let err = serde_json::from_str::<Program>(
+            r#"{"num_tapes":3,"rules":[{"cur_state":0,"next_state":0,"rule":[-1,1]}]}"#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("rule 0 has 2 adjustments"),
+            "{}",
+            err
+        );
+        let err = serde_json::from_str::<Program>(r#"{"num_tapes":0,"rules":[]}"#).unwrap_err();
+        assert!(err.to_string().contains("0 tapes"), "{}", err);
+        let probe =
+            r#"{"num_tapes":1,"rules":[{"cur_state":0,"next_state":0,"rule":[0],"probe":"a\"b"}]}"#;
+        let err = serde_json::from_str::<Program>(probe).unwrap_err();
+        assert!(
+            err.to_string().contains("cannot be written as m3"),
+            "{}",
+            err
+        );
+
+        let err = serde_json::from_str::<Machine>(r#"{"machine_state":0,"tape_state":[1,-2]}"#)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("tape 1 has negative initial position -2"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_to_m3_string() {
+        let rule0 = Rule::new(0, 0, vec![1, -1, 0]);
+        let rule1 = Rule::new(0, 2, vec![0, 0, 5]);
+        let program = Program::new(3, vec![rule0, rule1]);
+        assert_eq!(
+            program.to_m3_string(),
+            "tapes: 3\n0 [1, -1, 0] 0\n0 [0, 0, 5] 2\n"
+        );
+    }
+
+    #[test]
+    fn test_find_linear_invariant() {
+        let check = |program: &Program, weights: &[i32]| {
+            let decrease: Vec<i64> = program
+                .iter()
+                .map(|r| {
+                    r.net_effect()
+                        .iter()
+                        .zip(weights)
+                        .map(|(a, w)| i64::from(*a) * i64::from(*w))
+                        .sum()
+                })
+                .collect();
+            assert!(weights.iter().all(|w| *w >= 0));
+            assert!(decrease.iter().all(|d| *d <= 0), "{:?}", decrease);
+            assert!(decrease.iter().any(|d| *d < 0), "{:?}", decrease);
+        };
+
+        let adder = crate::arith::adder_program();
+        assert_eq!(find_linear_invariant(&adder), Some(vec![0, 1]));
+
+        for program in [
+            crate::arith::mult_program(),
+            crate::arith::power_program(),
+            crate::arith::monus_program(),
+        ] {
+            let weights = find_linear_invariant(&program).expect("no invariant found");
+            check(&program, &weights);
+        }
+
+        // a rule that only grows a tape, and two rules undoing each other
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![1])]);
+        assert_eq!(find_linear_invariant(&program), None);
+        let program = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![1, -1]), Rule::new(0, 0, vec![-1, 1])],
+        );
+        assert_eq!(find_linear_invariant(&program), None);
+
+        // probes have no effect to account for
+        let mut rules: Vec<Rule> = adder.iter().cloned().collect();
+        rules.push(Rule::probe(0, vec![0, 0], "done"));
+        let probed = Program::new(2, rules);
+        assert_eq!(find_linear_invariant(&probed), Some(vec![0, 1]));
+        let r = interpret_auto(Machine::new(0, vec![2, 3]), &probed);
+        assert!(r.is_ok(), "{:?}", r);
+        assert_eq!(find_linear_invariant(&Program::new(2, vec![])), None);
+
+        // large adjustments overflow the exact simplex, which gives up instead of panicking
+        let program = Program::new(
+            4,
+            vec![
+                Rule::new(0, 0, vec![111091438, -693714981, -678759465, 710144432]),
+                Rule::new(0, 0, vec![901366272, -342300401, -491400604, 373838113]),
+                Rule::new(0, 0, vec![543825821, -563607104, -378859009, 202922217]),
+                Rule::new(0, 0, vec![-224468600, -4374760, -453303510, -861612281]),
+                Rule::new(0, 0, vec![211507718, -388303639, 825065003, -164074553]),
+            ],
+        );
+        if let Some(weights) = find_linear_invariant(&program) {
+            check(&program, &weights);
+        }
+        find_ranking_weights(&program);
+        let r = interpret_auto(Machine::new(0, vec![1, 2, 3, 4]), &program);
+        assert!(
+            matches!(r, Ok(_) | Err(ErrorCode::TerminationUnknown)),
+            "{:?}",
+            r
+        );
+    }
+
+    #[test]
+    fn test_render_ascii() {
+        let machine = Machine::new(1, vec![3, 0]);
+        assert_eq!(
+            render_ascii(&machine),
+            "state: 1\ntape 0: ———● (pos 3)\ntape 1: ● (pos 0)\n"
+        );
+
+        // large positions are scaled down together
+        let machine = Machine::new(0, vec![i32::MAX, i32::MAX / 2, 0]);
+        let rendered = render_ascii(&machine);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            format!("tape 0: {}● (pos {})", "—".repeat(40), i32::MAX)
+        );
+        assert!(lines[2].starts_with(&format!("tape 1: {}●", "—".repeat(19))));
+        assert_eq!(lines[3], "tape 2: ● (pos 0)");
+
+        // tape labels are aligned
+        let rendered = render_ascii(&Machine::new(0, vec![0; 11]));
+        assert!(rendered.contains("\ntape  0: ●"), "{}", rendered);
+        assert!(rendered.contains("\ntape 10: ●"), "{}", rendered);
+
+        // negative positions are drawn at 0 and do not affect the scale
+        let machine = Machine::new(0, vec![-5, 3, i32::MIN]);
+        assert_eq!(
+            render_ascii(&machine),
+            format!(
+                "state: 0\ntape 0: ● (pos -5)\ntape 1: ———● (pos 3)\ntape 2: ● (pos {})\n",
+                i32::MIN
+            )
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let rule0 = Rule::new(0, 0, vec![1, -1]);
+        let rule1 = Rule::new(0, 1, vec![0, 0]);
+        let program = Program::new(2, vec![rule0, rule1]);
+        let expected = "digraph program {
+    0;
+    1;
+    0 -> 0 [label=\"[1, -1]\"];
+    0 -> 1 [label=\"[0, 0]\"];
+}
+";
+        assert_eq!(to_dot(&program), expected);
+    }
+
+    #[test]
+    fn test_to_dot_tape_names() {
+        let rule0 = Rule::new(0, 0, vec![1, -1]);
+        let rule1 = Rule::new(0, 1, vec![0, 0]);
+        let program = Program::new(2, vec![rule0, rule1])
+            .with_tape_names(vec!["acc".to_string(), "x".to_string()])
+            .unwrap();
+        let dot = to_dot(&program);
+        assert!(dot.contains("0 -> 0 [label=\"acc+1, x-1\"];"), "{}", dot);
+        assert!(dot.contains("0 -> 1 [label=\"\"];"), "{}", dot);
+    }
+
+    #[test]
+    fn test_diff_machines() {
+        let a = Machine::new(0, vec![1, 2, 3]);
+        assert_eq!(diff_machines(&a, &a.clone()), vec![]);
+
+        let b = Machine::new(0, vec![1, 5, 3]);
+        let expected = TapeDiff::Tape {
+            tape: TapeId(1),
+            a: 2,
+            b: 5,
+        };
+        assert_eq!(diff_machines(&a, &b), vec![expected]);
 
-        // Rule 1 doesn't apply b/c of the guard
-        assert!(!machine.apply_rule(&rule1));
-        // Rule 2 doesn't apply b/c the machine state is wrong
-        assert!(!machine.apply_rule(&rule2));
-        // tape_state: [0, 0], machine_state: 0
-        assert_eq!(machine.machine_state, 0);
-        assert_eq!(machine.tape_state.0, vec![0, 0]);
+        // a state mismatch comes first, extra tapes of the wider machine are ignored
+        let c = Machine::new(1, vec![1, 2, 4, 7]);
+        let expected = vec![
+            TapeDiff::State {
+                a: State(0),
+                b: State(1),
+            },
+            TapeDiff::Tape {
+                tape: TapeId(2),
+                a: 3,
+                b: 4,
+            },
+        ];
+        assert_eq!(diff_machines(&a, &c), expected);
+    }
 
-        // Rule 0 always applies
-        assert!(machine.apply_rule(&rule0));
-        // tape_state: [1, 1], machine_state: 0
-        assert_eq!(machine.machine_state, 0);
-        assert_eq!(machine.tape_state.0, vec![1, 1]);
+    #[test]
+    fn test_with_start_state() {
+        let rule0 = Rule::new(0, 1, vec![1]);
+        let rule1 = Rule::new(1, 1, vec![-1]);
+        let program = Program::new(1, vec![rule0, rule1]);
+        assert_eq!(program.start_state(), None);
+        let program = program.with_start_state(1).unwrap();
+        assert_eq!(program.start_state(), Some(State(1)));
+        assert!(program.to_m3_string().contains("start: 1"));
+        let err = program.with_start_state(2).unwrap_err();
+        assert!(err.contains("start state 2"), "{}", err);
+    }
 
-        assert!(machine.apply_rule(&rule0));
-        assert_eq!(machine.machine_state, 0);
-        assert_eq!(machine.tape_state.0, vec![2, 2]);
+    #[test]
+    fn test_codegen_rust() {
+        let program = crate::arith::mult_program();
+        let source = codegen_rust(&program, "mult");
+        let file = syn::parse_file(&source).expect("generated code does not parse");
+        assert!(matches!(&file.items[..], [syn::Item::Fn(f)] if f.sig.ident == "mult"));
+        assert!(source.contains("i64::from(tapes[1]) >= 1"), "{}", source);
 
-        assert!(machine.apply_rule(&rule0));
-        assert!(machine.apply_rule(&rule0));
+        // bounds become part of the guard, a rule without a guard ends the chain
+        let rule0 = Rule::new(0, 1, vec![1, -1]);
+        let rule1 = Rule::new(1, 1, vec![0, 1]);
+        let program = Program::new(2, vec![rule0, rule1])
+            .with_bounds(vec![Some(3), None])
+            .unwrap();
+        let source = codegen_rust(&program, "bounded");
+        syn::parse_file(&source).expect("generated code does not parse");
+        assert!(
+            source.contains("tapes[0] <= 2 && i64::from(tapes[1]) >= 1"),
+            "{}",
+            source
+        );
+        assert!(source.contains("if tapes[1] > 2147483646"), "{}", source);
 
-        // Try rule1:
-        assert!(machine.apply_rule(&rule1));
-        assert_eq!(machine.machine_state, 1);
-        assert_eq!(machine.tape_state.0, vec![3, 6]);
+        // a decrement by `i32::MIN` still compiles, the overflow check precedes every move
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![i32::MIN, i32::MAX])]);
+        let source = codegen_rust(&program, "extreme");
+        syn::parse_file(&source).expect("generated code does not parse");
+        assert!(
+            source.contains("i64::from(tapes[0]) >= 2147483648"),
+            "{}",
+            source
+        );
+        let check = source.find("if tapes[1] > 0").expect("no overflow check");
+        assert!(check < source.find("tapes[0] +=").unwrap(), "{}", source);
 
-        // Rule 1 doesn't apply anymore b/c we're in state 1
-        assert!(!machine.apply_rule(&rule1));
+        // probes are left out, a state with only probes halts
+        let program = Program::new(
+            2,
+            vec![
+                Rule::probe(0, vec![0, -1], "pass"),
+                Rule::new(0, 0, vec![1, -1]),
+                Rule::probe(1, vec![0, 0], "done"),
+            ],
+        );
+        let source = codegen_rust(&program, "probed");
+        syn::parse_file(&source).expect("generated code does not parse");
+        assert_eq!(source.matches("tapes[1]) >= 1").count(), 1, "{}", source);
+        assert!(!source.contains("1 => {"), "{}", source);
+        let without = codegen_rust(&program.without_probes(), "probed");
+        assert_eq!(
+            source.lines().skip(1).collect::<Vec<_>>(),
+            without.lines().skip(1).collect::<Vec<_>>()
+        );
+    }
 
-        // Rule 2 applies:
-        assert!(machine.apply_rule(&rule2));
-        assert_eq!(machine.machine_state, 1);
-        assert_eq!(machine.tape_state.0, vec![1, 4]);
+    #[test]
+    fn test_renumber_states() {
+        let rule0 = Rule::new(5, 5, vec![1, -1]);
+        let rule1 = Rule::new(5, 42, vec![0, 0]);
+        let rule2 = Rule::new(42, 0, vec![-1, 0]);
+        let program = Program::new(2, vec![rule0, rule1, rule2])
+            .with_start_state(5)
+            .unwrap();
+        assert_eq!(program.num_states(), 3);
+        let renumbered = program.renumber_states();
+        let states: Vec<(State, State)> = renumbered
+            .iter()
+            .map(|r| (r.cur_state(), r.next_state()))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (State(1), State(1)),
+                (State(1), State(2)),
+                (State(2), State(0))
+            ]
+        );
+        assert_eq!(renumbered.start_state(), Some(State(1)));
+        assert_eq!(renumbered.renumber_states(), renumbered);
 
-        // Rule 2 doesn't apply anymore b/c tape 0 can't move back 2
-        assert!(!machine.apply_rule(&rule2));
+        let (steps, end, _) = interpret(Machine::new(5, vec![0, 3]), &program, 100).unwrap();
+        let (renumbered_steps, renumbered_end, _) =
+            interpret(Machine::new(1, vec![0, 3]), &renumbered, 100).unwrap();
+        assert_eq!(steps, renumbered_steps);
+        assert_eq!(end.tapes(), renumbered_end.tapes());
+    }
+
+    #[test]
+    fn test_to_counter_machine() {
+        let program = crate::arith::adder_program();
+        let expected = "0: DEC 1 1 2\n1: INC 0 0\n2: HALT\n";
+        assert_eq!(to_counter_machine(&program).unwrap(), expected);
+
+        // rules without increments jump through an INC/DEC pair, state 2 halts
+        let rule0 = Rule::new(0, 1, vec![0, -1]);
+        let rule1 = Rule::new(0, 2, vec![0, 0]);
+        let rule2 = Rule::new(1, 0, vec![2, 0]);
+        let program = Program::new(2, vec![rule0, rule1, rule2]);
+        let expected = "0: DEC 1 1 3\n1: INC 0 2\n2: DEC 0 5 5\n3: INC 0 4\n4: DEC 0 7 7\n\
+                        5: INC 0 6\n6: INC 0 0\n7: HALT\n";
+        assert_eq!(to_counter_machine(&program).unwrap(), expected);
+
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -2])]);
+        let err = to_counter_machine(&program).unwrap_err();
+        assert!(err.contains("rule 0 does not decrement"), "{}", err);
+
+        // huge increments are not expanded one instruction at a time
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![i32::MAX, i32::MAX])]);
+        let err = to_counter_machine(&program).unwrap_err();
+        assert!(
+            err.contains("rule 0 increments its tapes by 4294967294"),
+            "{}",
+            err
+        );
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![1 << 16])]);
+        assert_eq!(
+            to_counter_machine(&program).unwrap().lines().count(),
+            1 << 16
+        );
+
+        // probes are left out of the listing
+        let mut rules = vec![Rule::probe(0, vec![-1, 0], "tape 0 is set")];
+        rules.extend(crate::arith::adder_program().iter().cloned());
+        let probed = Program::new(2, rules);
+        assert_eq!(
+            to_counter_machine(&probed).unwrap(),
+            "0: DEC 1 1 2\n1: INC 0 0\n2: HALT\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be written as m3")]
+    fn test_probe_message_quote() {
+        Rule::probe(0, vec![0], "say \"hi\"");
+    }
+
+    #[test]
+    fn test_compose_sequential() {
+        // add tape 1 into tape 0, then tape 2 into tape 0
+        let first = Program::new(3, vec![Rule::new(0, 0, vec![1, -1, 0])])
+            .with_accept_states(vec![0])
+            .unwrap();
+        let second = Program::new(3, vec![Rule::new(0, 0, vec![1, 0, -1])]);
+        let composed = compose_sequential(&first, &second).unwrap();
+        let rules: Vec<Rule> = composed.iter().cloned().collect();
+        assert_eq!(
+            rules,
+            vec![
+                Rule::new(0, 0, vec![1, -1, 0]),
+                Rule::new(0, 1, vec![0, 0, 0]),
+                Rule::new(1, 1, vec![1, 0, -1]),
+            ]
+        );
+        let machine = Machine::new(0, vec![0, 3, 4]);
+        let (steps, end, _) = interpret(machine, &composed, 100).unwrap();
+        assert_eq!(end.tapes(), &[7, 0, 0]);
+        assert_eq!(end.machine_state(), 1);
+        assert_eq!(steps, 3 + 1 + 4);
+
+        // the bridge starts the second program in its declared start state
+        let second = Program::new(
+            3,
+            vec![
+                Rule::new(0, 0, vec![1, 0, -1]),
+                Rule::new(1, 1, vec![0, 1, -1]),
+            ],
+        )
+        .with_start_state(1)
+        .unwrap();
+        let composed = compose_sequential(&first, &second).unwrap();
+        let machine = Machine::new(0, vec![0, 3, 4]);
+        let (_, end, _) = interpret(machine, &composed, 100).unwrap();
+        assert_eq!(end.tapes(), &[3, 4, 0]);
+        assert_eq!(composed.start_state(), None);
+
+        // only halting in an accept state continues with the second program
+        let first = Program::new(
+            3,
+            vec![
+                Rule::new(0, 0, vec![1, -1, 0]),
+                Rule::new(2, 2, vec![-1, 0, 0]),
+            ],
+        )
+        .with_accept_states(vec![0])
+        .unwrap();
+        let composed = compose_sequential(&first, &second).unwrap();
+        let (_, end, _) = interpret(Machine::new(0, vec![0, 3, 4]), &composed, 100).unwrap();
+        assert_eq!(end.tapes(), &[3, 4, 0]);
+        let (_, end, _) = interpret(Machine::new(2, vec![2, 3, 4]), &composed, 100).unwrap();
+        assert_eq!(end.machine_state(), 2);
+        assert_eq!(end.tapes(), &[0, 3, 4]);
+
+        let err = compose_sequential(&first, &crate::arith::adder_program()).unwrap_err();
+        assert!(err.contains("3 and 2 tapes"), "{}", err);
+        let no_accept = Program::new(3, vec![Rule::new(0, 0, vec![1, -1, 0])]);
+        let err = compose_sequential(&no_accept, &second).unwrap_err();
+        assert!(err.contains("no accept states"), "{}", err);
+    }
+
+    #[test]
+    fn test_reorder_rules() {
+        let mult = crate::arith::mult_program();
+        let samples: Vec<Machine> = (0..4)
+            .flat_map(|x| (1..4).map(move |y| Machine::new(0, vec![0, x, 0, y - 1])))
+            .collect();
+
+        // the rules of state 1 can move ahead of those of state 0
+        let hot_first = reorder_rules(&mult, &[2, 3, 0, 1]).unwrap();
+        assert_eq!(hot_first.rules[0], mult.rules[2]);
+        assert_eq!(hot_first.num_tapes(), mult.num_tapes());
+        assert!(preserves_semantics(&mult, &hot_first, &samples, 1000));
+
+        // rule 1 always applies in state 0, so moving it first skips the additions
+        let swapped = reorder_rules(&mult, &[1, 0, 2, 3]).unwrap();
+        assert!(!preserves_semantics(&mult, &swapped, &samples, 1000));
+
+        assert!(reorder_rules(&mult, &[0, 1, 2]).is_err());
+        assert!(reorder_rules(&mult, &[0, 1, 2, 4]).is_err());
+        assert!(reorder_rules(&mult, &[0, 1, 1, 3]).is_err());
+    }
+
+    #[test]
+    fn test_program_macro() {
+        let program = crate::program! {
+            tapes: 3;
+            0 => 0 [1, -1, 0];
+            0 => 1 [0, 0, -5];
+            1 => (1 + 1) [0, 2 * 3, 0,];
+        };
+        let expected = Program::new(
+            3,
+            vec![
+                Rule::new(0, 0, vec![1, -1, 0]),
+                Rule::new(0, 1, vec![0, 0, -5]),
+                Rule::new(1, 2, vec![0, 6, 0]),
+            ],
+        );
+        assert_eq!(program, expected);
+
+        let empty = crate::program! { tapes: 1; };
+        assert_eq!(empty, Program::new(1, vec![]));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid program")]
+    fn test_program_macro_width() {
+        crate::program! {
+            tapes: 2;
+            0 => 0 [1, -1];
+            0 => 1 [0, -5, 0];
+        };
+    }
+
+    #[test]
+    fn test_adjacency_matrix_and_sccs() {
+        let mult = crate::arith::mult_program();
+        assert_eq!(mult.adjacency_matrix(), vec![vec![1, 1], vec![1, 1]]);
+        assert_eq!(mult.sccs(), vec![vec![State(0), State(1)]]);
+
+        // a chain 3 -> 5 -> 9 with a self-loop on 5 and a loop 9 <-> 12
+        let program = Program::new(
+            1,
+            vec![
+                Rule::new(3, 5, vec![0]),
+                Rule::new(5, 5, vec![1]),
+                Rule::new(5, 9, vec![0]),
+                Rule::new(9, 12, vec![-1]),
+                Rule::new(12, 9, vec![0]),
+                Rule::new(12, 9, vec![1]),
+            ],
+        );
+        assert_eq!(
+            program.adjacency_matrix(),
+            vec![
+                vec![0, 1, 0, 0],
+                vec![0, 1, 1, 0],
+                vec![0, 0, 0, 1],
+                vec![0, 0, 2, 0],
+            ]
+        );
+        assert_eq!(
+            program.sccs(),
+            vec![vec![State(9), State(12)], vec![State(5)], vec![State(3)],]
+        );
+        assert!(Program::new(1, vec![]).sccs().is_empty());
+    }
+
+    #[test]
+    fn test_tape_phase_report() {
+        // phase a moves tape 1 onto tape 0 using tape 2 as scratch, phase b moves tape 3 onto
+        // tape 0 and reuses tape 2
+        let a = Program::new(
+            4,
+            vec![
+                Rule::new(0, 0, vec![1, -1, 1, 0]),
+                Rule::new(0, 1, vec![0, 0, 0, 0]),
+            ],
+        );
+        let b = Program::new(4, vec![Rule::new(0, 0, vec![1, 0, 1, -1])]);
+        let program = Program::merge(&a, &b, 1).unwrap();
+        let sccs = program.sccs();
+        let phase = |s: usize| sccs.iter().position(|scc| scc.contains(&State(s))).unwrap();
+        let mut shared = vec![phase(0), phase(1)];
+        shared.sort_unstable();
+        assert_eq!(
+            program.tape_phase_report(0),
+            vec![(TapeId(0), shared.clone()), (TapeId(2), shared)]
+        );
+        // from the second phase on nothing is shared
+        assert!(program.tape_phase_report(1).is_empty());
+
+        // a probe in the first phase does not move tape 3
+        let mut rules: Vec<Rule> = program.iter().cloned().collect();
+        rules.push(Rule::probe(0, vec![0, 0, 0, -1], "tape 3 is set"));
+        let probed = Program::new(4, rules);
+        assert_eq!(probed.tape_phase_report(0), program.tape_phase_report(0));
+    }
+
+    #[test]
+    fn test_structurally_equivalent() {
+        let adder = crate::arith::adder_program();
+        let shifted = Program::new(2, vec![Rule::new(7, 7, vec![1, -1])]);
+        assert_ne!(adder, shifted);
+        assert!(structurally_equivalent(&adder, &shifted));
+
+        // swapping the numbers of two states is only a renumbering
+        let a = Program::new(1, vec![Rule::new(0, 1, vec![1]), Rule::new(1, 0, vec![-1])]);
+        let b = Program::new(1, vec![Rule::new(1, 0, vec![1]), Rule::new(0, 1, vec![-1])]);
+        assert!(structurally_equivalent(&a, &b));
+        assert!(structurally_equivalent(
+            &a.clone().with_start_state(1).unwrap(),
+            &b.clone().with_start_state(0).unwrap()
+        ));
+        assert!(!structurally_equivalent(
+            &a,
+            &b.clone().with_start_state(1).unwrap()
+        ));
+
+        // the rule order and adjustments still matter
+        let c = Program::new(1, vec![Rule::new(1, 0, vec![-1]), Rule::new(0, 1, vec![1])]);
+        assert!(!structurally_equivalent(&a, &c));
+        let d = Program::new(1, vec![Rule::new(0, 1, vec![1]), Rule::new(1, 1, vec![-1])]);
+        assert!(!structurally_equivalent(&a, &d));
+    }
+
+    #[test]
+    fn test_accept_states() {
+        // a recognizer for even numbers, state 0 is even and state 1 odd
+        let rule0 = Rule::new(0, 1, vec![-1]);
+        let rule1 = Rule::new(1, 0, vec![-1]);
+        let program = Program::new(1, vec![rule0, rule1]);
+        let (_, _, reason) = interpret(Machine::new(0, vec![4]), &program, 100).unwrap();
+        assert_eq!(reason, HaltReason::Halted);
+
+        let program = program.with_accept_states(vec![0]).unwrap();
+        assert_eq!(program.accept_states().len(), 1);
+        let (_, _, reason) = interpret(Machine::new(0, vec![4]), &program, 100).unwrap();
+        assert_eq!(reason, HaltReason::Accepted);
+        let (_, _, reason) = interpret(Machine::new(0, vec![3]), &program, 100).unwrap();
+        assert_eq!(reason, HaltReason::Halted);
+        let (_, _, reason) = interpret(Machine::new(0, vec![0]), &program, 100).unwrap();
+        assert_eq!(reason, HaltReason::Accepted);
+        let mut machine = Machine::new(1, vec![0]);
+        let (_, reason) = interpret_mut(&mut machine, &program, 100).unwrap();
+        assert_eq!(reason, HaltReason::NoRuleFired);
+
+        // renumbering and merging carry the accept states along
+        let renumbered = Program::new(1, vec![Rule::new(3, 7, vec![-1])])
+            .with_accept_states(vec![7])
+            .unwrap()
+            .renumber_states();
+        assert!(renumbered.is_accepting(1));
+        let merged = Program::merge(&program, &renumbered, 2).unwrap();
+        let accept: Vec<State> = merged.accept_states().iter().copied().collect();
+        assert_eq!(accept, vec![State(0), State(3)]);
+
+        let err = program.with_accept_states(vec![2]).unwrap_err();
+        assert!(err.contains("accept state 2 does not appear"), "{}", err);
+    }
+
+    #[test]
+    fn test_reachability() {
+        // state 3 is orphaned: it only transitions to reachable states
+        let rule0 = Rule::new(0, 1, vec![1]);
+        let rule1 = Rule::new(1, 2, vec![1]);
+        let rule2 = Rule::new(3, 0, vec![1]);
+        let rule3 = Rule::new(2, 2, vec![-1]);
+        let program = Program::new(1, vec![rule0, rule1, rule2, rule3]);
+        let expected: BTreeSet<State> = (0..3).map(State).collect();
+        assert_eq!(reachable_states(&program, 0), expected);
+        assert_eq!(dead_rules(&program, 0), vec![2]);
+
+        // everything is reachable from the orphan
+        assert!(dead_rules(&program, 3).is_empty());
+        let expected = BTreeSet::from([State(2)]);
+        assert_eq!(reachable_states(&program, 2), expected);
+        assert_eq!(dead_rules(&program, 2), vec![0, 1, 2]);
     }
 
     #[test]
@@ -346,8 +4079,8 @@ mod test {
         let program = Program::new(3, vec![rule0, rule1, rule2]);
 
         let machine = Machine {
-            machine_state: 0,
-            tape_state: TapeState(vec![0, 0, 5]),
+            machine_state: State(0),
+            tape_state: TapeState::from(vec![0, 0, 5]),
         };
 
         // machine transitions:
@@ -356,9 +4089,862 @@ mod test {
         //   - rule 2 will then fire 5 times, moving tape 0 to 0, tape 1 to 5, and tape 2 to 10
         let end_machine = interpret(machine, &program, 1000);
         assert!(end_machine.is_ok());
-        let (_, end_machine) = end_machine.unwrap();
+        let (_, end_machine, _) = end_machine.unwrap();
         println!("end machine: {:?}", end_machine);
         assert_eq!(end_machine.machine_state, 1);
-        assert_eq!(end_machine.tape_state.0, vec![0, 5, 10]);
+        assert_eq!(*end_machine.tape_state, vec![0, 5, 10]);
+    }
+
+    #[test]
+    fn test_clone_reuse() {
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+        let initial = Machine::new(0, vec![1, 2]);
+        let (_, first, _) = interpret(initial.clone(), &program.clone(), 100).unwrap();
+        let (_, second, _) = interpret(initial.clone(), &program, 100).unwrap();
+        assert_eq!(first.tapes(), second.tapes());
+        assert_eq!(initial.tapes(), &[1, 2]);
+    }
+
+    #[test]
+    fn test_interpret_batch() {
+        let program = crate::arith::mult_program();
+        let inits: Vec<Machine> = (1..8)
+            .flat_map(|x| (1..8).map(move |y| Machine::new(0, vec![0, x, 0, y - 1])))
+            .collect();
+        let batch = interpret_batch(&program, inits.clone(), 1000);
+        assert_eq!(batch.len(), inits.len());
+        for (init, result) in inits.into_iter().zip(batch) {
+            let (steps, end_machine, _) = result.unwrap();
+            let (serial_steps, serial_machine, _) = interpret(init, &program, 1000).unwrap();
+            assert_eq!(steps, serial_steps);
+            assert_eq!(end_machine.tapes(), serial_machine.tapes());
+        }
+    }
+
+    #[test]
+    fn test_error_code_display() {
+        let program = crate::arith::adder_program();
+        let err = interpret(Machine::new(0, vec![0, 5]), &program, 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "out of fuel after 2 steps in state 0 with tapes [2, 3]"
+        );
+        let err = interpret_checked(Machine::new(3, vec![0, 5]), &program, 2).unwrap_err();
+        assert_eq!(err.to_string(), "no rule fires in the initial state 3");
+        assert_eq!(
+            ErrorCode::Overflow { tape: TapeId(1) }.to_string(),
+            "tape 1 overflowed"
+        );
+    }
+
+    #[test]
+    fn test_interpret_checked() {
+        let rule0 = Rule::new(0, 1, vec![1, -1]);
+        let rule1 = Rule::new(1, 2, vec![0, 0]);
+        let program = Program::new(2, vec![rule0, rule1]);
+        let expected: BTreeSet<State> = (0..3).map(State).collect();
+        assert_eq!(program.states(), expected);
+
+        let (steps, _, reason) =
+            interpret_checked(Machine::new(0, vec![0, 1]), &program, 10).unwrap();
+        assert_eq!(steps, 2);
+        assert_eq!(reason, HaltReason::Halted);
+
+        // state 2 only appears as a next state, so a machine starting there halts immediately
+        let r = interpret_checked(Machine::new(2, vec![0, 1]), &program, 10);
+        assert!(matches!(
+            r,
+            Err(ErrorCode::InvalidStart { state: State(2) })
+        ));
+        let r = interpret_checked(Machine::new(7, vec![0, 1]), &program, 10);
+        assert!(matches!(
+            r,
+            Err(ErrorCode::InvalidStart { state: State(7) })
+        ));
+        let (steps, _, reason) = interpret(Machine::new(7, vec![0, 1]), &program, 10).unwrap();
+        assert_eq!(steps, 0);
+        assert_eq!(reason, HaltReason::NoRuleFired);
+    }
+
+    #[test]
+    fn test_interpret_errors() {
+        // the last configuration is reported when running out of fuel
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+        let machine = Machine::new(0, vec![0, 10]);
+        match interpret(machine, &program, 4) {
+            Err(ErrorCode::OutOfFuel { steps, machine }) => {
+                assert_eq!(steps, 4);
+                assert_eq!(machine.tapes(), &[4, 6]);
+            }
+            r => panic!("expected out of fuel, got {:?}", r),
+        }
+
+        // the offending rule is reported for a malformed clause
+        let rule0 = Rule::new(1, 1, vec![1, -1]);
+        let rule1 = Rule::new(0, 0, vec![1]);
+        let program = Program::new(2, vec![rule0, rule1]);
+        let machine = Machine::new(0, vec![0, 10]);
+        let r = interpret(machine, &program, 4);
+        assert!(matches!(r, Err(ErrorCode::BadClause { rule: 1 })));
+    }
+
+    #[test]
+    fn test_interpret_mut() {
+        let program = crate::arith::mult_program();
+        let mut machine = Machine::new(0, vec![0, 0, 0, 0]);
+        for (x, y) in [(2, 3), (5, 1), (4, 4)] {
+            machine.reset(0, vec![0, x, 0, y - 1]);
+            let (steps, reason) = interpret_mut(&mut machine, &program, 1000).unwrap();
+            assert_eq!(machine.tape_pos(0), x * y);
+            let (expected_steps, expected, _) =
+                interpret(Machine::new(0, vec![0, x, 0, y - 1]), &program, 1000).unwrap();
+            assert_eq!(steps, expected_steps);
+            assert_eq!(machine, expected);
+            assert_eq!(reason, HaltReason::Halted);
+        }
+
+        machine.reset(0, vec![0, 5, 0, 5]);
+        match interpret_mut(&mut machine, &program, 3) {
+            Err(ErrorCode::OutOfFuel { steps, machine: m }) => {
+                assert_eq!(steps, 3);
+                assert_eq!(m, machine);
+            }
+            r => panic!("expected to run out of fuel, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_index_newtypes() {
+        let state = State::from(3);
+        assert_eq!(usize::from(state), 3);
+        assert_eq!(state, 3);
+        assert_eq!(state.to_string(), "3");
+        let tape: TapeId = 2.into();
+        assert_eq!(tape, TapeId(2));
+
+        let rule = Rule::new(state, 4, vec![1, -1]);
+        assert_eq!(rule.cur_state(), State(3));
+        assert_eq!(rule.to_string(), "3 [1, -1] 4");
+        let machine = Machine::new(State(3), vec![0, 5]);
+        assert_eq!(machine.tape_pos(TapeId(1)), 5);
+        assert_eq!(machine.tape_pos(1), 5);
+    }
+
+    #[test]
+    fn test_tape_usage() {
+        let usage = crate::arith::mult_program().tape_usage();
+        let expected = vec![
+            // output
+            TapeUsage {
+                increments: true,
+                decrements: false,
+                touched_by: vec![0],
+            },
+            // x, restored from the scratch tape
+            TapeUsage {
+                increments: true,
+                decrements: true,
+                touched_by: vec![0, 2],
+            },
+            // scratch
+            TapeUsage {
+                increments: true,
+                decrements: true,
+                touched_by: vec![0, 2],
+            },
+            // y - 1, only consumed
+            TapeUsage {
+                increments: false,
+                decrements: true,
+                touched_by: vec![3],
+            },
+        ];
+        assert_eq!(usage, expected);
+
+        let usage = crate::arith::adder_program().tape_usage();
+        assert!(usage[1].decrements && !usage[1].increments);
+    }
+
+    #[test]
+    fn test_program_validate() {
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![-1])]);
+        assert_eq!(program.validate(), Ok(vec![]));
+
+        let err = Program::new(0, vec![]).validate().unwrap_err();
+        assert!(err.contains("0 tapes"), "{}", err);
+        let err = Program::new(2, vec![Rule::new(0, 0, vec![1])])
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("rule 0 has 1 adjustments"), "{}", err);
+
+        let warnings = Program::new(2, vec![]).validate().unwrap();
+        assert_eq!(warnings, vec!["program has no rules and halts immediately"]);
+        let warnings = Program::new(1, vec![Rule::new(3, 3, vec![0])])
+            .validate()
+            .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("rule 0: "), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn test_rule_validate() {
+        assert!(Rule::new(0, 0, vec![1, -1]).validate().is_ok());
+        assert!(Rule::new(0, 1, vec![0, 0]).validate().is_ok());
+        assert_eq!(
+            Rule::new(2, 2, vec![0, 0]).validate().unwrap_err(),
+            "rule `2 [0, 0] 2` does not change the machine and loops forever once it fires"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+        let b = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![-1, 1]), Rule::new(0, 1, vec![0, 0])],
+        );
+        let offset = a.states().into_iter().max().unwrap().0 + 1;
+        let mut merged = Program::merge(&a, &b, offset).unwrap();
+        assert_eq!(merged.num_rules(), 3);
+        let expected: BTreeSet<State> = (0..3).map(State).collect();
+        assert_eq!(merged.states(), expected);
+        assert_eq!(merged.iter().nth(1), Some(&Rule::new(1, 1, vec![-1, 1])));
+
+        // the phases are separate until they are glued together
+        let (_, end, _) = interpret(Machine::new(0, vec![0, 3]), &merged, 100).unwrap();
+        assert_eq!((end.machine_state(), end.tapes()), (State(0), &[3, 0][..]));
+        merged.push_rule(Rule::new(0, 1, vec![0, 0])).unwrap();
+        let (_, end, _) = interpret(Machine::new(0, vec![0, 3]), &merged, 100).unwrap();
+        assert_eq!((end.machine_state(), end.tapes()), (State(2), &[0, 3][..]));
+
+        let c = Program::new(3, vec![Rule::new(0, 0, vec![1, -1, 0])]);
+        assert!(Program::merge(&a, &c, 1).is_err());
+        let bounded = a.clone().with_bounds(vec![Some(5), None]).unwrap();
+        let merged = Program::merge(&bounded, &b, 1).unwrap();
+        assert_eq!(merged.bound(0), Some(5));
+        let other = b.with_bounds(vec![Some(4), None]).unwrap();
+        assert!(Program::merge(&bounded, &other, 1).is_err());
+    }
+
+    #[test]
+    fn test_equality_and_hashing() {
+        let build = || {
+            Program::new(
+                2,
+                vec![Rule::new(0, 0, vec![1, -1]), Rule::new(0, 1, vec![0, 0])],
+            )
+        };
+        assert_eq!(build(), build());
+        assert_ne!(build(), Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]));
+        assert_ne!(build(), build().with_bounds(vec![Some(3), None]).unwrap());
+
+        let machines: std::collections::HashSet<Machine> = vec![
+            Machine::new(0, vec![1, 2]),
+            Machine::new(0, vec![1, 2]),
+            Machine::new(1, vec![1, 2]),
+            Machine::new(0, vec![2, 1]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(machines.len(), 3);
+
+        let rules: std::collections::HashSet<Rule> = build().iter().cloned().collect();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(TapeState::from(vec![1, 2]), TapeState::from(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![-1, 2])]);
+        let machine = Machine::new(0, vec![10, i32::MAX - 5]);
+        match interpret(machine, &program, 100) {
+            Err(ErrorCode::Overflow { tape }) => assert_eq!(tape, 1),
+            r => panic!("expected an overflow, got {:?}", r),
+        }
+
+        let mut machine = Machine::new(0, vec![10, i32::MAX - 1]);
+        assert_eq!(machine.step(&program), StepResult::Overflow(0, TapeId(1)));
+        assert_eq!(machine.tapes(), &[10, i32::MAX - 1]);
+        assert!(!machine.apply_rule(&Rule::new(0, 0, vec![-1, 2])));
+
+        // a bounded tape never overflows, the rule just doesn't fire
+        let bounded = program.with_bounds(vec![None, Some(i32::MAX)]).unwrap();
+        let machine = Machine::new(0, vec![10, i32::MAX - 5]);
+        let (steps, end, _) = interpret(machine, &bounded, 100).unwrap();
+        assert_eq!(steps, 2);
+        assert_eq!(end.tapes(), &[8, i32::MAX - 1]);
+    }
+
+    #[test]
+    fn test_fired_rule_sequence() {
+        // the multiplier fires rule 0 x times, then rule 1, then rule 2 x times, then rule 3, for
+        // each of the remaining y - 1 units, and finally halts in state 1
+        let program = crate::arith::mult_program();
+        let (x, y) = (2, 3);
+        let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+        let fired = fired_rule_sequence(machine.clone(), &program, 100).unwrap();
+        let round = [0, 0, 1, 2, 2, 3];
+        let expected: Vec<usize> = round
+            .iter()
+            .cycle()
+            .take(round.len() * (y - 1) as usize)
+            .chain(&[0, 0, 1, 2, 2])
+            .copied()
+            .collect();
+        assert_eq!(fired, expected);
+
+        let r = fired_rule_sequence(machine, &program, 4);
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 4, .. })));
+    }
+
+    #[test]
+    fn test_interpret_profiled() {
+        // rules 0 and 2 fire x times for each of the y rounds of the multiplier
+        let program = crate::arith::mult_program();
+        let (x, y) = (5, 4);
+        let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+        let (counts, end, _) = interpret_profiled(machine.clone(), &program, 1000).unwrap();
+        assert_eq!(counts, vec![20, 4, 20, 3]);
+        let (steps, expected, _) = interpret(machine.clone(), &program, 1000).unwrap();
+        assert_eq!(counts.iter().sum::<u64>(), steps);
+        assert_eq!(end, expected);
+
+        let r = interpret_profiled(machine, &program, 10);
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 10, .. })));
+    }
+
+    #[test]
+    fn test_interpret_with_stats() {
+        // the scratch tape peaks at x and is back at zero when the multiplier halts
+        let program = crate::arith::mult_program();
+        let (x, y) = (5, 4);
+        let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+        let (stats, end, _) = interpret_with_stats(machine.clone(), &program, 1000).unwrap();
+        assert_eq!(end.tapes(), &[20, 5, 0, 0]);
+        assert_eq!(stats.max_tape, vec![20, 5, 5, 3]);
+        assert_eq!(stats.firings_per_rule, vec![20, 4, 20, 3]);
+        let (steps, _, _) = interpret(machine.clone(), &program, 1000).unwrap();
+        assert_eq!(stats.total_steps, steps);
+
+        let r = interpret_with_stats(machine, &program, 10);
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 10, .. })));
+    }
+
+    #[test]
+    fn test_interpret_auto() {
+        // the adder moves tape 1 into tape 0, one step per unit
+        let adder = crate::arith::adder_program();
+        let (steps, end, reason) = interpret_auto(Machine::new(0, vec![2, 3]), &adder).unwrap();
+        assert_eq!(steps, 3);
+        assert_eq!(end.tapes(), &[5, 0]);
+        assert_eq!(reason, HaltReason::Halted);
+        let (steps, _, reason) = interpret_auto(Machine::new(0, vec![4, 0]), &adder).unwrap();
+        assert_eq!(steps, 0);
+        assert_eq!(reason, HaltReason::NoRuleFired);
+
+        // a rule that only grows a tape, and a rule that leaves the monovariant unchanged
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![1])]);
+        let r = interpret_auto(Machine::new(0, vec![0]), &program);
+        assert!(matches!(r, Err(ErrorCode::TerminationUnknown)));
+        let program = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![-1, 0]), Rule::new(0, 1, vec![0, 0])],
+        );
+        let r = interpret_auto(Machine::new(0, vec![3, 0]), &program);
+        assert!(matches!(r, Err(ErrorCode::TerminationUnknown)));
+
+        // tape 0 alone leaves the second rule unchanged, weighting tape 1 too ranks both
+        let program = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![-1, 1]), Rule::new(0, 0, vec![0, -1])],
+        );
+        assert_eq!(find_ranking_weights(&program), Some(vec![2, 1]));
+        let (steps, end, _) = interpret_auto(Machine::new(0, vec![2, 1]), &program).unwrap();
+        assert_eq!(steps, 5);
+        assert_eq!(end.tapes(), &[0, 0]);
+        assert_eq!(
+            find_ranking_weights(&Program::new(2, vec![])),
+            Some(vec![0, 0])
+        );
+
+        // the weighted sum of these tapes does not fit in an `i64`
+        let k = i32::MAX - 1;
+        let program = Program::new(
+            4,
+            vec![
+                Rule::new(0, 0, vec![k, -1, 0, 0]),
+                Rule::new(0, 0, vec![k, 0, -1, 0]),
+                Rule::new(0, 0, vec![k, 0, 0, -1]),
+                Rule::new(0, 0, vec![-1, 0, 0, 0]),
+            ],
+        );
+        assert_eq!(
+            find_ranking_weights(&program),
+            Some(vec![1, i32::MAX, i32::MAX, i32::MAX])
+        );
+        let machine = Machine::new(0, vec![i32::MAX; 4]);
+        let r = interpret_auto(machine, &program);
+        assert!(matches!(r, Err(ErrorCode::Overflow { tape: TapeId(0) })));
+    }
+
+    #[test]
+    fn test_interpret_with_limits() {
+        // rule 0 is a self-loop that drains tape 1 into tape 0
+        let program = crate::arith::adder_program();
+        let machine = Machine::new(0, vec![0, 10]);
+        let r = interpret_with_limits(machine.clone(), &program, 100, &[Some(3)]);
+        match r {
+            Err(ErrorCode::RuleCapExceeded {
+                rule: 0,
+                steps: 4,
+                machine,
+            }) => assert_eq!(machine.tapes(), &[4, 6]),
+            r => panic!("expected the cap to be exceeded, got {:?}", r),
+        }
+
+        // caps that are not reached, missing, or None leave the run unchanged
+        let (steps, end, reason) = interpret(machine.clone(), &program, 100).unwrap();
+        for caps in [vec![Some(10)], vec![None], vec![]] {
+            let r = interpret_with_limits(machine.clone(), &program, 100, &caps);
+            assert_eq!(r.unwrap(), (steps, end.clone(), reason));
+        }
+        let r = interpret_with_limits(machine, &program, 5, &[None]);
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 5, .. })));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_interpret_timed() {
+        use std::time::Duration;
+
+        // a rule with no guard fires forever
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![0])]);
+        let r = interpret_timed(
+            Machine::new(0, vec![0]),
+            &program,
+            Duration::from_millis(10),
+        );
+        match r {
+            Err(ErrorCode::Timeout { steps }) => {
+                assert!(steps > 0);
+                assert_eq!(steps % TIMEOUT_STRIDE, 0);
+            }
+            r => panic!("expected a timeout, got {:?}", r),
+        }
+
+        // halting programs finish like with fuel
+        let program = crate::arith::adder_program();
+        let machine = Machine::new(0, vec![2, 3]);
+        let (steps, end, reason) = interpret(machine.clone(), &program, 100).unwrap();
+        let r = interpret_timed(machine, &program, Duration::from_secs(10));
+        assert_eq!(r.unwrap(), (steps, end, reason));
+    }
+
+    #[test]
+    fn test_run_iter() {
+        let program = crate::arith::mult_program();
+        let machine = Machine::new(0, vec![0, 3, 0, 1]);
+        let (trace, end, _) = interpret_traced(machine.clone(), &program, 100).unwrap();
+        let configs: Vec<Configuration> = run_iter(machine.clone(), &program).collect();
+        assert_eq!(configs, trace);
+        assert_eq!(configs.iter().filter(|c| c.fired_rule.is_none()).count(), 1);
+
+        let mut iter = run_iter(machine.clone(), &program);
+        assert_eq!(iter.by_ref().count(), trace.len());
+        assert!(iter.next().is_none());
+        assert_eq!(iter.machine().tapes(), end.tapes());
+
+        // stop once tape 0 reaches a threshold
+        let reached = run_iter(machine, &program).find(|c| c.tapes[0] == 2);
+        assert_eq!(reached.map(|c| c.state), Some(State(0)));
+
+        // an endless program can still be explored with `take`
+        let looping = Program::new(1, vec![Rule::new(0, 0, vec![1])]);
+        let last = run_iter(Machine::new(0, vec![0]), &looping)
+            .take(100)
+            .last();
+        assert_eq!(last.unwrap().tapes, vec![99]);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let program = crate::arith::mult_program();
+        let machine = Machine::new(0, vec![0, 6, 0, 4]);
+        let (_, expected, _) = interpret(machine.clone(), &program, 1000).unwrap();
+
+        // checkpoint part way through and resume from the snapshot
+        let snapshot = match interpret(machine, &program, 10) {
+            Err(ErrorCode::OutOfFuel { machine, .. }) => machine.snapshot(),
+            r => panic!("expected to run out of fuel, got {:?}", r),
+        };
+        assert_eq!(snapshot.tapes.len(), 4);
+        let resumed = Machine::restore(snapshot.clone());
+        assert_eq!(resumed.snapshot(), snapshot);
+        let (_, end, _) = interpret(resumed, &program, 1000).unwrap();
+        assert_eq!(end.tapes(), expected.tapes());
+    }
+
+    #[test]
+    fn test_machine_try_new() {
+        let machine = Machine::try_new(1, vec![0, 3]).unwrap();
+        assert_eq!(machine.machine_state(), 1);
+        assert_eq!(machine.tapes(), &[0, 3]);
+        let err = Machine::try_new(0, vec![2, -1]).unwrap_err();
+        assert_eq!(err, "tape 1 has negative initial position -1");
+    }
+
+    #[test]
+    fn test_estimate_fuel() {
+        let adder = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+        let machine = Machine::new(0, vec![2, 3]);
+        assert_eq!(estimate_fuel(&adder, &machine), 72);
+        assert!(interpret(machine, &adder, 72).is_ok());
+
+        let mult = crate::arith::mult_program();
+        for (x, y) in [(1, 1), (7, 3), (100, 100)] {
+            let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+            let fuel = estimate_fuel(&mult, &machine);
+            assert!(interpret(machine, &mult, fuel).is_ok());
+        }
+
+        let machine = Machine::new(0, vec![i32::MAX; 8]);
+        assert_eq!(estimate_fuel(&mult, &machine), u64::MAX);
+    }
+
+    #[test]
+    fn test_applicable_rules() {
+        let rule0 = Rule::new(0, 0, vec![1, -1]);
+        let rule1 = Rule::new(0, 1, vec![-1, 0]);
+        let rule2 = Rule::new(1, 0, vec![0, 0]);
+        let rule3 = Rule::new(0, 0, vec![0, 0, 0]);
+        let rule4 = Rule::new(0, 0, vec![1, 0]);
+        let program = Program::new(2, vec![rule0, rule1, rule2, rule3, rule4])
+            .with_bounds(vec![Some(1), None])
+            .unwrap();
+
+        let machine = Machine::new(0, vec![1, 2]);
+        let indices: Vec<usize> = machine
+            .applicable_rules(&program)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(indices, vec![1]);
+
+        let machine = Machine::new(0, vec![0, 2]);
+        let applicable = machine.applicable_rules(&program);
+        assert_eq!(applicable.len(), 2);
+        assert_eq!(applicable[0], (0, &Rule::new(0, 0, vec![1, -1])));
+        assert_eq!(applicable[1].0, 4);
+
+        let machine = Machine::new(2, vec![0, 2]);
+        assert!(machine.applicable_rules(&program).is_empty());
+    }
+
+    #[test]
+    fn test_interpret_with_strategy() {
+        let rule0 = Rule::new(0, 0, vec![-1, 0]);
+        let rule1 = Rule::new(0, 0, vec![-1, 2]);
+        let rule2 = Rule::new(0, 0, vec![-1, 1]);
+        let program = Program::new(2, vec![rule0, rule1, rule2]);
+        let run = |strategy| {
+            let machine = Machine::new(0, vec![3, 0]);
+            let (steps, machine, _) =
+                interpret_with_strategy(machine, &program, 10, strategy).expect("run failed");
+            assert_eq!(steps, 3);
+            machine.tape_pos(1)
+        };
+        assert_eq!(run(Strategy::FirstMatch), 0);
+        assert_eq!(run(Strategy::LastMatch), 3);
+        assert_eq!(run(Strategy::MaxAdjustment), 6);
+
+        // first match is the default interpreter policy
+        let mult = Program::new(
+            4,
+            vec![
+                Rule::new(0, 0, vec![1, -1, 1, 0]),
+                Rule::new(0, 1, vec![0, 0, 0, 0]),
+                Rule::new(1, 1, vec![0, 1, -1, 0]),
+                Rule::new(1, 0, vec![0, 0, 0, -1]),
+            ],
+        );
+        let machine = Machine::new(0, vec![0, 3, 0, 4]);
+        let (steps, end, reason) = interpret(machine.clone(), &mult, 100).unwrap();
+        let (s_steps, s_end, s_reason) =
+            interpret_with_strategy(machine, &mult, 100, Strategy::FirstMatch).unwrap();
+        assert_eq!(steps, s_steps);
+        assert_eq!(reason, s_reason);
+        assert_eq!(end.tapes(), s_end.tapes());
+    }
+
+    #[test]
+    fn test_interpret_until() {
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+
+        let machine = Machine::new(0, vec![0, 5]);
+        match interpret_until(machine, &program, None) {
+            Ok(RunOutcome::Halted {
+                steps,
+                machine,
+                reason,
+            }) => {
+                assert_eq!(steps, 5);
+                assert_eq!(reason, HaltReason::Halted);
+                assert_eq!(machine.tapes(), &[5, 0]);
+            }
+            r => panic!("expected a halt, got {:?}", r),
+        }
+
+        // halting exactly at the limit is still a halt
+        let machine = Machine::new(0, vec![0, 5]);
+        let r = interpret_until(machine, &program, Some(5));
+        assert!(matches!(r, Ok(RunOutcome::Halted { steps: 5, .. })));
+
+        let machine = Machine::new(0, vec![0, 5]);
+        match interpret_until(machine, &program, Some(3)) {
+            Ok(RunOutcome::StepLimit { steps, machine }) => {
+                assert_eq!(steps, 3);
+                assert_eq!(machine.tapes(), &[3, 2]);
+            }
+            r => panic!("expected the step limit, got {:?}", r),
+        }
+
+        let machine = Machine::new(0, vec![0, 5]);
+        let r = interpret_until(machine, &program, Some(0));
+        assert!(matches!(r, Ok(RunOutcome::StepLimit { steps: 0, .. })));
+    }
+
+    #[test]
+    fn test_interpret_until_state() {
+        let program = crate::arith::mult_program();
+
+        // the first pass through x ends with the transition to state 1
+        let machine = Machine::new(0, vec![0, 3, 0, 1]);
+        let (steps, machine, halted) = interpret_until_state(machine, &program, 1, 100).unwrap();
+        assert_eq!(halted, None);
+        assert_eq!(steps, 4);
+        assert_eq!(machine.machine_state(), 1);
+        assert_eq!(machine.tapes(), &[3, 0, 3, 1]);
+
+        // the target is only checked after a rule fires
+        let machine = Machine::new(0, vec![0, 3, 0, 1]);
+        let (steps, _, halted) = interpret_until_state(machine, &program, 0, 100).unwrap();
+        assert_eq!(halted, None);
+        assert_eq!(steps, 1);
+
+        // halting without reaching the target
+        let machine = Machine::new(0, vec![0, 3, 0, 1]);
+        let (_, machine, halted) = interpret_until_state(machine, &program, 5, 100).unwrap();
+        assert_eq!(halted, Some(HaltReason::Halted));
+        assert_eq!(machine.tape_pos(0), 6);
+
+        let machine = Machine::new(0, vec![0, 3, 0, 1]);
+        let r = interpret_until_state(machine, &program, 1, 2);
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 2, .. })));
+    }
+
+    #[test]
+    fn test_interpret_traced() {
+        let rule0 = Rule::new(0, 0, vec![1, -1]);
+        let rule1 = Rule::new(0, 1, vec![0, 0]);
+        let program = Program::new(2, vec![rule0, rule1]);
+        let machine = Machine::new(0, vec![0, 1]);
+        let (trace, end_machine, _) = interpret_traced(machine, &program, 100).unwrap();
+        let config = |state: usize, tapes: Vec<i32>, fired_rule| Configuration {
+            state: state.into(),
+            tapes,
+            fired_rule,
+        };
+        assert_eq!(
+            trace,
+            vec![
+                config(0, vec![0, 1], Some(0)),
+                config(0, vec![1, 0], Some(1)),
+                config(1, vec![1, 0], None),
+            ]
+        );
+        assert_eq!(end_machine.machine_state(), 1);
+    }
+
+    #[test]
+    fn test_interpret_with_trace() {
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+        let machine = Machine::new(0, vec![0, 3]);
+        let mut seen = Vec::new();
+        let (steps, _, _) = interpret_with_trace(machine.clone(), &program, 100, |m, step| {
+            seen.push((step, m.tape_pos(0)));
+        })
+        .unwrap();
+        assert_eq!(steps, 3);
+        assert_eq!(seen, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+
+        // the last machine is seen when the fuel runs out too
+        let mut seen = Vec::new();
+        let r = interpret_with_trace(machine, &program, 2, |m, step| {
+            seen.push((step, m.tape_pos(0)));
+        });
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 2, .. })));
+        assert_eq!(seen, vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_interpret_with_probes() {
+        // the probe is tried once per pass through the loop, before the rule that fires
+        let program = Program::new(
+            2,
+            vec![
+                Rule::probe(0, vec![0, -1], "pass"),
+                Rule::new(0, 0, vec![1, -1]),
+            ],
+        );
+        let machine = Machine::new(0, vec![0, 3]);
+        let mut seen = Vec::new();
+        let (steps, end, _) =
+            interpret_with_probes(machine.clone(), &program, 100, |msg, m, step| {
+                seen.push((msg.to_string(), step, m.tape_pos(1)));
+            })
+            .unwrap();
+        assert_eq!(steps, 3);
+        assert_eq!(end.tapes(), &[3, 0]);
+        let pass = |step, pos| ("pass".to_string(), step, pos);
+        assert_eq!(seen, vec![pass(0, 3), pass(1, 2), pass(2, 1)]);
+        // probes do not change how the program runs
+        assert_eq!(interpret(machine.clone(), &program, 100).unwrap().1, end);
+        assert_eq!(
+            interpret(machine, &program.without_probes(), 100)
+                .unwrap()
+                .1,
+            end
+        );
+
+        // a probe that always holds still cannot keep the machine running
+        let program = Program::new(1, vec![Rule::probe(0, vec![0], "here")]);
+        let mut count = 0;
+        let (steps, _, _) =
+            interpret_with_probes(Machine::new(0, vec![0]), &program, 100, |_, _, _| {
+                count += 1
+            })
+            .unwrap();
+        assert_eq!((steps, count), (0, 1));
+        assert!(program.iter().next().unwrap().validate().is_ok());
+
+        // the start state has to fire a rule, and states that only probes used are dropped
+        let program = Program::new(
+            1,
+            vec![Rule::probe(1, vec![0], "here"), Rule::new(0, 1, vec![1])],
+        );
+        assert!(program.clone().with_start_state(1).is_err());
+        let program = program
+            .with_start_state(0)
+            .unwrap()
+            .with_accept_states(vec![1])
+            .unwrap();
+        let stripped = program.without_probes();
+        assert_eq!(stripped.start_state(), Some(State(0)));
+        assert!(stripped.is_accepting(1));
+        let program = Program::new(
+            1,
+            vec![Rule::probe(1, vec![0], "here"), Rule::new(0, 0, vec![1])],
+        )
+        .with_accept_states(vec![1])
+        .unwrap();
+        let stripped = program.without_probes();
+        assert!(stripped.accept_states().is_empty());
+        assert_eq!(stripped.renumber_states().num_rules(), 1);
+    }
+
+    #[test]
+    fn test_interpret_with_sampled_trace() {
+        // 3 * 4 takes 4 * (2 * 3 + 2) - 1 = 31 steps, see `arith::mult_steps`
+        let machine = Machine::new(0, vec![0, 3, 0, 3]);
+        let program = crate::arith::mult_program();
+        let mut seen = Vec::new();
+        let (steps, end, _) =
+            interpret_with_sampled_trace(machine.clone(), &program, 1000, 10, |m, step| {
+                seen.push((step, m.clone()))
+            })
+            .unwrap();
+        assert_eq!(steps, 31);
+        let steps_seen: Vec<u64> = seen.iter().map(|(step, _)| *step).collect();
+        assert_eq!(steps_seen, vec![0, 10, 20, 30, 31]);
+        assert_eq!(seen[0].1, machine);
+        assert_eq!(seen[4].1, end);
+
+        // the final machine is not repeated when it was just sampled, and 0 samples every step
+        let mut count = 0;
+        interpret_with_sampled_trace(machine.clone(), &program, 1000, 31, |_, _| count += 1)
+            .unwrap();
+        assert_eq!(count, 2);
+        let mut count = 0;
+        interpret_with_sampled_trace(machine.clone(), &program, 1000, 0, |_, _| count += 1)
+            .unwrap();
+        assert_eq!(count, 32);
+
+        // the last machine of a failed run is seen as well
+        let mut seen = Vec::new();
+        let r = interpret_with_sampled_trace(machine, &program, 25, 10, |_, step| seen.push(step));
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 25, .. })));
+        assert_eq!(seen, vec![0, 10, 20, 25]);
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![1])]);
+        let mut seen = Vec::new();
+        let r = interpret_with_sampled_trace(
+            Machine::new(0, vec![i32::MAX - 3]),
+            &program,
+            100,
+            2,
+            |m, step| seen.push((step, m.tape_pos(0))),
+        );
+        assert!(matches!(r, Err(ErrorCode::Overflow { .. })));
+        assert_eq!(
+            seen,
+            vec![(0, i32::MAX - 3), (2, i32::MAX - 1), (3, i32::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_configuration_hash() {
+        let machine = Machine::new(1, vec![3, 0, 7]);
+        assert_eq!(
+            machine.configuration_hash(),
+            Machine::new(1, vec![3, 0, 7]).configuration_hash()
+        );
+        assert_ne!(
+            machine.configuration_hash(),
+            Machine::new(1, vec![3, 0, 8]).configuration_hash()
+        );
+        assert_ne!(
+            machine.configuration_hash(),
+            Machine::new(0, vec![3, 0, 7]).configuration_hash()
+        );
+        // the hash is stable across platforms and runs
+        assert_eq!(
+            Machine::new(0, vec![]).configuration_hash(),
+            0xa8c7_f832_281a_39c5
+        );
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        // tape 0 is moved back and forth between two states forever
+        let rule0 = Rule::new(0, 1, vec![1, 0]);
+        let rule1 = Rule::new(1, 0, vec![-1, 0]);
+        let program = Program::new(2, vec![rule0, rule1]);
+        let machine = Machine::new(0, vec![0, 3]);
+        match interpret_with_cycle_detection(machine, &program, 1000, 10) {
+            Err(ErrorCode::Cycle { period }) => assert_eq!(period, 2),
+            r => panic!("expected a cycle, got {:?}", r),
+        }
+
+        // a growing tape never repeats, so the interpreter runs out of fuel
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![1])]);
+        let machine = Machine::new(0, vec![0]);
+        let r = interpret_with_cycle_detection(machine, &program, 100, 10);
+        assert!(matches!(r, Err(ErrorCode::OutOfFuel { steps: 100, .. })));
+
+        // halting programs are unaffected
+        let program = Program::new(2, vec![Rule::new(0, 0, vec![1, -1])]);
+        let machine = Machine::new(0, vec![2, 3]);
+        let (_, end_machine, _) =
+            interpret_with_cycle_detection(machine, &program, 100, 10).unwrap();
+        assert_eq!(*end_machine.tape_state, vec![5, 0]);
     }
 }
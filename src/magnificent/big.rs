@@ -0,0 +1,227 @@
+//! # Arbitrary Precision Tapes
+//!
+//! This module provides a machine whose tape head positions are [`BigInt`]s, so programs like the
+//! multipliers in [`crate::arith`] can run on inputs whose results do not fit in an `i32`. It
+//! runs the same [`Program`]s as [`Machine`](super::Machine), with the same step and interpreter
+//! loop over the [`TapeValue`] trait.
+//!
+//! Since arithmetic on such inputs takes billions of steps, a self-looping rule that is the first
+//! rule of its state is fired as often as it applies in one go. The step counts are the same as
+//! when firing it one step at a time.
+//!
+//! This module is only available with the `bigint` feature.
+
+use core::convert::TryFrom;
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use num_bigint::BigInt;
+
+use super::{
+    apply_tapes, run_until_halted, step_machine, ErrorCode, HaltReason, Program, Rule, RunMachine,
+    State, StepResult, Strategy, TapeId, TapeValue,
+};
+
+impl TapeValue for BigInt {
+    fn from_i32(n: i32) -> Self {
+        BigInt::from(n)
+    }
+
+    fn checked_adjust(&self, amt: i32) -> Option<Self> {
+        Some(self + amt)
+    }
+}
+
+/// A Magnificent Minsky Machine with arbitrary precision tapes
+#[derive(Clone, Debug)]
+pub struct BigMachine {
+    machine_state: State,
+    tapes: Vec<BigInt>,
+}
+
+impl BigMachine {
+    /// Create a new machine given an initial machine state and tape head positions.
+    pub fn new(machine_state: impl Into<State>, tapes: Vec<BigInt>) -> Self {
+        BigMachine {
+            machine_state: machine_state.into(),
+            tapes,
+        }
+    }
+
+    /// Return the current machine state.
+    pub fn machine_state(&self) -> State {
+        self.machine_state
+    }
+
+    /// Return the current tape head positions for all tapes.
+    pub fn tapes(&self) -> &[BigInt] {
+        &self.tapes
+    }
+
+    /// Return the current tape head position for the given tape.
+    ///
+    /// Panics if `id` is not a valid tape.
+    pub fn tape_pos(&self, id: impl Into<TapeId>) -> &BigInt {
+        &self.tapes[id.into().0]
+    }
+
+    /// Apply at most one rule from the program to the machine, see
+    /// [`Machine::step`](super::Machine::step).
+    pub fn step(&mut self, program: &Program) -> StepResult {
+        step_machine(
+            self,
+            program,
+            0..program.num_rules(),
+            Strategy::FirstMatch,
+            |_, _| {},
+        )
+    }
+}
+
+impl RunMachine for BigMachine {
+    type Value = BigInt;
+
+    fn state(&self) -> State {
+        self.machine_state
+    }
+
+    fn positions(&self) -> &[BigInt] {
+        &self.tapes
+    }
+
+    fn fire(&mut self, rule: &Rule) {
+        apply_tapes(&mut self.tapes, rule);
+        self.machine_state = rule.next_state;
+    }
+
+    fn repeat(&mut self, rule: &Rule, bounds: &[Option<i32>], limit: u64) -> u64 {
+        // each firing must keep every decremented tape at or above 0 and every bounded tape at
+        // or below its bound
+        let mut times = BigInt::from(limit);
+        for (t, (tp, amt)) in self.tapes.iter().zip(rule.iter()).enumerate() {
+            let room = match bounds.get(t).copied().flatten() {
+                _ if *amt < 0 => tp / -BigInt::from(*amt),
+                Some(b) if *amt > 0 => (BigInt::from(b) - tp) / amt,
+                _ => continue,
+            };
+            times = times.min(room);
+        }
+        for (tp, amt) in self.tapes.iter_mut().zip(rule.iter()) {
+            *tp += &times * amt;
+        }
+        u64::try_from(times).expect("repeats are at most the limit")
+    }
+}
+
+/// Interpret the given program starting with the initial machine, like
+/// [`interpret`](super::interpret).
+///
+/// Tapes never overflow, so the only errors are a rule with the wrong number of tapes and
+/// running out of fuel.
+pub fn interpret_big(
+    initial_machine: BigMachine,
+    program: &Program,
+    fuel: u64,
+) -> Result<(u64, BigMachine, HaltReason), ErrorCode<BigMachine>> {
+    let mut machine = initial_machine;
+    let (steps, reason) = run_until_halted(
+        &mut machine,
+        program,
+        fuel,
+        Strategy::FirstMatch,
+        1,
+        |_, _| {},
+    )?;
+    Ok((steps, machine, reason))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{interpret_big, BigMachine};
+    use crate::arith;
+    use crate::magnificent::{interpret, ErrorCode, HaltReason, Machine, Program, Rule};
+    use num_bigint::BigInt;
+
+    fn big(n: i64) -> BigInt {
+        BigInt::from(n)
+    }
+
+    #[test]
+    fn big_adder_past_i32() {
+        let machine = BigMachine::new(0, vec![big(i32::MAX as i64), big(10)]);
+        let (steps, end, reason) = interpret_big(machine, &arith::adder_program(), 100).unwrap();
+        assert_eq!(steps, 10);
+        assert_eq!(reason, HaltReason::Halted);
+        assert_eq!(*end.tape_pos(0), big(i32::MAX as i64 + 10));
+    }
+
+    #[test]
+    fn big_mult() {
+        // 100000 * 100000 does not fit in an i32, the multiplier takes y(2x + 2) - 1 steps
+        let (x, y) = (100_000, 100_000);
+        let machine = BigMachine::new(0, vec![big(0), big(x), big(0), big(y - 1)]);
+        let (steps, end, _) = interpret_big(machine, &arith::mult_program(), u64::MAX).unwrap();
+        assert_eq!(*end.tape_pos(0), big(x) * big(y));
+        assert_eq!(steps, (y * (2 * x + 2) - 1) as u64);
+    }
+
+    #[test]
+    fn big_matches_interpret() {
+        // firing self-loops in one go takes the same steps as the i32 interpreter
+        let bounded = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![3, -1]), Rule::new(0, 1, vec![-1, 0])],
+        )
+        .with_bounds(vec![Some(20), None])
+        .unwrap();
+        let probed = Program::new(
+            2,
+            vec![
+                Rule::probe(0, vec![0, -1], "pass"),
+                Rule::new(0, 0, vec![1, -1]),
+            ],
+        );
+        let runs = [
+            (arith::mult_program(), vec![0, 7, 0, 4], 1000),
+            (arith::mult_program(), vec![0, 7, 0, 4], 20),
+            (arith::power_program(), vec![1, 3, 4, 0, 0], 10_000),
+            (bounded, vec![0, 10], 100),
+            (probed, vec![0, 5], 100),
+        ];
+        for (program, tapes, fuel) in runs.iter() {
+            let machine = Machine::new(0, tapes.clone());
+            let big_machine = BigMachine::new(0, tapes.iter().map(|t| big(*t as i64)).collect());
+            let (expected, actual) = match (
+                interpret(machine, program, *fuel),
+                interpret_big(big_machine, program, *fuel),
+            ) {
+                (Ok((s, m, _)), Ok((big_s, big_m, _))) => ((s, m), (big_s, big_m)),
+                (
+                    Err(ErrorCode::OutOfFuel { steps, machine }),
+                    Err(ErrorCode::OutOfFuel {
+                        steps: big_steps,
+                        machine: big_machine,
+                    }),
+                ) => ((steps, machine), (big_steps, big_machine)),
+                (e, a) => panic!("runs disagree: {:?} and {:?}", e, a),
+            };
+            assert_eq!(expected.0, actual.0);
+            assert_eq!(expected.1.machine_state(), actual.1.machine_state());
+            let tapes: Vec<BigInt> = expected.1.tapes().iter().map(|t| big(*t as i64)).collect();
+            assert_eq!(tapes, actual.1.tapes());
+        }
+    }
+
+    #[test]
+    fn big_out_of_fuel() {
+        let machine = BigMachine::new(0, vec![big(0), big(10)]);
+        match interpret_big(machine, &arith::adder_program(), 3) {
+            Err(ErrorCode::OutOfFuel { steps, machine }) => {
+                assert_eq!(steps, 3);
+                assert_eq!(machine.tapes(), &[big(3), big(7)]);
+            }
+            r => panic!("expected to run out of fuel, got {:?}", r),
+        }
+    }
+}
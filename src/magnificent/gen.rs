@@ -0,0 +1,89 @@
+//! # Random Programs
+//!
+//! This module generates random programs from a seed, for stress testing the interpreter and
+//! the transpiler in ordinary tests and benchmarks. The same seed always produces the same
+//! program. Unlike the [`Arbitrary`] implementations of the `fuzz` module, no feature is needed
+//! and the shape of the program is chosen by the caller.
+//!
+//! Every rule of a generated program has one adjustment per tape, so generated programs are
+//! valid by construction. Adjustments are bounded by `MAX_ADJUSTMENT`.
+//!
+//! [`Arbitrary`]: https://docs.rs/arbitrary
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use super::{Program, Rule};
+
+/// Generated adjustments are in `-MAX_ADJUSTMENT..=MAX_ADJUSTMENT`
+pub const MAX_ADJUSTMENT: i32 = 3;
+
+/// A small seeded pseudo-random number generator (xorshift64*)
+struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a seed. The seed is scrambled first (splitmix64), so that
+    /// similar seeds, including 0, give unrelated sequences.
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Return a number in `0..n`, `n` must not be 0.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Generate a program with `num_tapes` tapes and `num_rules` rules whose states are in
+/// `0..num_states`.
+///
+/// Panics if `num_states` is 0 while `num_rules` is not.
+pub fn random_program(seed: u64, num_tapes: usize, num_rules: usize, num_states: usize) -> Program {
+    assert!(
+        num_states > 0 || num_rules == 0,
+        "rules need at least one state"
+    );
+    let mut rng = Rng::new(seed);
+    let span = (2 * MAX_ADJUSTMENT + 1) as usize;
+    let rules: Vec<Rule> = (0..num_rules)
+        .map(|_| {
+            let cur_state = rng.below(num_states);
+            let next_state = rng.below(num_states);
+            let adjustments = (0..num_tapes)
+                .map(|_| rng.below(span) as i32 - MAX_ADJUSTMENT)
+                .collect();
+            Rule::new(cur_state, next_state, adjustments)
+        })
+        .collect();
+    Program::new(num_tapes, rules)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_random_program() {
+        let program = random_program(42, 3, 10, 4);
+        assert_eq!(program, random_program(42, 3, 10, 4));
+        assert_ne!(program, random_program(43, 3, 10, 4));
+        assert_eq!(program.num_tapes(), 3);
+        assert_eq!(program.num_rules(), 10);
+        assert!(program.validate().is_ok());
+        for rule in program.iter() {
+            assert!(rule.cur_state.0 < 4 && rule.next_state.0 < 4);
+            assert!(rule.iter().all(|a| a.abs() <= MAX_ADJUSTMENT));
+        }
+        assert_eq!(random_program(7, 2, 0, 0).num_rules(), 0);
+    }
+}
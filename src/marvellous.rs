@@ -75,13 +75,16 @@
 //!
 //! [`magnificent`]: minsky::magnificent
 
-use std::collections::HashMap;
-use std::collections::HashSet;
+use alloc::collections::{BTreeMap, BTreeSet};
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec, vec::Vec};
 
-use crate::magnificent::{Program, Rule, State};
+use crate::magnificent::{
+    diff_machines, interpret, ErrorCode, Machine, Program, Rule, State, StepResult, TapeDiff,
+};
 
 /// The unique state of marvellous Minsky machines
-const MARV_STATE: State = 0;
+const MARV_STATE: State = State(0);
 
 /// The action tape adjustment for emulated states
 const ACTION_ADJ: i32 = 1;
@@ -89,31 +92,56 @@ const ACTION_ADJ: i32 = 1;
 /// The guard tape adjustment for emulated states
 const GUARD_ADJ: i32 = -1;
 
+/// The shape of the magnificent program that a marvellous program was transpiled from
+#[derive(Debug, Eq, PartialEq)]
+pub struct TranspileInfo {
+    /// number of tapes of the original program
+    pub orig_tapes: usize,
+    /// number of states of the original program
+    pub orig_states: usize,
+}
+
+/// Outcome of [`check_equivalent`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Equivalence {
+    /// both machines halted and agree on the original tapes
+    Equivalent,
+    /// both machines halted but disagree on some original tape, the first such difference is
+    /// given
+    Differs(TapeDiff),
+    /// exactly one of the machines ran out of fuel, `orig_halted` tells whether the original
+    /// machine is the one that halted
+    FuelDivergence { orig_halted: bool },
+}
+
 /// Compute a mapping from original states to new states.
 ///
 /// The original states are collected and sorted by value, then paired with a new state.
 /// If there are `m` original states, then the new state values will range from 0 ... m-1.
-fn compute_state_map(program: &Program) -> HashMap<State, State> {
-    let mut orig_states: HashSet<usize> = HashSet::new();
-    for rule in program.iter() {
-        orig_states.insert(rule.cur_state());
-        orig_states.insert(rule.next_state());
-    }
-
-    // sort and collect the original states into a map from original to new states. If
-    // `num_orig_states == m`, then the new states are {0, 1, ..., m-1}.
-    let mut orig_states: Vec<State> = orig_states.into_iter().collect();
-    orig_states.sort();
-    orig_states.into_iter().zip((0 as usize)..).collect()
+///
+/// Original state `s` is emulated by the tapes `n + 2*t` and `n + 2*t + 1` of the transpiled
+/// program, where `t` is the new state of `s` and `n` the number of original tapes. The initial
+/// marvellous machine for an original machine in state `s` has tape `n + 2*t` set to 1.
+pub fn compute_state_map(program: &Program) -> BTreeMap<State, State> {
+    program.state_renumbering()
 }
 
 /// Given an original rule, produce either one or two new rules for the Marvellous machine.
-fn translate_rule(rule: &Rule, state_map: &HashMap<State, State>, num_tapes: usize) -> Vec<Rule> {
+///
+/// `state_map` maps the original states to new states, see [`compute_state_map`], and
+/// `num_tapes` is the number of tapes of the transpiled program, i.e. the original number of
+/// tapes plus twice the number of original states.
+pub fn translate_rule(
+    rule: &Rule,
+    state_map: &BTreeMap<State, State>,
+    num_tapes: usize,
+) -> Vec<Rule> {
     let orig_tapes = rule.len();
     let mut ret_rules = Vec::new();
     let new_emulated_state = state_map
         .get(&rule.cur_state())
-        .expect("state map is incomplete");
+        .expect("state map is incomplete")
+        .0;
 
     // duplicate the original tape adjustments
     let mut new_rule = vec![0; num_tapes];
@@ -137,7 +165,8 @@ fn translate_rule(rule: &Rule, state_map: &HashMap<State, State>, num_tapes: usi
         // produce a single new rule for the original one
         let new_next_emulated_state = state_map
             .get(&rule.next_state())
-            .expect("state map is incomplete");
+            .expect("state map is incomplete")
+            .0;
         new_rule[orig_tapes + 2 * new_emulated_state] = GUARD_ADJ;
         new_rule[orig_tapes + 2 * new_next_emulated_state] = ACTION_ADJ;
         ret_rules.push(Rule::new(MARV_STATE, MARV_STATE, new_rule));
@@ -146,6 +175,13 @@ fn translate_rule(rule: &Rule, state_map: &HashMap<State, State>, num_tapes: usi
 }
 
 pub fn transpile(program: &Program) -> Program {
+    transpile_with_state_map(program).0
+}
+
+/// Transpile the program like [`transpile`], also returning the state map that was used, see
+/// [`compute_state_map`] and [`lift_machine`].
+pub fn transpile_with_state_map(program: &Program) -> (Program, BTreeMap<State, State>) {
+    let program = &program.without_probes();
     // collect and sort the original rules by state
     let state_map = compute_state_map(program);
     let num_orig_states = state_map.len();
@@ -156,13 +192,351 @@ pub fn transpile(program: &Program) -> Program {
     for rule in program.iter() {
         new_rules.extend(translate_rule(rule, &state_map, new_num_tapes));
     }
-    Program::new(new_num_tapes, new_rules)
+
+    // original tapes keep their bounds, the emulated state tapes are unbounded
+    let bounds = (0..new_num_tapes).map(|t| program.bound(t)).collect();
+    let marv_program = Program::new(new_num_tapes, new_rules)
+        .with_bounds(bounds)
+        .expect("bounds of the original program are valid");
+    (marv_program, state_map)
+}
+
+/// Construct the marvellous machine corresponding to an original machine.
+///
+/// The original tape positions are copied to the first `orig_tapes` tapes and the emulated state
+/// tape of the original machine state is set to 1, all other of the `2 * orig_states` emulated
+/// state tapes are 0. An original state that is not in `state_map` is not emulated, so the
+/// lifted machine halts immediately, just like the original.
+pub fn lift_machine(
+    orig: &Machine,
+    state_map: &BTreeMap<State, State>,
+    orig_tapes: usize,
+    orig_states: usize,
+) -> Machine {
+    let mut tapes = vec![0; orig_tapes + 2 * orig_states];
+    tapes[..orig_tapes].copy_from_slice(&orig.tapes()[..orig_tapes]);
+    if let Some(s) = state_map.get(&orig.machine_state()) {
+        tapes[orig_tapes + 2 * s.0] = 1;
+    }
+    Machine::new(MARV_STATE, tapes)
+}
+
+/// Check whether rule `earlier` is applicable in every configuration that `later` is applicable
+/// in, so that `later` can never fire if it comes after `earlier` in the program.
+///
+/// This is the case if both fire in the same state, `earlier` decrements each tape by at most as
+/// much as `later` does, and `earlier` moves each bounded tape forward by at most as much as
+/// `later` does. A probe never fires, so it dominates no rule.
+fn dominates(program: &Program, earlier: &Rule, later: &Rule) -> bool {
+    earlier.cur_state() == later.cur_state()
+        && !earlier.is_probe()
+        && earlier
+            .iter()
+            .zip(later.iter())
+            .enumerate()
+            .all(|(t, (a, b))| {
+                let guard_ok = *a >= 0 || *a >= *b;
+                let bound_ok = *a <= 0 || program.bound(t).is_none() || *a <= *b;
+                guard_ok && bound_ok
+            })
+}
+
+/// Remove rules that can never fire from a program, typically one produced by [`transpile`].
+///
+/// A rule is removed if an earlier rule that is kept fires in the same state and is applicable
+/// whenever the rule is, see `dominates`. Since the interpreter fires the first applicable rule,
+/// the removed rules never fire and the optimized program makes exactly the same moves as the
+/// original on every machine. This relies on the first-match semantics of [`interpret`]: with
+/// another `Strategy` a removed rule could have been chosen, so the optimized program is only
+/// equivalent when run with `Strategy::FirstMatch`.
+///
+/// The start state, accept states, bounds, and tape names of the program are kept. If a removed
+/// rule is the only one that mentions an accept state, the first such rule is kept as well, so
+/// that the state stays in the program and `Program::is_accepting` does not change; it still
+/// never fires.
+///
+/// In particular this coalesces the aux rules of a transpiled program: every self-looping
+/// original rule in state `s` is translated with the same aux rule resetting emulated state `s`,
+/// and only the first copy is kept. Rules following an unconditional rule in the same state are
+/// removed as well. Rules whose width does not match the program are left in place so that the
+/// interpreter still reports them.
+pub fn optimize(program: &Program) -> Program {
+    let num_tapes = program.num_tapes();
+    let mut kept: Vec<&Rule> = Vec::new();
+    let mut dead = Vec::new();
+    for rule in program.iter() {
+        let is_dead = rule.len() == num_tapes
+            && kept
+                .iter()
+                .any(|k| k.len() == num_tapes && dominates(program, k, rule));
+        if !is_dead {
+            kept.push(rule);
+        }
+        dead.push(is_dead);
+    }
+    // keep a removed rule for every accept state that would otherwise disappear
+    let mut states: BTreeSet<State> = kept
+        .iter()
+        .flat_map(|r| vec![r.cur_state(), r.next_state()])
+        .collect();
+    for (idx, rule) in program.iter().enumerate() {
+        if dead[idx] && program.is_accepting(rule.next_state()) && states.insert(rule.next_state())
+        {
+            dead[idx] = false;
+        }
+    }
+    let kept: Vec<Rule> = program
+        .iter()
+        .zip(dead)
+        .filter(|(_, is_dead)| !is_dead)
+        .map(|(rule, _)| rule.clone())
+        .collect();
+    let bounds = (0..num_tapes).map(|t| program.bound(t)).collect();
+    let optimized = Program::new(num_tapes, kept)
+        .with_bounds(bounds)
+        .expect("bounds of the original program are valid");
+    let optimized = if program.tape_names().is_empty() {
+        optimized
+    } else {
+        optimized
+            .with_tape_names(program.tape_names().to_vec())
+            .expect("tape names of the original program are valid")
+    };
+    // the first rule of every state is kept, so the start state still has rules
+    let optimized = match program.start_state() {
+        Some(start) => optimized
+            .with_start_state(start)
+            .expect("start state of the original program has rules"),
+        None => optimized,
+    };
+    optimized
+        .with_accept_states(program.accept_states().iter().copied())
+        .expect("accept states appear in the optimized program")
+}
+
+/// Check that the transpiled program computes the same tape positions as the original.
+///
+/// The original program is run on `orig_init` with the given fuel. The marvellous initial machine
+/// has the same positions on the first `n` tapes and the emulated-state tape for the initial
+/// state set, and is run with twice the fuel since each original step may take two steps in the
+/// transpiled program. The result is `Equivalence::Equivalent` if both halt and agree on the
+/// first `n` tapes, and `Equivalence::Differs` with the first differing tape if both halt but
+/// disagree.
+///
+/// If exactly one of the machines runs out of fuel, `Equivalence::FuelDivergence` is returned.
+/// This is either a bug in the transpiler or a sign that the fuel was just enough for one of the
+/// machines, so it is kept apart from a difference in the tapes. If both run out of fuel the
+/// check is inconclusive and the original program's `ErrorCode::OutOfFuel` is returned. Any other
+/// interpreter error is passed through.
+pub fn check_equivalent(
+    orig: &Program,
+    orig_init: &Machine,
+    fuel: u64,
+) -> Result<Equivalence, ErrorCode> {
+    let (marv_program, state_map) = transpile_with_state_map(orig);
+    let orig_tapes = orig.num_tapes();
+    let marv_init = lift_machine(orig_init, &state_map, orig_tapes, state_map.len());
+
+    let orig_result = interpret(orig_init.clone(), orig, fuel);
+    let marv_result = interpret(marv_init, &marv_program, fuel.saturating_mul(2));
+    match (orig_result, marv_result) {
+        (Ok((_, orig_end, _)), Ok((_, marv_end, _))) => Ok(compare_ends(&orig_end, &marv_end)),
+        (Err(e @ ErrorCode::OutOfFuel { .. }), Err(ErrorCode::OutOfFuel { .. })) => Err(e),
+        (Ok(_), Err(ErrorCode::OutOfFuel { .. })) => {
+            Ok(Equivalence::FuelDivergence { orig_halted: true })
+        }
+        (Err(ErrorCode::OutOfFuel { .. }), Ok(_)) => {
+            Ok(Equivalence::FuelDivergence { orig_halted: false })
+        }
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    }
+}
+
+/// Compare the final machines of an equivalence check on the tapes they have in common.
+fn compare_ends(orig_end: &Machine, marv_end: &Machine) -> Equivalence {
+    // the marvellous machine is always in its single state, only the tapes are compared
+    let diff = diff_machines(orig_end, marv_end)
+        .into_iter()
+        .find(|d| matches!(d, TapeDiff::Tape { .. }));
+    match diff {
+        Some(diff) => Equivalence::Differs(diff),
+        None => Equivalence::Equivalent,
+    }
+}
+
+/// Classify the emulated-state part `emu` of a rule, returning the emulated state it is guarded
+/// on if the rule follows the transpiler's encoding.
+///
+/// The accepted rules are: a translated self-loop (guard `2*s`, action `2*s+1`), an aux rule
+/// resetting the emulated state (action `2*s`, guard `2*s+1`, original tapes untouched), a
+/// transition (guard `2*s`, action `2*t` with `t != s`), and a halting rule (guard `2*s` only).
+fn classify_emulated(orig: &[i32], emu: &[i32]) -> Option<usize> {
+    let nonzero: Vec<(usize, i32)> = emu
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| **a != 0)
+        .map(|(i, a)| (i, *a))
+        .collect();
+    match nonzero.as_slice() {
+        [(g, GUARD_ADJ)] if g % 2 == 0 => Some(g / 2),
+        [(i, a), (j, b)] => {
+            let (guard, action) = if *a == GUARD_ADJ { (*i, *j) } else { (*j, *i) };
+            let is_pair = *a + *b == 0 && (*a == GUARD_ADJ || *b == GUARD_ADJ);
+            if !is_pair {
+                None
+            } else if guard % 2 == 0 && action == guard + 1 {
+                // translated self-loop
+                Some(guard / 2)
+            } else if action % 2 == 0 && guard == action + 1 && orig.iter().all(|a| *a == 0) {
+                // aux rule, it guards on the "reset" tape of the state
+                Some(action / 2)
+            } else if guard % 2 == 0 && action % 2 == 0 {
+                // transition between emulated states
+                Some(guard / 2)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Check whether `program` follows the encoding produced by [`transpile`] for an original program
+/// with `orig_states` states.
+fn check_encoding(program: &Program, orig_states: usize) -> Result<(), String> {
+    let orig_tapes = program.num_tapes() - 2 * orig_states;
+    let mut used = vec![false; orig_states];
+    for (idx, rule) in program.iter().enumerate() {
+        let adjustments: Vec<i32> = rule.iter().copied().collect();
+        let (orig, emu) = adjustments.split_at(orig_tapes);
+        match classify_emulated(orig, emu) {
+            Some(s) => used[s] = true,
+            None => return Err(format!("rule {} does not encode an emulated state", idx)),
+        }
+    }
+    match used.iter().position(|u| !u) {
+        Some(s) => Err(format!("emulated state {} is never used", s)),
+        None => Ok(()),
+    }
+}
+
+/// Recover the shape of the original program from a marvellous program.
+///
+/// The program must use only the marvellous state and every rule must follow the emulated-state
+/// encoding of [`transpile`], where each rule is guarded on exactly one emulated state. Rules
+/// that only consume the emulated state, and so halt the machine, are also accepted. If several
+/// numbers of original states fit the encoding the smallest one is returned.
+pub fn analyze(program: &Program) -> Result<TranspileInfo, String> {
+    if let Some(idx) = program
+        .iter()
+        .position(|r| r.cur_state() != MARV_STATE || r.next_state() != MARV_STATE)
+    {
+        return Err(format!(
+            "rule {} uses a state other than {}",
+            idx, MARV_STATE
+        ));
+    }
+    let mut last_err = String::from("program has too few tapes to encode any states");
+    for orig_states in 1..=program.num_tapes() / 2 {
+        match check_encoding(program, orig_states) {
+            Ok(()) => {
+                return Ok(TranspileInfo {
+                    orig_tapes: program.num_tapes() - 2 * orig_states,
+                    orig_states,
+                })
+            }
+            Err(e) => last_err = format!("with {} emulated states, {}", orig_states, e),
+        }
+    }
+    Err(last_err)
+}
+
+/// Check whether the rules in `rules` can only fire finitely often, in any run of any machine.
+///
+/// A rule that decrements a tape which no rule in the set increments can only fire as many times
+/// as the tape's initial position allows, so it is removed from the set. Since the removed rules
+/// fire finitely often, the tapes they increment are in turn only incremented finitely often and
+/// the argument is repeated on the remaining rules. This amounts to finding a lexicographic
+/// monovariant over the tapes.
+fn rules_terminate(program: &Program, mut rules: Vec<&Rule>) -> bool {
+    loop {
+        if rules.is_empty() {
+            return true;
+        }
+        let decreasing = (0..program.num_tapes()).find(|t| {
+            rules
+                .iter()
+                .any(|r| r.iter().nth(*t).is_some_and(|a| *a < 0))
+                && rules
+                    .iter()
+                    .all(|r| r.iter().nth(*t).map_or(true, |a| *a <= 0))
+        });
+        match decreasing {
+            Some(t) => rules.retain(|r| r.iter().nth(t).map_or(true, |a| *a >= 0)),
+            None => return false,
+        }
+    }
 }
 
-#[cfg(test)]
+/// Try to decide whether a marvellous machine halts, without running it.
+///
+/// This is a bounded static analysis that only checks a few sufficient conditions:
+///
+/// - if no rule can fire, or every rule of the program can only fire finitely often (see
+///   `rules_terminate`), the machine halts and `Some(true)` is returned,
+/// - if the rule that fires first only moves tapes forward, never past a tape bound, and does not
+///   move any tape that blocks the rules before it, then it fires forever and `Some(false)` is
+///   returned.
+///
+/// Otherwise, or if `program` uses a state other than the marvellous state, the result is `None`.
+pub fn will_halt_heuristic(program: &Program, machine: &Machine) -> Option<bool> {
+    if program
+        .iter()
+        .any(|r| r.cur_state() != MARV_STATE || r.next_state() != MARV_STATE)
+    {
+        return None;
+    }
+    let first = match machine.clone().step(program) {
+        StepResult::Halted => return Some(true),
+        StepResult::BadClause(_) | StepResult::Overflow(..) => return None,
+        StepResult::Fired(idx) => idx,
+    };
+    if rules_terminate(program, program.iter().collect()) {
+        return Some(true);
+    }
+
+    let rule = program
+        .iter()
+        .nth(first)
+        .expect("fired rule is in the program");
+    let tapes = machine.tapes();
+    let forward = rule
+        .iter()
+        .enumerate()
+        .all(|(t, a)| *a == 0 || (*a > 0 && program.bound(t).is_none()));
+    // every earlier rule stays blocked by a tape that is too low and that `rule` never moves
+    let blocked = program.iter().take(first).all(|r| {
+        r.iter()
+            .zip(rule.iter())
+            .enumerate()
+            .any(|(t, (a, b))| *a < 0 && tapes[t] + a < 0 && *b == 0)
+    });
+    if forward && blocked {
+        return Some(false);
+    }
+    None
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use super::{transpile, MARV_STATE};
-    use crate::magnificent::{interpret, Machine, Program, Rule};
+    use super::{
+        analyze, check_equivalent, compare_ends, compute_state_map, lift_machine, optimize,
+        translate_rule, transpile, transpile_with_state_map, will_halt_heuristic, Equivalence,
+        TranspileInfo, MARV_STATE,
+    };
+    use crate::arith;
+    use crate::m3_parser;
+    use crate::magnificent::{interpret, Machine, Program, Rule, State, TapeDiff, TapeId};
 
     // Test that a transpiled program has the expected number of tapes and rules
     #[test]
@@ -181,6 +555,168 @@ mod test {
         }
     }
 
+    // Test the halting heuristic on transpiled and hand-written marvellous programs
+    #[test]
+    fn halting_heuristic() {
+        let marv_adder = transpile(&arith::adder_program());
+        let machine = Machine::new(MARV_STATE, vec![4, 5, 1, 0]);
+        assert_eq!(will_halt_heuristic(&marv_adder, &machine), Some(true));
+
+        // tape 0 grows forever while the first rule stays blocked on tape 1
+        let program = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![0, -1]), Rule::new(0, 0, vec![1, 0])],
+        );
+        let machine = Machine::new(MARV_STATE, vec![0, 0]);
+        assert_eq!(will_halt_heuristic(&program, &machine), Some(false));
+
+        // tapes 0 and 1 trade back and forth forever, which is not detected
+        let program = Program::new(
+            2,
+            vec![Rule::new(0, 0, vec![1, -1]), Rule::new(0, 0, vec![-1, 1])],
+        );
+        let machine = Machine::new(MARV_STATE, vec![0, 1]);
+        assert_eq!(will_halt_heuristic(&program, &machine), None);
+
+        // multi-state programs are not marvellous
+        let machine = Machine::new(0, vec![1, 1, 0, 0]);
+        assert_eq!(will_halt_heuristic(&arith::mult_program(), &machine), None);
+    }
+
+    // Test lifting original machines and running them end to end
+    #[test]
+    fn lift_and_run() {
+        let program = Program::new(
+            3,
+            vec![
+                Rule::new(3, 3, vec![1, -1, 0]),
+                Rule::new(3, 7, vec![0, 0, 1]),
+                Rule::new(7, 7, vec![-1, 0, 1]),
+                Rule::new(7, 3, vec![0, -1, 0]),
+            ],
+        );
+        let (marv_program, state_map) = transpile_with_state_map(&program);
+        for (state, tapes) in [(3, vec![2, 5, 0]), (7, vec![4, 1, 1]), (5, vec![1, 1, 1])] {
+            let orig = Machine::new(state, tapes);
+            let lifted = lift_machine(&orig, &state_map, 3, state_map.len());
+            assert_eq!(lifted.num_tapes(), marv_program.num_tapes());
+            assert_eq!(&lifted.tapes()[..3], orig.tapes());
+
+            let orig_end = interpret(orig, &program, 1000);
+            let marv_end = interpret(lifted, &marv_program, 2000);
+            match (orig_end, marv_end) {
+                (Ok((_, o, _)), Ok((_, m, _))) => assert_eq!(o.tapes(), &m.tapes()[..3]),
+                (o, m) => panic!("runs disagree: {:?} and {:?}", o, m),
+            }
+        }
+
+        let lifted = lift_machine(&Machine::new(7, vec![4, 1, 1]), &state_map, 3, 2);
+        assert_eq!(lifted.tapes(), &[4, 1, 1, 0, 0, 1, 0]);
+    }
+
+    // Test building the initial marvellous machine from the public state map
+    #[test]
+    fn state_map_initial_machine() {
+        let program = arith::mult_program();
+        let state_map = compute_state_map(&program);
+        assert_eq!(state_map.len(), 2);
+        assert_eq!(state_map[&State(0)], 0);
+        assert_eq!(state_map[&State(1)], 1);
+
+        let marv_program = transpile(&program);
+        let (x, y) = (3, 4);
+        let mut tapes = vec![0; marv_program.num_tapes()];
+        tapes[..4].copy_from_slice(&[0, x, 0, y - 1]);
+        tapes[4 + 2 * state_map[&State(0)].0] = 1;
+        let machine = Machine::new(MARV_STATE, tapes);
+        let (_, end, _) = interpret(machine, &marv_program, 1000).unwrap();
+        assert_eq!(end.tape_pos(0), x * y);
+
+        let translated: Vec<Rule> = program
+            .iter()
+            .flat_map(|r| translate_rule(r, &state_map, marv_program.num_tapes()))
+            .collect();
+        assert!(translated.iter().eq(marv_program.iter()));
+        assert_eq!(
+            translate_rule(program.iter().next().unwrap(), &state_map, 8).len(),
+            2
+        );
+    }
+
+    // Test that optimizing a transpiled program removes rules without changing its runs
+    #[test]
+    fn optimize_transpiled() {
+        // two self-loops in state 0 share an aux rule, the last rule of state 1 is unreachable
+        let program = Program::new(
+            3,
+            vec![
+                Rule::new(0, 0, vec![-1, 0, 1]),
+                Rule::new(0, 0, vec![0, -1, 1]),
+                Rule::new(0, 1, vec![0, 0, 0]),
+                Rule::new(1, 1, vec![0, 0, -2]),
+                Rule::new(1, 2, vec![0, 0, 0]),
+                Rule::new(1, 1, vec![0, 0, -1]),
+            ],
+        );
+        let (marv_program, state_map) = transpile_with_state_map(&program);
+        let optimized = optimize(&marv_program);
+        assert_eq!(marv_program.num_rules(), 10);
+        assert_eq!(optimized.num_rules(), 7);
+        assert!(optimize(&optimized) == optimized);
+
+        for tapes in [vec![0, 0, 0], vec![3, 4, 0], vec![2, 0, 5], vec![0, 7, 1]] {
+            let lifted = lift_machine(&Machine::new(0, tapes), &state_map, 3, 3);
+            let expected = interpret(lifted.clone(), &marv_program, 1000);
+            let actual = interpret(lifted, &optimized, 1000);
+            match (expected, actual) {
+                (Ok((_, e, _)), Ok((_, a, _))) => assert_eq!(e, a),
+                (e, a) => panic!("runs disagree: {:?} and {:?}", e, a),
+            }
+        }
+
+        // a probe does not shadow the rule it guards, a probe after an unconditional rule is
+        // never reached
+        let program = Program::new(
+            2,
+            vec![
+                Rule::probe(0, vec![0, -1], "pass"),
+                Rule::new(0, 0, vec![1, -1]),
+                Rule::new(0, 1, vec![0, 0]),
+                Rule::probe(0, vec![0, 0], "unreachable"),
+            ],
+        );
+        let optimized = optimize(&program);
+        assert_eq!(optimized.num_rules(), 3);
+        let machine = Machine::new(0, vec![0, 3]);
+        let (_, end, _) = interpret(machine, &optimized, 100).unwrap();
+        assert_eq!(end.tapes(), &[3, 0]);
+
+        // a removed rule that leads to an accept state is kept so the state stays accepting
+        let program = Program::new(
+            1,
+            vec![
+                Rule::new(0, 0, vec![0]),
+                Rule::new(0, 1, vec![-1]),
+                Rule::new(0, 1, vec![-2]),
+            ],
+        )
+        .with_accept_states(vec![1])
+        .unwrap();
+        let optimized = optimize(&program);
+        let rules: Vec<Rule> = optimized.iter().cloned().collect();
+        assert_eq!(
+            rules,
+            vec![Rule::new(0, 0, vec![0]), Rule::new(0, 1, vec![-1])]
+        );
+        assert!(optimized.is_accepting(1));
+
+        // nothing to remove from the transpiled arithmetic programs
+        for program in [arith::mult_program(), arith::power_program()] {
+            let marv_program = transpile(&program);
+            assert!(optimize(&marv_program) == marv_program);
+        }
+    }
+
     // Test that the transpiled adder program works
     #[test]
     fn transpile_equivalent_adder() {
@@ -194,7 +730,7 @@ mod test {
 
         let end_machine = interpret(marv_machine, &marv_program, 100);
         assert!(end_machine.is_ok());
-        let (_, end_machine) = end_machine.unwrap();
+        let (_, end_machine, _) = end_machine.unwrap();
         assert_eq!(end_machine.tape_pos(0), x + y);
     }
 
@@ -215,4 +751,111 @@ mod test {
         assert!(end_machine.is_ok());
         assert_eq!(end_machine.unwrap().1.tape_pos(0), x * y);
     }
+
+    // Test the equivalence checker on the multiplier over a range of inputs
+    #[test]
+    fn check_equivalent_mult() {
+        let program = arith::mult_program();
+        for x in 0..6 {
+            for y in 1..6 {
+                let machine = Machine::new(0, vec![0, x, 0, y - 1]);
+                assert_eq!(
+                    check_equivalent(&program, &machine, 1000).unwrap(),
+                    Equivalence::Equivalent
+                );
+            }
+        }
+    }
+
+    // Test that the transpiled exponentiation program agrees with the original
+    #[test]
+    fn check_equivalent_power() {
+        let program = arith::power_program();
+        for x in 0..4 {
+            for y in 0..4 {
+                let machine = Machine::new(0, vec![1, x, y, 0, 0]);
+                assert_eq!(
+                    check_equivalent(&program, &machine, 1000).unwrap(),
+                    Equivalence::Equivalent
+                );
+            }
+        }
+    }
+
+    // Test that a non-halting program is reported as inconclusive
+    #[test]
+    fn check_equivalent_out_of_fuel() {
+        let program = Program::new(1, vec![Rule::new(0, 0, vec![1])]);
+        let machine = Machine::new(0, vec![0]);
+        assert!(check_equivalent(&program, &machine, 100).is_err());
+    }
+
+    // Test that the first differing original tape is reported, ignoring the state and the
+    // emulated-state tapes
+    #[test]
+    fn check_equivalent_differs() {
+        let orig_end = Machine::new(2, vec![3, 4]);
+        let marv_end = Machine::new(0, vec![3, 5, 1, 0]);
+        assert_eq!(
+            compare_ends(&orig_end, &marv_end),
+            Equivalence::Differs(TapeDiff::Tape {
+                tape: TapeId(1),
+                a: 4,
+                b: 5
+            })
+        );
+        let marv_end = Machine::new(0, vec![3, 4, 0, 1]);
+        assert_eq!(compare_ends(&orig_end, &marv_end), Equivalence::Equivalent);
+    }
+
+    // Test that running out of fuel on one side only is reported as fuel divergence
+    #[test]
+    fn check_equivalent_fuel_divergence() {
+        // the transition takes one step in both programs, but the original program counts as out
+        // of fuel once it has taken as many steps as it has fuel
+        let program = Program::new(1, vec![Rule::new(0, 1, vec![-1])]);
+        let machine = Machine::new(0, vec![1]);
+        assert_eq!(
+            check_equivalent(&program, &machine, 1).unwrap(),
+            Equivalence::FuelDivergence { orig_halted: false }
+        );
+        assert_eq!(
+            check_equivalent(&program, &machine, 2).unwrap(),
+            Equivalence::Equivalent
+        );
+    }
+
+    // Test that the shape of the original program is recovered from a transpiled one
+    #[test]
+    fn analyze_transpiled() {
+        let program = arith::mult_program();
+        let expected = TranspileInfo {
+            orig_tapes: 4,
+            orig_states: 2,
+        };
+        assert_eq!(analyze(&transpile(&program)), Ok(expected));
+
+        let info = analyze(&transpile(&arith::adder_program())).unwrap();
+        assert_eq!((info.orig_tapes, info.orig_states), (2, 1));
+    }
+
+    // Test the hand-written marvellous multiplier
+    #[test]
+    fn analyze_marvellous_mult() {
+        let program = m3_parser::read_program("examples/marvellous-mult.m3");
+        let info = analyze(&program).unwrap();
+        assert_eq!((info.orig_tapes, info.orig_states), (4, 2));
+    }
+
+    // Test that programs not following the encoding are rejected
+    #[test]
+    fn analyze_rejects() {
+        // multiple machine states
+        let program = Program::new(2, vec![Rule::new(0, 1, vec![1, -1])]);
+        assert!(analyze(&program).is_err());
+
+        // a rule guarded on both tapes of an emulated state
+        let program = Program::new(4, vec![Rule::new(0, 0, vec![1, 0, -1, -1])]);
+        assert!(analyze(&program).is_err());
+    }
 }
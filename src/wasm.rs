@@ -0,0 +1,122 @@
+//! # WebAssembly Bindings
+//!
+//! This module exports a small JavaScript API for parsing and running m3 programs, e.g. in a
+//! browser playground. Programs are passed as m3 source strings and tapes as number arrays, and
+//! results come back as JSON strings so that callers never handle Rust values. Errors are thrown
+//! as strings with the same messages as the command line tool.
+//!
+//! This module is only available with the `wasm` feature. The library is not built as a
+//! `cdylib` by default, so build the module with
+//!
+//! ```text
+//! cargo rustc --lib --release --crate-type cdylib --target wasm32-unknown-unknown --features wasm
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/minsky.wasm
+//! ```
+
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+
+use crate::m3_parser;
+use crate::magnificent::{interpret, HaltReason, Machine};
+
+/// Parse an m3 program and return it as JSON.
+///
+/// The JSON has the shape of the serialized `Program`, with `num_tapes` and `rules` fields and
+/// the optional headers of the program.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<String, JsValue> {
+    parse_json(source).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Run an m3 program from the machine with the given state and tapes.
+///
+/// If `state` is omitted the run starts in the start state of the program, or state 0. The
+/// result is a JSON object with the number of `steps` taken, the final `state` and `tapes`, and
+/// whether the machine halted in an accept state (`accepted`).
+#[wasm_bindgen]
+pub fn run(
+    source: &str,
+    state: Option<u32>,
+    tapes: Vec<i32>,
+    fuel: u32,
+) -> Result<String, JsValue> {
+    run_json(source, state, tapes, fuel).map_err(|e| JsValue::from_str(&e))
+}
+
+fn parse_json(source: &str) -> Result<String, String> {
+    let program = m3_parser::parse_m3(source)?;
+    serde_json::to_string(&program).map_err(|e| e.to_string())
+}
+
+fn run_json(
+    source: &str,
+    state: Option<u32>,
+    tapes: Vec<i32>,
+    fuel: u32,
+) -> Result<String, String> {
+    let program = m3_parser::parse_m3(source)?;
+    if tapes.len() != program.num_tapes() {
+        return Err(format!(
+            "program has {} tapes, but {} initial positions were given",
+            program.num_tapes(),
+            tapes.len()
+        ));
+    }
+    let state = match state {
+        Some(s) => s as usize,
+        None => program.start_state().map_or(0, |s| s.0),
+    };
+    let machine = Machine::try_new(state, tapes)?;
+    match interpret(machine, &program, fuel as u64) {
+        Ok((steps, machine, reason)) => Ok(json!({
+            "steps": steps,
+            "state": machine.machine_state().0,
+            "tapes": machine.tapes(),
+            "accepted": reason == HaltReason::Accepted,
+        })
+        .to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_json, run_json};
+    #[cfg(target_arch = "wasm32")]
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const PROGRAM: &str = "tapes: 3\n0 [-1, 0, 0] 0\n";
+
+    // Run with `wasm-pack test --node -- --features wasm`, or natively as ordinary tests. The
+    // exported wrappers only convert errors to `JsValue`, which needs a JavaScript host.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_parse() {
+        let json: serde_json::Value = serde_json::from_str(&parse_json(PROGRAM).unwrap()).unwrap();
+        assert_eq!(json["num_tapes"], 3);
+        assert_eq!(json["rules"].as_array().unwrap().len(), 1);
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_run() {
+        let adder = "0 [1, -1] 0";
+        let json: serde_json::Value =
+            serde_json::from_str(&run_json(adder, None, vec![2, 3], 100).unwrap()).unwrap();
+        assert_eq!(json["steps"], 3);
+        assert_eq!(json["state"], 0);
+        assert_eq!(json["tapes"], serde_json::json!([5, 0]));
+        assert_eq!(json["accepted"], false);
+
+        assert!(run_json(adder, None, vec![1], 100)
+            .unwrap_err()
+            .contains("2 tapes"));
+        assert_eq!(
+            run_json(adder, None, vec![2, -1], 100).unwrap_err(),
+            "tape 1 has negative initial position -1"
+        );
+        assert!(run_json("0 [1] 0", None, vec![0], 10)
+            .unwrap_err()
+            .starts_with("out of fuel"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn minsky(args: &[&str]) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_minsky"))
+        .args(args)
+        .output()
+        .expect("failed to run minsky");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// Test running the adder from the command line
+#[test]
+pub fn cli_adder() {
+    let (ok, stdout, _) = minsky(&["examples/adder.m3", "3", "4"]);
+    assert!(ok);
+    assert_eq!(stdout, "halted after 4 steps\nstate: 0\ntapes: [7, 0]\n");
+
+    let (ok, stdout, _) = minsky(&["--trace", "examples/adder.m3", "0", "1"]);
+    assert!(ok);
+    assert!(
+        stdout.starts_with("0: 0 [0, 1]\n1: 0 [1, 0]\n"),
+        "{}",
+        stdout
+    );
+
+    let (ok, stdout, _) = minsky(&["--trace", "--sample", "3", "examples/adder.m3", "0", "7"]);
+    assert!(ok);
+    assert!(
+        stdout.starts_with("0: 0 [0, 7]\n3: 0 [3, 4]\n6: 0 [6, 1]\n7: 0 [7, 0]\nhalted"),
+        "{}",
+        stdout
+    );
+
+    // sampling implies tracing
+    let (ok, stdout, _) = minsky(&["--sample", "3", "examples/adder.m3", "0", "7"]);
+    assert!(ok);
+    assert!(
+        stdout.starts_with("0: 0 [0, 7]\n3: 0 [3, 4]\n"),
+        "{}",
+        stdout
+    );
+
+    let (ok, stdout, _) = minsky(&["--dot", "examples/adder.m3"]);
+    assert!(ok);
+    assert!(stdout.starts_with("digraph program {"), "{}", stdout);
+}
+
+// Test that errors are reported without panicking
+#[test]
+pub fn cli_errors() {
+    let (ok, _, stderr) = minsky(&["examples/does-not-exist.m3"]);
+    assert!(!ok);
+    assert!(stderr.contains("does-not-exist.m3"), "{}", stderr);
+
+    let (ok, _, stderr) = minsky(&["--fuel", "2", "examples/adder.m3", "0", "5"]);
+    assert!(!ok);
+    assert!(stderr.contains("out of fuel after 2 steps"), "{}", stderr);
+
+    let (ok, _, stderr) = minsky(&["examples/adder.m3", "1", "2", "3"]);
+    assert!(!ok);
+    assert!(stderr.contains("3 tape positions given"), "{}", stderr);
+
+    let (ok, _, stderr) = minsky(&[]);
+    assert!(!ok);
+    assert!(stderr.contains("usage: minsky"), "{}", stderr);
+}
+
+// Run minsky with the given program on stdin
+fn minsky_stdin(args: &[&str], program: &str) -> (bool, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_minsky"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run minsky");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(program.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// Test reading the program from stdin
+#[test]
+pub fn cli_stdin() {
+    let (ok, stdout, _) = minsky_stdin(&["-", "3", "4"], "tapes: 2\n0 [1, -1] 0\n");
+    assert!(ok);
+    assert_eq!(stdout, "halted after 4 steps\nstate: 0\ntapes: [7, 0]\n");
+}
+
+// Test that the start: header sets the default initial state
+#[test]
+pub fn cli_start_state() {
+    let program = "tapes: 1\nstart: 1\n0 [1] 0\n1 [-1] 1\n";
+    let (ok, stdout, _) = minsky_stdin(&["-", "3"], program);
+    assert!(ok);
+    assert_eq!(stdout, "halted after 3 steps\nstate: 1\ntapes: [0]\n");
+
+    // an explicit --state overrides the header
+    let (ok, stdout, stderr) = minsky_stdin(&["--state", "0", "--fuel", "5", "-", "3"], program);
+    assert!(!ok);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("out of fuel after 5 steps"), "{}", stderr);
+}
+
+// Test that the result reports acceptance for programs with accept states
+#[test]
+pub fn cli_accept_states() {
+    // accepts even numbers: states 0 and 1 track the parity of what was consumed
+    let program = "tapes: 1\naccept: [0]\n0 [-1] 1\n1 [-1] 0\n";
+    let (ok, stdout, _) = minsky_stdin(&["-", "4"], program);
+    assert!(ok);
+    assert!(
+        stdout.ends_with("state: 0\ntapes: [0]\naccepted: true\n"),
+        "{}",
+        stdout
+    );
+    let (ok, stdout, _) = minsky_stdin(&["-", "3"], program);
+    assert!(ok);
+    assert!(stdout.ends_with("accepted: false\n"), "{}", stdout);
+}
+
+// Run the REPL on the given program file, feeding it the given commands on stdin
+fn minsky_repl(args: &[&str], commands: &str) -> (bool, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_minsky-repl"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run minsky-repl");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )
+}
+
+// Test stepping the adder in the REPL
+#[test]
+pub fn repl_adder() {
+    let commands = "rules\nstep\nrun 10\nstep\nrules\nreset\nrun 2\nfly\nquit\ntapes\n";
+    let (ok, stdout) = minsky_repl(&["examples/adder.m3", "1", "3"], commands);
+    assert!(ok);
+    assert_eq!(
+        stdout,
+        "0: 0 [1, 3]\n\
+         rule 0: 0 [1, -1] 0\n\
+         rule 0: 0 [1, -1] 0\n\
+         1: 0 [2, 2]\n\
+         halted after 3 steps\n\
+         3: 0 [4, 0]\n\
+         the machine has halted, use `reset` to start over\n\
+         no rule applies\n\
+         0: 0 [1, 3]\n\
+         2: 0 [3, 1]\n\
+         error: unknown command `fly`, try `help`\n"
+    );
+
+    let (ok, _) = minsky_repl(&["examples/adder.m3", "1", "2", "3"], "");
+    assert!(!ok);
+}
@@ -0,0 +1,107 @@
+extern crate minsky;
+
+use std::process::Command;
+
+use minsky::arith;
+use minsky::magnificent::{codegen_rust, interpret_with_trace, ErrorCode, Machine, Program, Rule};
+
+/// A generated function, with the runs to compare as initial state, tapes, and fuel
+struct Case {
+    name: &'static str,
+    program: Program,
+    runs: Vec<(usize, Vec<i32>, u64)>,
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            name: "mult",
+            program: arith::mult_program(),
+            runs: vec![(0, vec![0, 3, 0, 3], 1000), (0, vec![0, 5, 0, 2], 10)],
+        },
+        Case {
+            // the increment of tape 1 overflows after tape 0 has been checked
+            name: "transfer",
+            program: Program::new(2, vec![Rule::new(0, 0, vec![-1, 2])]),
+            runs: vec![(0, vec![3, 0], 100), (0, vec![5, i32::MAX - 3], 100)],
+        },
+        Case {
+            name: "bounded",
+            program: Program::new(
+                2,
+                vec![Rule::new(0, 0, vec![2, -1]), Rule::new(0, 1, vec![-1, 0])],
+            )
+            .with_bounds(vec![Some(7), None])
+            .unwrap(),
+            runs: vec![(0, vec![0, 10], 100), (1, vec![4, 4], 100)],
+        },
+        Case {
+            name: "extreme",
+            program: Program::new(2, vec![Rule::new(0, 0, vec![i32::MIN, i32::MAX])]),
+            runs: vec![(0, vec![i32::MAX, 0], 100)],
+        },
+    ]
+}
+
+/// Run a case with the interpreter, formatting the outcome like the generated driver does.
+fn interpret_outcome(program: &Program, state: usize, tapes: &[i32], fuel: u64) -> String {
+    let mut last = Machine::new(state, tapes.to_vec());
+    let result = interpret_with_trace(last.clone(), program, fuel, |m, _| last = m.clone());
+    match result {
+        Ok((steps, m, _)) => format!("ok {} {} {:?}", steps, m.machine_state(), m.tapes()),
+        Err(ErrorCode::OutOfFuel { machine, .. }) => {
+            format!("err out of fuel {:?}", machine.tapes())
+        }
+        Err(ErrorCode::Overflow { tape }) => {
+            format!("err tape {} overflowed {:?}", tape, last.tapes())
+        }
+        Err(e) => panic!("unexpected error {:?}", e),
+    }
+}
+
+// Test that the generated code compiles and runs exactly like the interpreter, including the
+// tapes it leaves behind when a tape overflows or the fuel runs out
+#[test]
+pub fn codegen_matches_interpret() {
+    let mut source = String::new();
+    let mut driver = String::from("fn main() {\n");
+    let mut expected = String::new();
+    for case in cases() {
+        source.push_str(&codegen_rust(&case.program, case.name));
+        for (state, tapes, fuel) in case.runs.iter() {
+            driver.push_str(&format!(
+                "    let mut tapes = vec!{:?};\n    match {}({}, &mut tapes, {}) {{\n        \
+                 Ok((steps, state)) => println!(\"ok {{}} {{}} {{:?}}\", steps, state, tapes),\n        \
+                 Err(e) => println!(\"err {{}} {{:?}}\", e, tapes),\n    }}\n",
+                tapes, case.name, state, fuel
+            ));
+            expected.push_str(&interpret_outcome(&case.program, *state, tapes, *fuel));
+            expected.push('\n');
+        }
+    }
+    driver.push_str("}\n");
+    source.push_str(&driver);
+
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let src = dir.join("codegen_matches_interpret.rs");
+    let exe = dir.join("codegen_matches_interpret");
+    std::fs::write(&src, &source).unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc)
+        .args(["--edition", "2018", "-o"])
+        .arg(&exe)
+        .arg(&src)
+        .output()
+        .expect("failed to run rustc");
+    assert!(
+        output.status.success(),
+        "generated code does not compile:\n{}\n{}",
+        String::from_utf8_lossy(&output.stderr),
+        source
+    );
+    let output = Command::new(&exe)
+        .output()
+        .expect("failed to run generated code");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
@@ -3,58 +3,42 @@ extern crate minsky;
 use minsky::m3_parser;
 use minsky::magnificent;
 
-// Test parsing / interpreting of the adder
-#[test]
-pub fn adder() {
-    let program = m3_parser::read_program("examples/adder.m3");
-    let x = 1;
-    let y = 3;
-    let machine = magnificent::Machine::new(0, vec![x, y]);
-    let end_machine = magnificent::interpret(machine, &program, 100);
-    assert!(end_machine.is_ok());
-    let (_, end_machine) = end_machine.unwrap();
-    assert_eq!(end_machine.tape_pos(0), x + y);
-}
-
-// Test parsing / interpreting of the 4-tape multiplier
-#[test]
-pub fn mult() {
-    let program = m3_parser::read_program("examples/mult.m3");
-    let x = 2;
-    let y = 3;
-    let machine = magnificent::Machine::new(0, vec![0, x, 0, y - 1]);
-    let end_machine = magnificent::interpret(machine, &program, 100);
-    assert!(end_machine.is_ok());
-    let (_, end_machine) = end_machine.unwrap();
-    assert_eq!(end_machine.tape_pos(0), x * y);
+/// Initial tape positions for running a bundled example and the expected result on tape 0
+fn example_run(name: &str) -> Option<(Vec<i32>, i32)> {
+    match name {
+        "adder.m3" => Some((vec![1, 3], 1 + 3)),
+        "mult.m3" => Some((vec![0, 2, 0, 3 - 1], 2 * 3)),
+        "6-rule-mult.m3" => Some((vec![0, 7, 11, 0], 7 * 11)),
+        "marvellous-mult.m3" => Some((vec![0, 7, 0, 13 - 1, 1, 0, 0, 0], 7 * 13)),
+        _ => None,
+    }
 }
 
-// Test parsing / interpreting of the 6-rule, 4-tape multiplier
+// Test that every bundled example parses
 #[test]
-pub fn six_rule_mult() {
-    let program = m3_parser::read_program("examples/6-rule-mult.m3");
-    let x = 7;
-    let y = 11;
-    let machine = magnificent::Machine::new(0, vec![0, x, y, 0]);
-    let end_machine = magnificent::interpret(machine, &program, 1000);
-    assert!(end_machine.is_ok());
-    let (_, end_machine) = end_machine.unwrap();
-    assert_eq!(end_machine.tape_pos(0), x * y);
+pub fn examples_parse() {
+    let examples = m3_parser::load_examples("examples");
+    assert!(!examples.is_empty());
+    for (name, program) in examples {
+        if let Err(e) = program {
+            panic!("{} failed to parse: {}", name, e);
+        }
+    }
 }
 
-// Test parsing / interpreting of the marvellous multiplier
+// Test interpreting every bundled example that has known inputs
 #[test]
-pub fn marvellous_mult() {
-    let program = m3_parser::read_program("examples/marvellous-mult.m3");
-    let x = 7;
-    let y = 13;
-    let machine = magnificent::Machine::new(0, vec![0, x, 0, y - 1, 1, 0, 0, 0]);
-    let end_machine = magnificent::interpret(machine, &program, 1000);
-    match end_machine {
-        Ok((cycles, end_machine)) => {
-            assert_eq!(end_machine.tape_pos(0), x * y);
-            println!("interpreter took {} cycles", cycles); // 7*13 takes 390 cycles
+pub fn examples_run() {
+    for (name, program) in m3_parser::load_examples("examples") {
+        let (tapes, expected) = match example_run(&name) {
+            Some(run) => run,
+            None => continue,
+        };
+        let program = program.unwrap();
+        let machine = magnificent::Machine::new(0, tapes);
+        match magnificent::interpret(machine, &program, 1000) {
+            Ok((_, end_machine, _)) => assert_eq!(end_machine.tape_pos(0), expected, "{}", name),
+            Err(e) => panic!("{}: interpreter error: {:?}", name, e),
         }
-        Err(e) => panic!("Interpreter error: {:?}", e),
     }
 }